@@ -19,12 +19,15 @@ var LButtonHeld = false;
 var RButtonHeld = false;
 var AButtonHeld = false;
 var BButtonHeld = false;
+var ZLButtonHeld = false;
+var ZRButtonHeld = false;
 
 var currentDescHeight = 0; // Used for the current position of the description (modified by the R-Stick Y Value).
 var currentActiveDescription // For reference to the current active description.
 var activeDescHeight = 0; // The height for the current active description so it can't be scrolled out of bounds.
 
 var mods = [];
+var priority = [];
 var currentMods = [];
 var pageCount = 0;
 
@@ -71,6 +74,43 @@ function toggleMod() {
     }));
 }
 
+// Mirrors the priority list handling of ARCropolis, mods that aren't in the list yet are added at the bottom when raised
+// and mods at the bottom of the list are removed from it when lowered.
+function changePriority(raise) {
+    var index = parseInt($(".is-focused").attr("data-mod-index"));
+    var position = priority.indexOf(index);
+
+    if (position == -1) {
+        if (raise) {
+            priority.push(index);
+        }
+    } else if (raise) {
+        if (position > 0) {
+            priority[position] = priority[position - 1];
+            priority[position - 1] = index;
+        }
+    } else if (position == priority.length - 1) {
+        priority.splice(position, 1);
+    } else {
+        priority[position] = priority[position + 1];
+        priority[position + 1] = index;
+    }
+
+    updatePriority(index);
+
+    window.nx.sendMessage(JSON.stringify({
+        "ChangePriority": {
+            "id": index,
+            "raise": raise
+        }
+    }));
+}
+
+function updatePriority(index) {
+    var position = priority.indexOf(index);
+    $("#priority").html(position == -1 ? "Default" : `${position + 1}`);
+}
+
 function updateCurrentDesc() {
     // Reset current description height
     currentDescHeight = 0;
@@ -117,6 +157,26 @@ function checkGamepad(index, gamepad) {
             BButtonHeld = false;
         }
 
+        // ZL Button
+        if (gamepad.buttons[6].pressed) {
+            if (!ZLButtonHeld) {
+                changePriority(true);
+                ZLButtonHeld = true;
+            }
+        } else {
+            ZLButtonHeld = false;
+        }
+
+        // ZR Button
+        if (gamepad.buttons[7].pressed) {
+            if (!ZRButtonHeld) {
+                changePriority(false);
+                ZRButtonHeld = true;
+            }
+        } else {
+            ZRButtonHeld = false;
+        }
+
         // Check if D-pad Left pressed or Left Stick X Axis less than -0.7
         if (gamepad.buttons[14].pressed || axisX < -0.7) {
             console.log("D-pad left pressed");
@@ -239,7 +299,12 @@ function move(source, target) {
         target.classList.add("is-focused");
         target.focus();
         var mod = mods[target.getAttribute("data-mod-index")];
-        $("#description").html(mod["description"]);
+        var description = mod["description"];
+        if (mod["conflicts"] != undefined && mod["conflicts"].length > 0) {
+            description += `<br /><br />Contested files:<br />${mod["conflicts"].join("<br />")}`;
+        }
+        $("#description").html(description);
+        updatePriority(mod["id"]);
         $("#version").html(mod["version"]);
         $("#authors").html(mod["authors"]);
         $("#preview").attr("src", `img/${mod['id']}`);
@@ -429,6 +494,7 @@ window.addEventListener("DOMContentLoaded", (e) => {
             url: "mods.json",
             success: (data) => {
                 mods = data["entries"];
+                priority = data["priority"];
                 $("#workspace").html(data["workspace"]);
                 currentMods = mods.map(x => x["id"]);
                 refreshCurrentMods();
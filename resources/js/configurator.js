@@ -36,7 +36,14 @@ window.addEventListener("DOMContentLoaded", (e) => {
     });
 
     window.nx.addEventListener("message", function(e) {
-        document.getElementById(e.data).classList.toggle("hidden");
+        // Messages in the "id|text" format replace the text of an element, the others toggle its checkmark
+        var separator = e.data.indexOf("|");
+
+        if (separator != -1) {
+            document.getElementById(e.data.substring(0, separator)).innerText = e.data.substring(separator + 1);
+        } else {
+            document.getElementById(e.data).classList.toggle("hidden");
+        }
     });
 
     window.nx.sendMessage("loaded");
@@ -2,6 +2,9 @@ const WORKSPACES_CONTROL = "&#xe000 Set Active &nbsp; &#xe002 Duplicate Workspac
 const WORKSPACE_CONTROL = "&#xe000 Select Option";
 
 var workspaces = [];
+var manifests = [];
+var settings = {};
+var configs = {};
 var selected_workspace = 0;
 var active_workspace = "";
 var AButtonHeld = false;
@@ -24,6 +27,9 @@ window.addEventListener("DOMContentLoaded", (e) => {
             success: (data) => {
                 workspaces = data["workspaces"];
                 active_workspace = data["active_workspace"];
+                manifests = data["manifests"];
+                settings = data["settings"];
+                configs = data["configs"];
                 setupWorkspaces();
             }
         });
@@ -262,6 +268,12 @@ function setupWorkspaces() {
     <div class="item-container">
         <h2>Create Workspace</h2>
     </div>
+</button>
+    <button onclick="importWorkspace()" class="flex-item">
+    <div class="icon-background"></div>
+    <div class="item-container">
+        <h2>Import Workspace</h2>
+    </div>
 </button>
     `;
 
@@ -332,6 +344,10 @@ function renameWorkspace() {
     targetName = res;
 
     workspaces[selected_workspace] = targetName;
+    settings[targetName] = settings[sourceName];
+    configs[targetName] = configs[sourceName];
+    delete settings[sourceName];
+    delete configs[sourceName];
 
     $("#workspace").html(workspaces[selected_workspace]);
 
@@ -362,6 +378,8 @@ function duplicateWorkspace() {
     targetName = res;
 
     workspaces.push(targetName);
+    settings[targetName] = settings[sourceName];
+    configs[targetName] = configs[sourceName];
 
     if (isNx) {
         window.nx.sendMessage(JSON.stringify({
@@ -425,4 +443,95 @@ function editWorkspace() {
         }));
         window.location.href = "http://localhost/quit";
     }
-}
\ No newline at end of file
+}
+
+// An empty answer clears the setting, so that the one of the configuration is used
+function promptSetting(message, value) {
+    var res = prompt(message, value == null ? "" : value);
+    if (res == null || res == undefined) { return undefined; }
+
+    res = res.trim();
+    return res == "" ? null : res;
+}
+
+function editSettings() {
+    var name = workspaces[selected_workspace];
+    var current = settings[name] || {};
+
+    var region = promptSetting("Region of the workspace (jp_ja, us_en, us_fr, us_es, eu_en, eu_fr, eu_es, eu_de, eu_nl, eu_it, eu_ru, kr_ko, zh_cn, zh_tw), empty to use the configuration", current["region"]);
+    if (region === undefined) { return; }
+
+    var extraPaths = promptSetting("Extra mod folders of the workspace, separated by commas, empty for none", (current["extra_paths"] || []).join(", "));
+    if (extraPaths === undefined) { return; }
+
+    var loggingLevel = promptSetting("Logging level of the workspace (Error, Warn, Info, Debug, Trace), empty to use the configuration", current["logging_level"]);
+    if (loggingLevel === undefined) { return; }
+
+    var configText = promptSetting("config.json of the workspace, empty for none", configs[name] == null ? "" : JSON.stringify(configs[name]));
+    if (configText === undefined) { return; }
+
+    var config = null;
+
+    if (configText != null) {
+        try {
+            config = JSON.parse(configText);
+        } catch (e) {
+            alert(`The config.json is not valid JSON: ${e.message}`);
+            return;
+        }
+    }
+
+    var edited = {
+        "region": region,
+        "extra_paths": extraPaths == null ? null : extraPaths.split(",").map(x => x.trim()).filter(x => x != ""),
+        "logging_level": loggingLevel,
+    };
+
+    settings[name] = edited;
+    configs[name] = config;
+
+    if (isNx) {
+        window.nx.sendMessage(JSON.stringify({
+            "EditSettings": {
+                "name": name,
+                "settings": edited,
+                "config": config,
+            }
+        }));
+    }
+}
+
+function exportWorkspace() {
+    if (isNx) {
+        window.nx.sendMessage(JSON.stringify({
+            "Export": {
+                "name": workspaces[selected_workspace]
+            }
+        }));
+        window.location.href = "http://localhost/quit";
+    }
+}
+
+function importWorkspace() {
+    if (manifests.length == 0) {
+        alert("No manifest was found in sd:/ultimate/arcropolis/workspaces");
+        return;
+    }
+
+    var res = prompt(`Enter the name of the manifest to import (${manifests.join(", ")})`, manifests[0]);
+    if (res == null || res == undefined) { return; }
+
+    if (!manifests.includes(res)) {
+        alert("No manifest with that name exists!");
+        return;
+    }
+
+    if (isNx) {
+        window.nx.sendMessage(JSON.stringify({
+            "Import": {
+                "name": res
+            }
+        }));
+        window.location.href = "http://localhost/quit";
+    }
+}
@@ -22,8 +22,15 @@ pub struct ApiVersion {
 /// Do your changes only add new APIs in a backwards compatible way: Minor bump
 ///
 /// Are your changes only internal? No version bump
-static API_VERSION: ApiVersion = ApiVersion { major: 1, minor: 8 };
+static API_VERSION: ApiVersion = ApiVersion { major: 1, minor: 13 };
 
+/// Gets the version of the API. The minor versions of API 1 added the following functions, so that plugins can tell
+/// which ones exist before calling them:
+/// * 1.9: `arcrop_register_extended_event_callback`, `arcrop_unregister_extended_event_callback` and `arcrop_unregister_event_callback`
+/// * 1.10: `arcrop_register_extension_callback` registers the callback it is given, and `arcrop_register_extension_callback_with_size`
+/// * 1.11: `arcrop_load_next_layer`
+/// * 1.12: `arcrop_refresh_scratch`
+/// * 1.13: `arcrop_reload_mods`
 #[no_mangle]
 pub extern "C" fn arcrop_api_version() -> &'static ApiVersion {
     debug!("arcrop_api_version -> Function called");
@@ -9,6 +9,8 @@ use crate::{fs::*, hashes};
 pub enum PendingApiCall {
     GenericCallback { hash: Hash40, max_size: usize, callback: CallbackFn },
     StreamCallback { hash: Hash40, callback: StreamCallbackFn },
+    ExtensionCallback { ext: Hash40, max_size: Option<usize>, callback: CallbackFn },
+    ScratchRefresh,
 }
 
 unsafe impl Send for PendingApiCall {}
@@ -59,7 +61,66 @@ pub extern "C" fn arcrop_register_callback_with_path(hash: Hash40, cb: StreamCal
     }
 }
 
+/// Registers a callback for every file of the game with the provided extension, such as `nutexb`.
+/// The buffer provided to the callback is filled with the file to transform, whose size is provided through the out size,
+/// and the callback has to set the out size to the size of the transformed file. Returning false leaves the file untouched.
+/// When several plugins register a callback for the same extension, they are chained in the order they were registered.
+/// The files keep their vanilla size, see `arcrop_register_extension_callback_with_size` to make them bigger.
+///
+/// Since API 1.10. Before that, ARCropolis 3 exported this function without arguments and it only logged an error.
+/// `arcropolis_api` always declared it with an extension and a callback, like ARCropolis 2 did, so plugins built against it
+/// call it the same way and their callbacks now run. Plugins can check `arcrop_api_version` for 1.10 to know whether they do.
 #[no_mangle]
-pub extern "C" fn arcrop_register_extension_callback() {
-    error!("Extension callbacks are not (yet) supported in ARCropolis 3.0.0. Please contact the developer to have them update their plugin.");
+pub extern "C" fn arcrop_register_extension_callback(ext: Hash40, cb: CallbackFn) {
+    debug!(
+        "arcrop_register_extension_callback -> Extension received: {} ({:#x})",
+        hashes::find(ext).green(),
+        ext.0
+    );
+
+    register_extension_callback(ext, None, cb);
+}
+
+/// Same as `arcrop_register_extension_callback`, but the files with the extension can grow up to `max_size` bytes like
+/// the files of `arcrop_register_callback`. Files which are bigger than that in the game or in a mod keep their size.
+///
+/// Since API 1.10
+#[no_mangle]
+pub extern "C" fn arcrop_register_extension_callback_with_size(ext: Hash40, max_size: usize, cb: CallbackFn) {
+    debug!(
+        "arcrop_register_extension_callback_with_size -> Extension received: {} ({:#x}), max size: {:#x}",
+        hashes::find(ext).green(),
+        ext.0,
+        max_size
+    );
+
+    register_extension_callback(ext, Some(max_size), cb);
+}
+
+fn register_extension_callback(ext: Hash40, max_size: Option<usize>, callback: CallbackFn) {
+    let request = PendingApiCall::ExtensionCallback { ext, max_size, callback };
+
+    let mut pending_calls = PENDING_CALLBACKS.lock();
+
+    if GlobalFilesystem::is_init() {
+        crate::GLOBAL_FILESYSTEM.write().handle_api_request(request);
+    } else {
+        pending_calls.push(request);
+    }
+}
+
+/// Picks up the files written to the scratch area of `mods:/` since the last refresh.
+/// Only files which replace a file of the game take effect right away, new files have to wait for the next boot.
+#[no_mangle]
+pub extern "C" fn arcrop_refresh_scratch() {
+    debug!("arcrop_refresh_scratch");
+
+    let mut pending_calls = PENDING_CALLBACKS.lock();
+
+    if GlobalFilesystem::is_init() {
+        crate::GLOBAL_FILESYSTEM.write().handle_api_request(PendingApiCall::ScratchRefresh);
+    } else {
+        // Discovery has not happened yet, and it reads the scratch directory like any other mod root
+        pending_calls.push(PendingApiCall::ScratchRefresh);
+    }
 }
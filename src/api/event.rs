@@ -1,6 +1,14 @@
+use std::{
+    convert::TryFrom,
+    ffi::{c_void, CString},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
 use arcropolis_api::{Event, EventCallbackFn};
 use once_cell::sync::Lazy;
 use parking_lot::RwLock;
+use skyline::libc::c_char;
+use smash_arc::Hash40;
 
 pub struct EventCallbacks {
     arc_fs_mounted: Vec<EventCallbackFn>,
@@ -70,3 +78,161 @@ pub fn send_event(e: Event) {
 pub fn setup() {
     let _ = std::thread::spawn(event_loop);
 }
+
+/// Events added after the ones of `arcropolis_api::Event`. They are delivered synchronously, in the thread they happen in,
+/// so that plugins observe them in the order they happen.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ExtendedEvent {
+    /// A file is about to be loaded into the buffer of the game. Payload: `FileReplaceEvent`
+    FileReplacing,
+    /// A file was loaded into the buffer of the game. Payload: `FileReplaceEvent`
+    FileReplaced,
+    /// The enabled mods of a workspace were changed in ARCadia. Payload: `PresetsChangedEvent`
+    PresetsChanged,
+    /// The active workspace was changed. Payload: `WorkspaceSwitchedEvent`
+    WorkspaceSwitched,
+}
+
+impl TryFrom<u32> for ExtendedEvent {
+    type Error = u32;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::FileReplacing),
+            1 => Ok(Self::FileReplaced),
+            2 => Ok(Self::PresetsChanged),
+            3 => Ok(Self::WorkspaceSwitched),
+            _ => Err(value),
+        }
+    }
+}
+
+#[repr(C)]
+pub struct FileReplaceEvent {
+    pub hash: Hash40,
+    pub buffer: *mut u8,
+    pub buffer_size: usize,
+    /// The size of the file that was loaded, always 0 for `FileReplacing`
+    pub file_size: usize,
+}
+
+#[repr(C)]
+pub struct PresetsChangedEvent {
+    pub workspace: *const c_char,
+    pub enabled_count: usize,
+}
+
+#[repr(C)]
+pub struct WorkspaceSwitchedEvent {
+    pub previous: *const c_char,
+    pub current: *const c_char,
+}
+
+pub type ExtendedEventCallbackFn = extern "C" fn(ExtendedEvent, *const c_void);
+
+struct ExtendedEventCallback {
+    handle: u64,
+    ty: ExtendedEvent,
+    callback: ExtendedEventCallbackFn,
+}
+
+static EXTENDED_CALLBACKS: Lazy<RwLock<Vec<ExtendedEventCallback>>> = Lazy::new(|| RwLock::new(Vec::new()));
+static NEXT_HANDLE: AtomicU64 = AtomicU64::new(1);
+
+/// Registers a callback for an extended event, returning the handle to unregister it with.
+/// The event is taken as its discriminant, so that an event this version doesn't know of is refused by returning 0.
+#[no_mangle]
+pub extern "C" fn arcrop_register_extended_event_callback(ty: u32, callback: ExtendedEventCallbackFn) -> u64 {
+    let ty = match ExtendedEvent::try_from(ty) {
+        Ok(ty) => ty,
+        Err(ty) => {
+            error!("arcrop_register_extended_event_callback -> Unknown extended event {}", ty);
+            return 0;
+        },
+    };
+
+    let handle = NEXT_HANDLE.fetch_add(1, Ordering::SeqCst);
+    EXTENDED_CALLBACKS.write().push(ExtendedEventCallback { handle, ty, callback });
+    handle
+}
+
+#[no_mangle]
+pub extern "C" fn arcrop_unregister_extended_event_callback(handle: u64) -> bool {
+    let mut cbs = EXTENDED_CALLBACKS.write();
+    let count = cbs.len();
+    cbs.retain(|cb| cb.handle != handle);
+    cbs.len() != count
+}
+
+#[no_mangle]
+pub extern "C" fn arcrop_unregister_event_callback(ty: Event, callback: EventCallbackFn) -> bool {
+    let mut cbs = EVENT_CALLBACKS.write();
+    let count = cbs[ty].len();
+    cbs[ty].retain(|cb| *cb as usize != callback as usize);
+    cbs[ty].len() != count
+}
+
+/// Calls every callback registered for the event before returning
+fn dispatch<T>(ty: ExtendedEvent, payload: &T) {
+    // The callbacks are copied out so that they are free to register or unregister callbacks themselves
+    let callbacks: Vec<ExtendedEventCallbackFn> = EXTENDED_CALLBACKS
+        .read()
+        .iter()
+        .filter(|cb| cb.ty == ty)
+        .map(|cb| cb.callback)
+        .collect();
+
+    for cb in callbacks {
+        cb(ty, payload as *const T as *const c_void);
+    }
+}
+
+pub fn send_file_replacing(hash: Hash40, buffer: &mut [u8]) {
+    dispatch(
+        ExtendedEvent::FileReplacing,
+        &FileReplaceEvent {
+            hash,
+            buffer: buffer.as_mut_ptr(),
+            buffer_size: buffer.len(),
+            file_size: 0,
+        },
+    );
+}
+
+pub fn send_file_replaced(hash: Hash40, buffer: &mut [u8], file_size: usize) {
+    dispatch(
+        ExtendedEvent::FileReplaced,
+        &FileReplaceEvent {
+            hash,
+            buffer: buffer.as_mut_ptr(),
+            buffer_size: buffer.len(),
+            file_size,
+        },
+    );
+}
+
+pub fn send_presets_changed(workspace: &str, enabled_count: usize) {
+    let workspace = CString::new(workspace).unwrap_or_default();
+
+    dispatch(
+        ExtendedEvent::PresetsChanged,
+        &PresetsChangedEvent {
+            workspace: workspace.as_ptr(),
+            enabled_count,
+        },
+    );
+}
+
+pub fn send_workspace_switched(previous: &str, current: &str) {
+    let previous = CString::new(previous).unwrap_or_default();
+    let current = CString::new(current).unwrap_or_default();
+
+    dispatch(
+        ExtendedEvent::WorkspaceSwitched,
+        &WorkspaceSwitchedEvent {
+            previous: previous.as_ptr(),
+            current: current.as_ptr(),
+        },
+    );
+}
@@ -1,10 +1,9 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashSet;
 
 use owo_colors::OwoColorize;
 use smash_arc::*;
-use walkdir::WalkDir;
 
-use crate::{config, hashes, resource};
+use crate::{config, fs::ApiLoader, hashes, resource};
 
 #[no_mangle]
 pub extern "C" fn arcrop_load_file(hash: Hash40, out_buffer: *mut u8, buf_length: usize, out_size: &mut usize) -> bool {
@@ -29,6 +28,71 @@ pub extern "C" fn arcrop_load_file(hash: Hash40, out_buffer: *mut u8, buf_length
     }
 }
 
+/// Loads the data of the layer beneath the callback that is currently running for the file: the callbacks registered before it,
+/// then the mods, then the game. Only usable from inside of a generic or extension callback, on the thread it was called on,
+/// since the layers only exist while the callback runs. Anywhere else, it fails and sets the out size to 0.
+#[no_mangle]
+pub extern "C" fn arcrop_load_next_layer(hash: Hash40, out_buffer: *mut u8, buf_length: usize, out_size: &mut usize) -> bool {
+    debug!(
+        "arcrop_load_next_layer -> Hash received: {} ({:#x}), Buffer len: {:#x}",
+        hashes::find(hash).green(),
+        hash.0,
+        buf_length
+    );
+
+    if !ApiLoader::in_layered_callback() {
+        error!("arcrop_load_next_layer -> Called outside of a generic or extension callback, there is no layer to load.");
+        *out_size = 0;
+        return false;
+    }
+
+    match ApiLoader::next_layer(hash) {
+        Some(data) if data.len() <= buf_length => {
+            let buffer = unsafe { std::slice::from_raw_parts_mut(out_buffer, buf_length) };
+            buffer[..data.len()].copy_from_slice(&data);
+            *out_size = data.len();
+            true
+        },
+        Some(data) => {
+            error!(
+                "arcrop_load_next_layer -> Buffer of size {:#x} is too small for the {:#x} bytes of '{}' ({:#x}).",
+                buf_length,
+                data.len(),
+                hashes::find(hash),
+                hash.0
+            );
+            *out_size = data.len();
+            false
+        },
+        None => {
+            error!(
+                "arcrop_load_next_layer -> The running callback is not for '{}' ({:#x}).",
+                hashes::find(hash),
+                hash.0
+            );
+            *out_size = 0;
+            false
+        },
+    }
+}
+
+/// Reloads the files of the enabled mods which were modified since the boot or the previous reload.
+/// Returns how many files were reloaded, files which need a reboot to be reloaded are not counted.
+#[no_mangle]
+pub extern "C" fn arcrop_reload_mods() -> usize {
+    debug!("arcrop_reload_mods");
+
+    match crate::fs::reload_mods() {
+        Some(report) => {
+            for path in report.needs_reboot.iter() {
+                warn!("arcrop_reload_mods -> '{}' needs a reboot to be reloaded.", path.display());
+            }
+            report.reloaded.len()
+        },
+        None => 0,
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn arcrop_get_decompressed_size(hash: Hash40, out_size: &mut usize) -> bool {
     debug!(
@@ -75,46 +139,34 @@ pub extern "C" fn arcrop_is_file_loaded(hash: Hash40) -> bool {
     }
 }
 
+/// The hash is either the one of the path of the mod, such as `sd:/ultimate/mods/My Mod`, or the one of its identity, which is
+/// the `id` of its `info.toml` or its folder name
 #[no_mangle]
 pub extern "C" fn arcrop_is_mod_enabled(hash: Hash40) -> bool {
     debug!("arcrop_is_mod_enabled -> Received hash {} ({:#x})", hashes::find(hash).green(), hash.0);
 
     let storage = crate::config::GLOBAL_CONFIG.lock().unwrap();
+    let legacy_discovery = storage.get_flag("legacy_discovery");
 
-    let preset: HashSet<Hash40> = if storage.get_flag("legacy_discovery") {
-        WalkDir::new(crate::config::umm_path())
-            .max_depth(1)
-            .into_iter()
-            .filter_map(|entry| {
-                if let Ok(entry) = entry {
-                    // Make this less gross
-                    if !entry.file_type().is_dir() {
-                        return None;
-                    }
-
-                    let path = entry.path();
-
-                    if path
-                        .file_name()
-                        .and_then(|name| name.to_str())
-                        .map(|name| !name.starts_with('.'))
-                        .unwrap_or(false)
-                    {
-                        Some(Hash40::from(path.to_str().unwrap()))
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            })
-            .collect()
+    let preset: HashSet<String> = if legacy_discovery {
+        HashSet::new()
     } else {
-        let workspace_name: String = storage.get_field("workspace").unwrap_or_else(|_| "Default".to_string());
-        let workspace_list: HashMap<String, String> = storage.get_field_json("workspace_list").unwrap_or_default();
-        let preset_name = &workspace_list[&workspace_name];
-        storage.get_field_json(preset_name).unwrap_or_default()
+        storage.get_field_json(config::active_preset_name(&storage)).unwrap_or_default()
     };
 
-    preset.contains(&hash)
+    // The installed mods are read with the configuration the first time, so it has to be unlocked first
+    drop(storage);
+
+    crate::fs::installed_mod_ids().iter().any(|(root, id)| {
+        if root.to_str().map(Hash40::from) != Some(hash) && Hash40::from(id.as_str()) != hash {
+            return false;
+        }
+
+        if legacy_discovery {
+            // Legacy discovery loads every mod, except for the ones with a period at the start of their name
+            !crate::fs::folder_name(root).starts_with('.')
+        } else {
+            preset.contains(id)
+        }
+    })
 }
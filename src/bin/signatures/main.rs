@@ -0,0 +1,141 @@
+//! Runs the offset scanner of ARCropolis over the code of a dumped `main`, so that the signatures can be checked against
+//! a new version of the game without a console. Every resolved offset is printed, along with the signatures which
+//! match more than once or resolve to the same offset as another one.
+//!
+//! Usage: `arcropolis-signatures <main>`, where `<main>` is either an NSO with an uncompressed text segment or the raw
+//! text segment.
+
+use std::{collections::HashMap, path::Path};
+
+#[path = "../../offsets/signatures.rs"]
+mod signatures;
+
+use signatures::{global_from_adrp, Signature, FILESYSTEM_INFO_ADRP, RES_SERVICE_ADRP, SIGNATURES};
+
+static NSO_MAGIC: &[u8] = b"NSO0";
+/// Set in the flags of the header when the text segment is compressed with LZ4
+const NSO_TEXT_COMPRESSED: u32 = 1 << 0;
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Gets the text segment of a `main` dump, which is either an NSO or the segment itself
+fn read_text(path: &Path) -> Result<Vec<u8>, String> {
+    let data = std::fs::read(path).map_err(|e| format!("Unable to read '{}': {}", path.display(), e))?;
+
+    if !data.starts_with(NSO_MAGIC) {
+        return Ok(data);
+    }
+
+    let header = |offset| read_u32(&data, offset).ok_or_else(|| String::from("The NSO header is truncated"));
+
+    if header(0xC)? & NSO_TEXT_COMPRESSED != 0 {
+        return Err(String::from(
+            "The text segment of the NSO is compressed, decompress it first (for example with `hactool --uncompressed`)",
+        ));
+    }
+
+    let offset = header(0x10)? as usize;
+    let size = header(0x18)? as usize;
+
+    data.get(offset..offset + size)
+        .map(|text| text.to_vec())
+        .ok_or_else(|| String::from("The text segment is outside of the NSO"))
+}
+
+struct Resolved {
+    signature: &'static Signature,
+    offset: Option<usize>,
+    /// Every position of the code matched by the patterns of the signature
+    matches: Vec<usize>,
+}
+
+fn main() {
+    let path = match std::env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: arcropolis-signatures <main>");
+            std::process::exit(2);
+        },
+    };
+
+    let text = match read_text(Path::new(&path)) {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        },
+    };
+
+    let resolved: Vec<Resolved> = SIGNATURES
+        .iter()
+        .map(|signature| {
+            let mut matches: Vec<usize> = signature.patterns.iter().flat_map(|pattern| pattern.find_all(&text)).collect();
+            matches.sort_unstable();
+            matches.dedup();
+
+            Resolved {
+                signature,
+                offset: signature.resolve(&text),
+                matches,
+            }
+        })
+        .collect();
+
+    let mut problems = 0;
+
+    for entry in resolved.iter() {
+        match entry.offset {
+            Some(offset) => println!("{:<24} {:#x}", entry.signature.name, offset),
+            None if entry.signature.optional => println!("{:<24} missing (optional)", entry.signature.name),
+            None => {
+                println!("{:<24} missing", entry.signature.name);
+                problems += 1;
+            },
+        }
+
+        // The plugin uses the first match, which might not be the right one when there are several of them
+        if entry.matches.len() > 1 {
+            let positions: Vec<String> = entry.matches.iter().map(|position| format!("{:#x}", position)).collect();
+            println!("  ambiguous: the patterns match at {}", positions.join(", "));
+            problems += 1;
+        }
+    }
+
+    let mut offsets: HashMap<usize, Vec<&str>> = HashMap::new();
+
+    for entry in resolved.iter() {
+        if let Some(offset) = entry.offset {
+            offsets.entry(offset).or_default().push(entry.signature.name);
+        }
+    }
+
+    let mut duplicates: Vec<(usize, Vec<&str>)> = offsets.into_iter().filter(|(_, names)| names.len() > 1).collect();
+    duplicates.sort_unstable();
+
+    for (offset, names) in duplicates {
+        println!("duplicate: {} all resolve to {:#x}", names.join(", "), offset);
+        problems += 1;
+    }
+
+    // The globals are what the plugin actually reads, not the instructions loading them
+    for signature in [&FILESYSTEM_INFO_ADRP, &RES_SERVICE_ADRP] {
+        let global = resolved
+            .iter()
+            .find(|entry| std::ptr::eq(entry.signature, signature))
+            .and_then(|entry| entry.offset)
+            .and_then(|adrp| global_from_adrp(&text, adrp));
+
+        match global {
+            Some(global) => println!("{:<24} {:#x} (global)", signature.name, global),
+            None => println!("{:<24} missing (global)", signature.name),
+        }
+    }
+
+    if problems != 0 {
+        eprintln!("error: {} problems were found with the signatures", problems);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,404 @@
+//! Runs the ARCropolis discovery and patching pipeline over a local mods directory and a dumped `data.arc`,
+//! so that modpacks can be validated without a console.
+//!
+//! Usage: `arcropolis-simulator --arc <data.arc> --mods <dir> [--extra <dir>]... [--region us_en] [--presets <file.json>]
+//! [--priority <file.json>] [--hashes <hashes.txt>] [--output <file.json>] [--strict]`
+//!
+//! The presets and the priority are lists of mod identities, like the ones of a workspace.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use orbits::{ConflictHandler, ConflictKind, LaunchPad, StandardLoader};
+use serde::{de::DeserializeOwned, Serialize};
+use smash_arc::{ArcFile, ArcLookup, Hash40};
+
+#[path = "../../replacement/config.rs"]
+mod mod_config;
+#[path = "../../fs/pipeline.rs"]
+mod pipeline;
+mod platform;
+mod tables;
+
+/// The pipeline refers to the config of the mods by its path in the plugin
+mod replacement {
+    pub(crate) use super::mod_config as config;
+}
+
+use pipeline::{Platform, TablePlan};
+use platform::HostPlatform;
+use replacement::config::ModConfig;
+use tables::TableDiff;
+
+static DEFAULT_CONFIG: &str = include_str!("../../../resources/override.json");
+
+struct Arguments {
+    mods: PathBuf,
+    extra: Vec<PathBuf>,
+    hashes: Option<PathBuf>,
+    output: Option<PathBuf>,
+    strict: bool,
+    platform: HostPlatform,
+}
+
+#[derive(Serialize)]
+struct SizePatch {
+    path: PathBuf,
+    vanilla_size: usize,
+    size: usize,
+}
+
+#[derive(Serialize)]
+struct Conflict {
+    local: PathBuf,
+    winner: PathBuf,
+    loser: PathBuf,
+}
+
+/// Everything the pipeline would do to the filesystem and the tables of the game
+#[derive(Serialize, Default)]
+struct SimulationReport {
+    /// Every enabled mod root, from the highest to the lowest priority
+    roots: Vec<PathBuf>,
+    /// Every discovered file, with the path it is loaded from
+    tree: BTreeMap<PathBuf, PathBuf>,
+    conflicts: Vec<Conflict>,
+    rejected_roots: Vec<PathBuf>,
+    /// Files that are bigger than their vanilla counterpart, and need their size patched. The files generated by patch
+    /// files are measured once they are patched, on the console.
+    size_patches: Vec<SizePatch>,
+    /// NUS3BANK files that have to be unshared for the NUS3AUDIO files that were replaced
+    unshared_nus3banks: Vec<PathBuf>,
+    /// Patch files, with the file of the game they apply to
+    patches: BTreeMap<PathBuf, PathBuf>,
+    tables: TableDiff,
+}
+
+fn main() {
+    let args = match parse_arguments() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            eprintln!("usage: arcropolis-simulator --arc <data.arc> --mods <dir> [--extra <dir>]... [--region us_en] [--presets <file.json>] [--priority <file.json>] [--hashes <hashes.txt>] [--output <file.json>] [--strict]");
+            std::process::exit(2);
+        },
+    };
+
+    let labels = match args.hashes.as_deref().map(read_hashes).transpose() {
+        Ok(labels) => labels.unwrap_or_default(),
+        Err(e) => {
+            args.platform.dialog_error(&e);
+            std::process::exit(1);
+        },
+    };
+
+    let mod_dirs: Vec<PathBuf> = std::iter::once(args.mods.clone()).chain(args.extra.iter().cloned()).collect();
+    let report = simulate(&args.platform, &mod_dirs, labels);
+
+    match serde_json::to_string_pretty(&report) {
+        Ok(json) => {
+            if let Some(output) = args.output.as_ref() {
+                if let Err(e) = std::fs::write(output, json.as_bytes()) {
+                    args.platform
+                        .dialog_error(&format!("Failed to write report to '{}'. Reason: {:?}", output.display(), e));
+                    std::process::exit(1);
+                }
+            } else {
+                println!("{}", json);
+            }
+        },
+        Err(e) => {
+            args.platform.dialog_error(&format!("Failed to serialize the report to JSON. {:?}", e));
+            std::process::exit(1);
+        },
+    }
+
+    if args.strict && (!report.conflicts.is_empty() || !report.rejected_roots.is_empty()) {
+        eprintln!(
+            "error: {} file conflicts were found",
+            report.conflicts.len() + report.rejected_roots.len()
+        );
+        std::process::exit(1);
+    }
+}
+
+fn parse_arguments() -> Result<Arguments, String> {
+    let mut arc = None;
+    let mut mods = None;
+    let mut extra = Vec::new();
+    let mut hashes = None;
+    let mut output = None;
+    let mut strict = false;
+    let mut region = String::from("us_en");
+    let mut presets = None;
+    let mut priority = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("Missing value for '{}'", arg));
+
+        match arg.as_str() {
+            "--arc" => arc = Some(PathBuf::from(value()?)),
+            "--mods" => mods = Some(PathBuf::from(value()?)),
+            "--extra" => extra.push(PathBuf::from(value()?)),
+            "--hashes" => hashes = Some(PathBuf::from(value()?)),
+            "--output" => output = Some(PathBuf::from(value()?)),
+            "--region" => {
+                region = value()?;
+                if !pipeline::REGIONS.contains(&region.as_str()) {
+                    return Err(format!("Unknown region '{}'", region));
+                }
+            },
+            "--presets" => presets = Some(read_json::<HashSet<String>>(value()?)?),
+            "--priority" => priority = read_json(value()?)?,
+            "--strict" => strict = true,
+            _ => return Err(format!("Unknown argument '{}'", arg)),
+        }
+    }
+
+    let arc = arc.ok_or("Missing '--arc'")?;
+    let arc = ArcFile::open(&arc).map_err(|e| format!("Failed to open '{}'. Reason: {:?}", arc.display(), e))?;
+
+    Ok(Arguments {
+        mods: mods.ok_or("Missing '--mods'")?,
+        extra,
+        hashes,
+        output,
+        strict,
+        platform: HostPlatform {
+            region,
+            priority,
+            presets,
+            arc,
+        },
+    })
+}
+
+fn read_json<T: DeserializeOwned>(path: String) -> Result<T, String> {
+    let json = std::fs::read_to_string(&path).map_err(|e| format!("Failed to read '{}'. Reason: {:?}", path, e))?;
+    serde_json::from_str(&json).map_err(|e| format!("Failed to parse '{}'. Reason: {:?}", path, e))
+}
+
+/// Reads a list of paths of the game, one per line, to name the hashes of the report with
+fn read_hashes(path: &Path) -> Result<HashMap<Hash40, String>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read '{}'. Reason: {:?}", path.display(), e))?;
+    Ok(text.lines().map(|line| (Hash40::from(line.trim()), line.trim().to_string())).collect())
+}
+
+/// Merges the configs that were collected during discovery into the default config, the way the plugin does
+fn load_configs<P: Platform>(platform: &P, collected: &[(PathBuf, PathBuf)]) -> ModConfig {
+    let mut config: ModConfig = serde_json::from_str(DEFAULT_CONFIG).unwrap_or_default();
+
+    for full_path in collected
+        .iter()
+        .map(|(root, local)| root.join(local))
+        .filter(|path| path.ends_with("config.json"))
+    {
+        let parsed = std::fs::read(&full_path)
+            .map_err(|e| format!("{:?}", e))
+            .and_then(|data| serde_json::from_slice::<serde_json::Value>(&data).map_err(|e| format!("{:?}", e)))
+            .and_then(|value| {
+                for key in ModConfig::unknown_keys(&value) {
+                    eprintln!("warning: Unknown key '{}' in {} will be ignored.", key, full_path.display());
+                }
+
+                serde_json::from_value::<ModConfig>(value).map_err(|e| format!("{:?}", e))
+            });
+
+        match parsed {
+            Ok(other) => config.merge(other),
+            Err(e) => platform.dialog_error(&format!(
+                "Could not read/parse JSON data from file {}. Reason: {}",
+                full_path.display(),
+                e
+            )),
+        }
+    }
+
+    config
+}
+
+fn simulate(platform: &HostPlatform, mod_dirs: &[PathBuf], mut labels: HashMap<Hash40, String>) -> SimulationReport {
+    let arc = platform.arc();
+    let region_str = platform.region_str();
+    let region = pipeline::region_from_str(&region_str);
+
+    let mut installed = Vec::new();
+
+    for path in mod_dirs.iter() {
+        match pipeline::collect_mod_roots(path, |_| true) {
+            Ok(roots) => installed.extend(roots),
+            Err(e) => platform.dialog_error(&format!("Failed to read mod directory '{}'. Reason: {:?}", path.display(), e)),
+        }
+    }
+
+    let enabled: Vec<PathBuf> = installed
+        .into_iter()
+        .filter(|root| platform.is_mod_enabled(root))
+        .filter(|root| {
+            // Reading mod archives needs the filesystem of the plugin
+            if pipeline::is_archive(root) {
+                platform.dialog_error(&format!(
+                    "The mod archive '{}' cannot be simulated, extract it to include it.",
+                    root.display()
+                ));
+            }
+            !pipeline::is_archive(root)
+        })
+        .collect();
+
+    let mut report = SimulationReport {
+        roots: pipeline::sort_by_priority(platform, enabled),
+        ..Default::default()
+    };
+
+    let mut launchpad = LaunchPad::new(StandardLoader, ConflictHandler::First);
+
+    launchpad.collecting(pipeline::is_collected);
+    launchpad.ignoring(move |path: &Path| pipeline::is_ignored(path, &region_str));
+
+    for root in report.roots.iter() {
+        for conflict in launchpad.discover_in_root(root) {
+            match conflict {
+                ConflictKind::StandardConflict {
+                    error_root,
+                    source_root,
+                    local,
+                } => report.conflicts.push(Conflict {
+                    local,
+                    winner: source_root,
+                    loser: error_root,
+                }),
+                ConflictKind::RootConflict(root_path, _) => report.rejected_roots.push(root_path),
+            }
+        }
+    }
+
+    let collected = launchpad.collected_paths().to_vec();
+    let config = load_configs(platform, &collected);
+
+    // The hash of every file the filesystem serves, with the local path it is served for
+    let mut served: HashMap<Hash40, PathBuf> = HashMap::new();
+
+    for (root, local) in collected.iter() {
+        if let Some(target) = pipeline::patch_target(local) {
+            report.patches.insert(root.join(local), target.clone());
+
+            if let Some(hash) = pipeline::smash_hash(&target).filter(|hash| arc.get_file_path_index_from_hash(*hash).is_ok()) {
+                served.insert(hash, target);
+            }
+        }
+    }
+
+    let mut locals = Vec::new();
+
+    launchpad.tree().walk_paths(|node, ty| {
+        if !ty.is_file() {
+            return;
+        }
+
+        let local = node.get_local().to_path_buf();
+        let full_path = node.full_path();
+        report.tree.insert(local.clone(), full_path.clone());
+        locals.push(local.clone());
+
+        let hash = match pipeline::smash_hash(&local) {
+            Some(hash) => hash,
+            None => {
+                platform.dialog_error(&format!("Failed to get hash for {}.", local.display()));
+                return;
+            },
+        };
+
+        if let Ok(data) = arc.get_file_data_from_hash(hash, region) {
+            let size = platform::mod_file_size(&full_path);
+
+            if size > data.decomp_size as usize {
+                report.size_patches.push(SizePatch {
+                    path: local.clone(),
+                    vanilla_size: data.decomp_size as usize,
+                    size,
+                });
+            }
+        }
+
+        served.insert(hash, pipeline::uncompressed_path(&local));
+    });
+
+    report.unshared_nus3banks = pipeline::required_nus3banks(launchpad.tree(), &config.unshare_blacklist)
+        .into_iter()
+        .collect();
+    report.unshared_nus3banks.sort();
+
+    for bank in report.unshared_nus3banks.iter() {
+        if let Some(hash) = pipeline::smash_hash(bank) {
+            served.insert(hash, bank.clone());
+        }
+    }
+
+    report.size_patches.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let (shared_groups, _) = pipeline::shared_file_groups(arc);
+    let shared: HashSet<Hash40> = shared_groups
+        .iter()
+        .flat_map(|(source, group)| std::iter::once(source).chain(group))
+        .copied()
+        .collect();
+
+    let plan = TablePlan::new(
+        platform,
+        &config,
+        &locals,
+        served.keys().copied(),
+        |hash| served.get(&hash).cloned().or_else(|| labels.get(&hash).map(PathBuf::from)),
+        |hash| shared.contains(&hash),
+    );
+
+    for hash in plan.unknown_files.iter() {
+        platform.dialog_error(&format!(
+            "Cannot add new file ({:#x}) because its path is unknown. Add it to the hashes or to a mod folder.",
+            hash.0
+        ));
+    }
+
+    // The served files are named by their path, the other ones by the hashes that were provided
+    for (hash, local) in served.iter() {
+        if let Some(local) = local.to_str() {
+            labels.insert(*hash, local.to_string());
+        }
+    }
+
+    report.tables = TableDiff::new(arc, region, &plan, &shared_groups, |hash| {
+        labels.get(&hash).cloned().unwrap_or_else(|| format!("{:#x}", hash.0))
+    });
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_short_region_suffixes() {
+        assert_eq!(
+            pipeline::smash_hash(Path::new("ui/message/msg_name+us")),
+            Some(Hash40::from("ui/message/msg_name"))
+        );
+        assert_eq!(
+            pipeline::smash_hash(Path::new("ui/message/msg_name+us_en.msbt")),
+            Some(Hash40::from("ui/message/msg_name.msbt"))
+        );
+        assert_eq!(
+            pipeline::smash_hash(Path::new("ui/message/msg_name+")),
+            Some(Hash40::from("ui/message/msg_name"))
+        );
+        assert_eq!(
+            pipeline::patch_target(Path::new("ui/message/msg_name+us_en.xmsbt")),
+            Some(PathBuf::from("ui/message/msg_name.msbt"))
+        );
+    }
+}
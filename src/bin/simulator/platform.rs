@@ -0,0 +1,66 @@
+use std::{collections::HashSet, path::Path};
+
+use smash_arc::ArcFile;
+
+use crate::pipeline::{self, Platform};
+
+/// Answers the pipeline using the arguments of the simulator and a dumped `data.arc`
+pub struct HostPlatform {
+    pub region: String,
+    pub priority: Vec<String>,
+    /// The identities of the enabled mods, or `None` to use the legacy discovery rules
+    pub presets: Option<HashSet<String>>,
+    pub arc: ArcFile,
+}
+
+impl Platform for HostPlatform {
+    type Arc = ArcFile;
+
+    fn region_str(&self) -> String {
+        self.region.clone()
+    }
+
+    fn mod_priority(&self) -> Vec<String> {
+        self.priority.clone()
+    }
+
+    fn mod_id(&self, root: &Path) -> String {
+        let info = std::fs::read(root.join("info.toml")).ok();
+        pipeline::mod_id_from_info(root, info.as_deref())
+    }
+
+    fn is_mod_enabled(&self, root: &Path) -> bool {
+        match self.presets.as_ref() {
+            Some(presets) => presets.contains(&self.mod_id(root)),
+            None => pipeline::is_enabled_by_name(root),
+        }
+    }
+
+    fn dialog_error(&self, msg: &str) {
+        eprintln!("error: {}", msg.replace("<br>", "\n"));
+    }
+
+    fn arc(&self) -> &ArcFile {
+        &self.arc
+    }
+}
+
+/// Gets the size of a mod file once the game loads it, which is the decompressed size for files compressed with zstd
+pub fn mod_file_size(path: &Path) -> usize {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(_) => return 0,
+    };
+
+    if !pipeline::is_compressed(path) || !pipeline::is_zstd(&data) {
+        return data.len();
+    }
+
+    pipeline::zstd_content_size(&data).unwrap_or_else(|| {
+        let mut cursor = std::io::Cursor::new(data);
+        ruzstd::StreamingDecoder::new(&mut cursor)
+            .ok()
+            .and_then(|mut decoder| std::io::copy(&mut decoder, &mut std::io::sink()).ok())
+            .map_or(0, |size| size as usize)
+    })
+}
@@ -0,0 +1,187 @@
+//! Measures what the edits planned by the pipeline do to the tables of the `data.arc`. The edits themselves are made by
+//! `replacement::addition` and `replacement::unshare`, which work on the memory of the running game, so the entries they
+//! push are counted here instead.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
+
+use serde::Serialize;
+use smash_arc::{ArcLookup, Hash40, Region};
+
+use crate::pipeline::TablePlan;
+
+/// The number of entries of a table, before and after the mods edit it
+#[derive(Serialize, Default)]
+pub struct TableSize {
+    pub before: usize,
+    pub after: usize,
+}
+
+impl TableSize {
+    fn new(before: usize) -> Self {
+        Self { before, after: before }
+    }
+}
+
+#[derive(Serialize)]
+pub struct SharedFile {
+    pub path: PathBuf,
+    pub shared_to: String,
+}
+
+#[derive(Serialize)]
+pub struct UnsharedFile {
+    pub path: String,
+    /// The file which owns the data it shared
+    pub source: String,
+    /// Whether the files sharing its data are given a copy of it first, which happens when it owns the data
+    pub reshares_dependents: bool,
+}
+
+#[derive(Serialize)]
+pub struct DirectoryDiff {
+    pub directory: String,
+    pub files_before: usize,
+    pub files_after: usize,
+    pub added: Vec<String>,
+}
+
+/// Everything the mods change in the tables of the game
+#[derive(Serialize, Default)]
+pub struct TableDiff {
+    pub file_paths: TableSize,
+    pub file_info_indices: TableSize,
+    pub file_infos: TableSize,
+    pub info_to_datas: TableSize,
+    pub file_datas: TableSize,
+    /// Files which are not part of the game, added with their own data
+    pub added_files: Vec<PathBuf>,
+    /// Files which are not part of the game, added to share the data of a file of the game
+    pub shared_files: Vec<SharedFile>,
+    /// Replaced files which are given data of their own
+    pub unshared_files: Vec<UnsharedFile>,
+    /// Directories which are loaded with more files than they were
+    pub directories: Vec<DirectoryDiff>,
+}
+
+impl TableDiff {
+    /// Pushes a whole chain of entries, from a `FilePath` to a `FileData`
+    fn push_file_chain(&mut self) {
+        self.file_paths.after += 1;
+        self.push_data_chain();
+    }
+
+    /// Pushes a chain of entries from a `FileInfoIndex` to a `FileData`, for an existing `FilePath` to point to
+    fn push_data_chain(&mut self) {
+        self.file_info_indices.after += 1;
+        self.file_infos.after += 1;
+        self.info_to_datas.after += 1;
+        self.file_datas.after += 1;
+    }
+
+    /// Counts the entries the edits of the plan push. `shared_groups` are the files which share the data of every file that
+    /// owns some, and `label` names a hash for the report.
+    pub fn new<A, L>(arc: &A, region: Region, plan: &TablePlan, shared_groups: &HashMap<Hash40, Vec<Hash40>>, label: L) -> Self
+    where
+        A: ArcLookup,
+        L: Fn(Hash40) -> String,
+    {
+        let mut diff = Self {
+            file_paths: TableSize::new(arc.get_file_paths().len()),
+            file_info_indices: TableSize::new(arc.get_file_info_indices().len()),
+            file_infos: TableSize::new(arc.get_file_infos().len()),
+            info_to_datas: TableSize::new(arc.get_file_info_to_datas().len()),
+            file_datas: TableSize::new(arc.get_file_datas().len()),
+            ..Default::default()
+        };
+
+        let mut added = HashSet::new();
+
+        // See `addition::add_file`
+        for path in plan.added_files.iter() {
+            diff.push_file_chain();
+            diff.added_files.push(path.clone());
+
+            if let Some(hash) = crate::pipeline::smash_hash(path) {
+                added.insert(hash);
+            }
+        }
+
+        // See `addition::add_shared_file`, which only pushes a `FilePath` pointing to the data of the other file
+        for (path, shared_to) in plan.shared_files.iter() {
+            if arc.get_file_info_from_hash(*shared_to).is_ok() {
+                diff.file_paths.after += 1;
+                diff.shared_files.push(SharedFile {
+                    path: path.clone(),
+                    shared_to: label(*shared_to),
+                });
+            }
+        }
+
+        // See `unshare::unshare_file`, which leaves out the files that are not part of a directory
+        let directories = crate::pipeline::file_directories(arc);
+        let shared_data_index = arc.get_shared_data_index();
+
+        for hash in plan.unshared_files.iter().filter(|hash| directories.contains_key(hash)) {
+            let (index, info) = match (arc.get_file_path_index_from_hash(*hash), arc.get_file_info_from_hash(*hash)) {
+                (Ok(index), Ok(info)) => (index, info),
+                _ => continue,
+            };
+
+            let source = crate::pipeline::shared_file_index(arc, *hash).map_or(*hash, |idx| arc.get_file_paths()[idx].path.hash40());
+            let owns_data = info.file_path_index == index;
+            let reshares_dependents = owns_data && shared_groups.get(hash).map_or(false, |group| !group.is_empty());
+
+            // See `unshare::reshare_dependent_files`
+            if reshares_dependents {
+                diff.push_file_chain();
+            }
+
+            // Files which own data outside of the shared section already load on their own
+            if !owns_data || arc.get_file_in_folder(info, region).file_data_index.0 >= shared_data_index {
+                diff.push_data_chain();
+            }
+
+            diff.unshared_files.push(UnsharedFile {
+                path: label(*hash),
+                source: label(source),
+                reshares_dependents,
+            });
+        }
+
+        // See `addition::add_files_to_directory`, which copies the file infos of the directory to the end of the table
+        for (directory, files) in plan.directory_additions.iter() {
+            let dir_info = match arc.get_dir_info_from_hash(*directory) {
+                Ok(dir_info) => dir_info,
+                Err(_) => continue,
+            };
+
+            let file_paths = arc.get_file_paths();
+            let contained: HashSet<Hash40> = arc.get_file_infos()[dir_info.file_info_range()]
+                .iter()
+                .map(|info| file_paths[info.file_path_index].path.hash40())
+                .collect();
+
+            let new_files: Vec<String> = files
+                .iter()
+                .filter(|file| !contained.contains(file) && (added.contains(file) || arc.get_file_path_index_from_hash(**file).is_ok()))
+                .map(|file| label(*file))
+                .collect();
+
+            let files_before = dir_info.file_info_range().len();
+            let files_after = files_before + new_files.len();
+
+            diff.file_infos.after += files_after;
+            diff.directories.push(DirectoryDiff {
+                directory: label(*directory),
+                files_before,
+                files_after,
+                added: new_files,
+            });
+        }
+
+        diff
+    }
+}
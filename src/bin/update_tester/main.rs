@@ -0,0 +1,170 @@
+//! Installs a release archive over a local copy of the SD card the way the updater does, then goes through a boot which
+//! never confirms itself and one which does, so that the rollback can be checked without a console or a network.
+//!
+//! Usage: `arcropolis-update-tester <release.zip> <sd>`, where `<sd>` is a copy of the root of the SD card. It is left
+//! with the update installed.
+
+use std::{
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+};
+
+use zip::ZipArchive;
+
+#[path = "../../update/rollback.rs"]
+mod rollback;
+
+use rollback::{PreviousBoot, UpdateLayout};
+
+/// Gets the content of every file of a release archive, by path relative to the root of the SD card
+fn archive_files(data: &[u8]) -> Result<Vec<(PathBuf, Vec<u8>)>, String> {
+    let mut zip = ZipArchive::new(Cursor::new(data)).map_err(|e| format!("The archive could not be read: {}", e))?;
+    let mut files = Vec::new();
+
+    for idx in 0..zip.len() {
+        let mut file = zip.by_index(idx).map_err(|e| format!("The archive could not be read: {}", e))?;
+
+        if file.is_dir() {
+            continue;
+        }
+
+        if let Some(local) = file.enclosed_name().map(Path::to_path_buf) {
+            let mut content = Vec::new();
+            file.read_to_end(&mut content).map_err(|e| format!("'{}' could not be read: {}", local.display(), e))?;
+            files.push((local, content));
+        }
+    }
+
+    Ok(files)
+}
+
+fn expect_boot(layout: &UpdateLayout, expected: PreviousBoot) -> Result<(), String> {
+    let found = rollback::check_previous_boot(layout).map_err(|e| format!("The boot check failed: {}", e))?;
+
+    if found == expected {
+        Ok(())
+    } else {
+        Err(format!("The boot check found {:?} instead of {:?}", found, expected))
+    }
+}
+
+/// Installs the archive, fails its first boot and checks that the installation is restored, then installs it again and
+/// checks that a confirmed boot keeps it
+fn run(data: &[u8], layout: &UpdateLayout) -> Result<(), String> {
+    let files = archive_files(data)?;
+    let before: Vec<Option<Vec<u8>>> = files.iter().map(|(local, _)| std::fs::read(layout.root.join(local)).ok()).collect();
+
+    rollback::install_archive(layout, data, "previous").map_err(|e| format!("The installation failed: {}", e))?;
+    println!("installed {} files", files.len());
+
+    expect_boot(layout, PreviousBoot::FirstBootOfUpdate)?;
+    println!("first boot of the update, which never confirms itself");
+
+    expect_boot(layout, PreviousBoot::RolledBack(String::from("previous")))?;
+
+    for ((local, _), before) in files.iter().zip(before.iter()) {
+        if std::fs::read(layout.root.join(local)).ok() != *before {
+            return Err(format!("'{}' was not restored by the rollback", local.display()));
+        }
+    }
+
+    println!("rolled back, every file is the way it was");
+
+    rollback::install_archive(layout, data, "previous").map_err(|e| format!("The installation failed: {}", e))?;
+    expect_boot(layout, PreviousBoot::FirstBootOfUpdate)?;
+
+    if !rollback::confirm_boot(layout) {
+        return Err(String::from("The boot of the update could not be confirmed"));
+    }
+
+    expect_boot(layout, PreviousBoot::Confirmed)?;
+
+    for (local, content) in files.iter() {
+        if std::fs::read(layout.root.join(local)).ok().as_ref() != Some(content) {
+            return Err(format!("'{}' is not the one of the update after a confirmed boot", local.display()));
+        }
+    }
+
+    println!("confirmed boot, the update stays installed");
+
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    if args.len() != 2 {
+        eprintln!("usage: arcropolis-update-tester <release.zip> <sd>");
+        std::process::exit(2);
+    }
+
+    let data = match std::fs::read(&args[0]) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("error: Unable to read '{}': {}", args[0], e);
+            std::process::exit(1);
+        },
+    };
+
+    let root = PathBuf::from(&args[1]);
+    let layout = UpdateLayout {
+        updates: root.join("ultimate/arcropolis/updates"),
+        root,
+    };
+
+    if let Err(e) = std::fs::create_dir_all(&layout.updates) {
+        eprintln!("error: Unable to create '{}': {}", layout.updates.display(), e);
+        std::process::exit(1);
+    }
+
+    if let Err(e) = run(&data, &layout) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+    use super::*;
+
+    fn release(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+
+        for (path, content) in files {
+            zip.start_file(*path, options).unwrap();
+            zip.write_all(content).unwrap();
+        }
+
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn rolls_back_an_unconfirmed_update() {
+        let root = std::env::temp_dir().join(format!("arcropolis-update-tester-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let plugin = root.join("atmosphere/contents/01006A800016E000/romfs/skyline/plugins/libarcropolis.nro");
+        std::fs::create_dir_all(plugin.parent().unwrap()).unwrap();
+        std::fs::write(&plugin, b"old plugin").unwrap();
+
+        let layout = UpdateLayout {
+            updates: root.join("ultimate/arcropolis/updates"),
+            root: root.clone(),
+        };
+        std::fs::create_dir_all(&layout.updates).unwrap();
+
+        let data = release(&[
+            ("atmosphere/contents/01006A800016E000/romfs/skyline/plugins/libarcropolis.nro", b"new plugin"),
+            ("ultimate/arcropolis/changelog.toml", b"new changelog"),
+        ]);
+
+        let result = run(&data, &layout);
+        let _ = std::fs::remove_dir_all(&root);
+        result.unwrap();
+    }
+}
@@ -127,7 +127,8 @@ pub struct NroBuilder {
 
 impl NroBuilder {
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, io::Error> {
-        Ok(Self { data: std::fs::read(path)? })
+        // Plugins can be packed in a mod archive, so they are read the same way as the other files of a mod
+        Ok(Self { data: crate::fs::archive::read_mod_file(path)? })
     }
 
     pub fn mount(self) -> Result<Module, NroMountFailedError> {
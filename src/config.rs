@@ -29,7 +29,16 @@ fn default_region() -> String {
 }
 
 pub static GLOBAL_CONFIG: Lazy<Mutex<StorageHolder<ArcStorage>>> = Lazy::new(|| {
-    let mut storage = StorageHolder::new(ArcStorage::new());
+    // Opening a storage creates its directory, so the one of the household is only opened once an account shared it
+    let household = household_exists().then(|| StorageHolder::new(ArcStorage::household()));
+
+    // Accounts following the household share its configuration, and only pick their own workspace in it
+    let follow_account = household.as_ref().map_or(false, follows_household);
+
+    let mut storage = match household {
+        Some(household) if follow_account => household,
+        _ => StorageHolder::new(ArcStorage::new()),
+    };
 
     let version: Result<Version, _> = storage.get_field("version");
 
@@ -108,18 +117,14 @@ pub static GLOBAL_CONFIG: Lazy<Mutex<StorageHolder<ArcStorage>>> = Lazy::new(||
         },
     }
 
+    if follow_account {
+        select_account_workspace(&mut storage);
+    }
+
     Mutex::new(storage)
 });
 
-pub static REGION: Lazy<Region> = Lazy::new(|| {
-    Region::from(
-        crate::REGIONS
-            .iter()
-            .position(|&x| x == region_str())
-            .map(|x| (x + 1) as u32)
-            .unwrap_or(0),
-    )
-});
+pub static REGION: Lazy<Region> = Lazy::new(|| crate::fs::pipeline::region_from_str(&region_str()));
 
 fn migrate_config_to_storage<CS: ConfigStorage>(storage: &mut StorageHolder<CS>, config: &Config) {
     info!("Converting legacy configuration file to ConfigStorage.");
@@ -145,7 +150,9 @@ fn generate_default_config<CS: ConfigStorage>(storage: &mut StorageHolder<CS>) {
     storage.set_field("logging_level", "Warn").unwrap();
     storage.set_field_json("extra_paths", &Vec::<String>::new()).unwrap();
     storage.set_flag("auto_update", true).unwrap();
-    storage.set_field_json("presets", &HashSet::<Hash40>::new()).unwrap();
+    storage.set_field_json("presets", &HashSet::<String>::new()).unwrap();
+    storage.set_flag("presets_by_id", true).unwrap();
+    storage.set_flag("priority_by_id", true).unwrap();
 
     let mut default_workspace = HashMap::<&str, &str>::new();
     default_workspace.insert("Default", "presets");
@@ -153,8 +160,219 @@ fn generate_default_config<CS: ConfigStorage>(storage: &mut StorageHolder<CS>) {
     storage.set_field("workspace", "Default").unwrap();
 }
 
-fn convert_legacy_to_presets() -> HashSet<Hash40> {
-    let mut presets: HashSet<Hash40> = HashSet::new();
+/// Gets the name of the storage field holding the hashes of the mods of a preset which could not be converted to their
+/// identity yet, see `migrate_presets`
+fn legacy_presets_field<S: AsRef<str>>(preset_name: S) -> String {
+    format!("{}_legacy", preset_name.as_ref())
+}
+
+/// Converts the hashes of the paths of mods to the identity of the installed ones, and gives back the hashes of the mods
+/// which are not installed
+fn identify_legacy_mods(hashes: HashSet<Hash40>, ids: &HashMap<Hash40, String>) -> (Vec<String>, HashSet<Hash40>) {
+    let (found, missing): (Vec<Hash40>, HashSet<Hash40>) = hashes.into_iter().partition(|hash| ids.contains_key(hash));
+    (found.iter().map(|hash| ids[hash].clone()).collect(), missing)
+}
+
+/// [3.4.0] Presets used to hold the hash of the path of the mods, which changed whenever a mod was renamed or moved, and
+/// the load priority used to hold their path. Both are converted to the identity of the mods. A mod which is not
+/// installed cannot be identified, so its hash is kept aside and converted once it is installed at the same path again.
+/// This runs after the logger is initialized, so that what could not be converted is reported.
+pub fn migrate_presets() {
+    let mut storage = GLOBAL_CONFIG.lock().unwrap();
+
+    let workspace_list: HashMap<String, String> = storage.get_field_json("workspace_list").unwrap_or_default();
+    let mut preset_names: Vec<String> = workspace_list.values().cloned().collect();
+    preset_names.push(String::from("presets"));
+    preset_names.sort_unstable();
+    preset_names.dedup();
+
+    let presets_by_id = storage.get_flag("presets_by_id");
+    let priority_by_id = storage.get_flag("priority_by_id");
+
+    let legacy: Vec<(String, HashSet<Hash40>)> = preset_names
+        .iter()
+        .filter_map(|preset_name| {
+            let hashes: HashSet<Hash40> = storage.get_field_json(legacy_presets_field(preset_name)).ok()?;
+            (!hashes.is_empty()).then(|| (preset_name.clone(), hashes))
+        })
+        .collect();
+
+    if presets_by_id && priority_by_id && legacy.is_empty() {
+        return;
+    }
+
+    // Every workspace is converted, so the extra paths of all of them are looked through
+    let mut extra_paths: Vec<String> = storage.get_field_json("extra_paths").unwrap_or_default();
+
+    for preset_name in preset_names.iter() {
+        if let Ok(settings) = storage.get_field_json::<WorkspaceSettings>(settings_field(preset_name)) {
+            extra_paths.extend(settings.extra_paths.unwrap_or_default());
+        }
+    }
+
+    let ids: HashMap<Hash40, String> = crate::fs::installed_mod_roots(&extra_paths)
+        .iter()
+        .filter_map(|root| Some((Hash40::from(root.to_str()?), crate::fs::mod_id(root))))
+        .collect();
+
+    if !presets_by_id {
+        // Nothing is lost by dropping the mods which are not installed from the known mods, they are only new ones again
+        if let Ok(hashes) = storage.get_field_json::<HashSet<Hash40>>("mod_cache") {
+            let mod_cache: HashSet<String> = hashes.iter().filter_map(|hash| ids.get(hash).cloned()).collect();
+            storage.set_field_json("mod_cache", &mod_cache).unwrap();
+        }
+
+        for preset_name in preset_names.iter() {
+            if let Ok(hashes) = storage.get_field_json::<HashSet<Hash40>>(preset_name) {
+                storage.set_field_json(preset_name, &HashSet::<String>::new()).unwrap();
+                storage.set_field_json(legacy_presets_field(preset_name), &hashes).unwrap();
+            }
+        }
+
+        storage.set_flag("presets_by_id", true).unwrap();
+    }
+
+    for preset_name in preset_names.iter() {
+        let hashes: HashSet<Hash40> = match storage.get_field_json(legacy_presets_field(preset_name)) {
+            Ok(hashes) => hashes,
+            Err(_) => continue,
+        };
+
+        if hashes.is_empty() {
+            continue;
+        }
+
+        let (found, missing) = identify_legacy_mods(hashes, &ids);
+
+        let mut presets: HashSet<String> = storage.get_field_json(preset_name).unwrap_or_default();
+        presets.extend(found);
+        storage.set_field_json(preset_name, &presets).unwrap();
+
+        if !missing.is_empty() {
+            warn!(
+                "{} enabled mods of '{}' are not installed anymore. They will be enabled again once they are installed where they were.",
+                missing.len(),
+                preset_name
+            );
+        }
+
+        storage.set_field_json(legacy_presets_field(preset_name), &missing).unwrap();
+    }
+
+    if !priority_by_id {
+        for preset_name in preset_names.iter() {
+            // A mod which is not installed anymore is identified by its folder name, which is what it falls back to
+            if let Ok(paths) = storage.get_field_json::<Vec<PathBuf>>(priority_field(preset_name)) {
+                let priority: Vec<String> = paths.iter().map(|path| crate::fs::mod_id(path)).collect();
+                storage.set_field_json(priority_field(preset_name), &priority).unwrap();
+            }
+        }
+
+        storage.set_flag("priority_by_id", true).unwrap();
+    }
+
+    storage.flush();
+}
+
+/// Selects the workspace the household assigned to the current account, if it still exists
+fn select_account_workspace<CS: ConfigStorage>(storage: &mut StorageHolder<CS>) {
+    let accounts: HashMap<String, String> = storage.get_field_json("account_workspaces").unwrap_or_default();
+    let workspace_list: HashMap<String, String> = storage.get_field_json("workspace_list").unwrap_or_default();
+
+    match accounts.get(&account_id()) {
+        Some(workspace) if workspace_list.contains_key(workspace) => storage.set_field("workspace", workspace).unwrap(),
+        Some(workspace) => warn!("The workspace '{}' assigned to this account does not exist anymore.", workspace),
+        None => {},
+    }
+}
+
+/// Whether the current account was assigned a workspace in this storage, which only happens in the one of the household
+pub fn follows_household<CS: ConfigStorage>(storage: &StorageHolder<CS>) -> bool {
+    let accounts: HashMap<String, String> = storage.get_field_json("account_workspaces").unwrap_or_default();
+    accounts.contains_key(&account_id())
+}
+
+/// Assigns a workspace to the current account, so that it is selected on boot while following the household
+pub fn assign_account_workspace<CS: ConfigStorage>(storage: &mut StorageHolder<CS>, workspace: &str) {
+    let mut accounts: HashMap<String, String> = storage.get_field_json("account_workspaces").unwrap_or_default();
+    accounts.insert(account_id(), workspace.to_string());
+    storage.set_field_json("account_workspaces", &accounts).unwrap();
+}
+
+/// Removes the workspace assigned to the current account in this storage
+pub fn unassign_account_workspace<CS: ConfigStorage>(storage: &mut StorageHolder<CS>) {
+    let mut accounts: HashMap<String, String> = storage.get_field_json("account_workspaces").unwrap_or_default();
+    accounts.remove(&account_id());
+    storage.set_field_json("account_workspaces", &accounts).unwrap();
+}
+
+/// Whether an account already shared its configuration with the household
+fn household_exists() -> bool {
+    ArcStorage::household().storage_path().exists()
+}
+
+/// Stops the current account from following the household, which keeps its configuration for the other accounts.
+/// Takes effect on the next boot.
+pub fn leave_household() {
+    if !household_exists() {
+        return;
+    }
+
+    let mut household = StorageHolder::new(ArcStorage::household());
+    unassign_account_workspace(&mut household);
+    household.flush();
+}
+
+/// Removes the assignments of the accounts to a workspace which was removed, which makes them boot with the default one
+pub fn remove_account_workspace<CS: ConfigStorage>(storage: &mut StorageHolder<CS>, name: &str) {
+    let mut accounts: HashMap<String, String> = storage.get_field_json("account_workspaces").unwrap_or_default();
+    accounts.retain(|_, workspace| workspace.as_str() != name);
+    storage.set_field_json("account_workspaces", &accounts).unwrap();
+}
+
+/// Points the accounts which were assigned a workspace that got renamed to its new name
+pub fn rename_account_workspace<CS: ConfigStorage>(storage: &mut StorageHolder<CS>, source_name: &str, target_name: &str) {
+    let mut accounts: HashMap<String, String> = storage.get_field_json("account_workspaces").unwrap_or_default();
+
+    for workspace in accounts.values_mut().filter(|workspace| workspace.as_str() == source_name) {
+        *workspace = target_name.to_string();
+    }
+
+    storage.set_field_json("account_workspaces", &accounts).unwrap();
+}
+
+/// Makes the current account follow the household, with the provided workspace. The first account to follow the household
+/// shares its configuration with it, the next ones use the configuration of the household. Takes effect on the next boot.
+pub fn follow_household(workspace: &str) -> std::io::Result<()> {
+    let account = ArcStorage::new();
+    let mut household = StorageHolder::new(ArcStorage::household());
+
+    if household.get_field::<String>("version").is_err() {
+        info!("Sharing the configuration of this account with the household.");
+
+        let household_path = ArcStorage::household().storage_path();
+        std::fs::create_dir_all(&household_path)?;
+
+        for entry in std::fs::read_dir(account.storage_path())? {
+            let path = entry?.path();
+
+            if let (true, Some(name)) = (path.is_file(), path.file_name()) {
+                std::fs::copy(&path, household_path.join(name))?;
+            }
+        }
+
+        // The household was created with the storage, so it has to be reopened to see the copied configuration
+        household = StorageHolder::new(ArcStorage::household());
+    }
+
+    assign_account_workspace(&mut household, workspace);
+    household.flush();
+
+    Ok(())
+}
+
+fn convert_legacy_to_presets() -> HashSet<String> {
+    let mut presets: HashSet<String> = HashSet::new();
 
     if umm_path().exists() {
         // TODO: Turn this into a map and use Collect
@@ -168,7 +386,7 @@ fn convert_legacy_to_presets() -> HashSet<Hash40> {
                 .map(|name| !name.starts_with('.'))
                 .unwrap_or(false)
             {
-                presets.insert(Hash40::from(path.to_str().unwrap()));
+                presets.insert(crate::fs::mod_id(path));
             } else {
                 // TODO: Check if the destination already exists, because it'll definitely happen, and when someone opens an issue about it and you'll realize you knew ahead of time, you'll feel dumb. But right this moment, you decided not to do anything.
                 std::fs::rename(path, format!("sd:/ultimate/mods/{}", &path.file_name().unwrap().to_str().unwrap()[1..])).unwrap();
@@ -256,11 +474,37 @@ pub fn beta_updates() -> bool {
     GLOBAL_CONFIG.lock().unwrap().get_flag("beta_updates")
 }
 
+/// Gets the version every console should stay on, if the updater was pinned to one
+pub fn pinned_version() -> Option<String> {
+    let version: String = GLOBAL_CONFIG.lock().unwrap().get_field("pinned_version").unwrap_or_default();
+    let version = version.trim().trim_start_matches('v');
+    (!version.is_empty()).then(|| version.to_string())
+}
+
+/// Gets the versions the updater must never install, such as a release known to be broken
+pub fn ignored_versions() -> Vec<String> {
+    GLOBAL_CONFIG.lock().unwrap().get_field_json("ignored_versions").unwrap_or_default()
+}
+
+/// Whether the updater refuses to install a version older than the current one, even to reach the pinned version
+pub fn never_downgrade() -> bool {
+    GLOBAL_CONFIG.lock().unwrap().get_flag("never_downgrade")
+}
+
+/// Whether the updater installs release archives which do not come with a checksum manifest, without verifying them
+pub fn allow_unverified_updates() -> bool {
+    GLOBAL_CONFIG.lock().unwrap().get_flag("allow_unverified_updates")
+}
+
 pub fn region() -> Region {
     *REGION
 }
 
 pub fn region_str() -> String {
+    if let Some(region) = workspace_settings().region {
+        return region;
+    }
+
     let region: String = GLOBAL_CONFIG
         .lock()
         .unwrap()
@@ -283,11 +527,121 @@ pub fn umm_path() -> PathBuf {
     path
 }
 
+/// Gets the directory backing the writable scratch area of `mods:/`, where plugins can write generated files
+pub fn scratch_path() -> PathBuf {
+    let path: String = GLOBAL_CONFIG
+        .lock()
+        .unwrap()
+        .get_field("scratch_path")
+        .unwrap_or_else(|_| String::from("sd:/ultimate/arcropolis/scratch"));
+    let path = PathBuf::from(path);
+
+    if !path.exists() {
+        let _ = std::fs::create_dir_all(&path);
+    }
+
+    path
+}
+
 pub fn extra_paths() -> Vec<String> {
+    if let Some(extra_paths) = workspace_settings().extra_paths {
+        return extra_paths;
+    }
+
     GLOBAL_CONFIG.lock().unwrap().get_field_json("extra_paths").unwrap_or_default()
 }
 
+pub fn workspace_name() -> String {
+    GLOBAL_CONFIG
+        .lock()
+        .unwrap()
+        .get_field("workspace")
+        .unwrap_or_else(|_| String::from("Default"))
+}
+
+/// Gets the name of the preset of a workspace, which is what its other fields are named after. A workspace which does
+/// not exist uses the default preset.
+pub fn workspace_preset_name<CS: ConfigStorage>(storage: &StorageHolder<CS>, workspace_name: &str) -> String {
+    let workspace_list: HashMap<String, String> = storage.get_field_json("workspace_list").unwrap_or_default();
+    workspace_list.get(workspace_name).cloned().unwrap_or_else(|| String::from("presets"))
+}
+
+/// Gets the name of the preset of the active workspace
+pub fn active_preset_name<CS: ConfigStorage>(storage: &StorageHolder<CS>) -> String {
+    let workspace_name: String = storage.get_field("workspace").unwrap_or_else(|_| "Default".to_string());
+    workspace_preset_name(storage, &workspace_name)
+}
+
+/// Gets the name of the storage field holding the load priority of the mods in a preset
+pub fn priority_field<S: AsRef<str>>(preset_name: S) -> String {
+    format!("{}_priority", preset_name.as_ref())
+}
+
+/// Gets the name of the storage field holding the settings of the workspace using a preset
+pub fn settings_field<S: AsRef<str>>(preset_name: S) -> String {
+    format!("{}_settings", preset_name.as_ref())
+}
+
+/// Gets the name of the storage field holding the `config.json` of the workspace using a preset
+pub fn mod_config_field<S: AsRef<str>>(preset_name: S) -> String {
+    format!("{}_config", preset_name.as_ref())
+}
+
+/// Gets the identity of the mods of the active workspace, from highest to lowest load priority
+pub fn mod_priority() -> Vec<String> {
+    let storage = GLOBAL_CONFIG.lock().unwrap();
+    storage.get_field_json(priority_field(active_preset_name(&storage))).unwrap_or_default()
+}
+
+/// Settings of a workspace which take precedence over the ones of the configuration while it is active
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct WorkspaceSettings {
+    pub region: Option<String>,
+    pub extra_paths: Option<Vec<String>>,
+    pub logging_level: Option<String>,
+}
+
+pub fn workspace_settings() -> WorkspaceSettings {
+    let storage = GLOBAL_CONFIG.lock().unwrap();
+    storage.get_field_json(settings_field(active_preset_name(&storage))).unwrap_or_default()
+}
+
+/// Gets the `config.json` of the workspace using a preset. A workspace without one stores `null`.
+pub fn preset_mod_config<CS: ConfigStorage>(storage: &StorageHolder<CS>, preset_name: &str) -> Option<serde_json::Value> {
+    storage
+        .get_field_json::<serde_json::Value>(mod_config_field(preset_name))
+        .ok()
+        .filter(|mod_config| !mod_config.is_null())
+}
+
+/// Empties every field of a preset whose workspace was removed, so that a workspace which gets the same preset name
+/// later does not inherit its mods or settings
+pub fn clear_preset<CS: ConfigStorage>(storage: &mut StorageHolder<CS>, preset_name: &str) {
+    storage.set_field_json(preset_name, &HashSet::<String>::new()).unwrap();
+    storage.set_field_json(priority_field(preset_name), &Vec::<String>::new()).unwrap();
+    storage
+        .set_field_json(legacy_presets_field(preset_name), &HashSet::<Hash40>::new())
+        .unwrap();
+    storage
+        .set_field_json(settings_field(preset_name), &WorkspaceSettings::default())
+        .unwrap();
+    storage
+        .set_field_json(mod_config_field(preset_name), &serde_json::Value::Null)
+        .unwrap();
+}
+
+/// Gets the `config.json` of the active workspace, which is merged into the ones of its mods
+pub fn workspace_mod_config() -> Option<serde_json::Value> {
+    let storage = GLOBAL_CONFIG.lock().unwrap();
+    preset_mod_config(&storage, &active_preset_name(&storage))
+}
+
 pub fn logger_level() -> String {
+    if let Some(level) = workspace_settings().logging_level {
+        return level;
+    }
+
     let level: String = GLOBAL_CONFIG
         .lock()
         .unwrap()
@@ -300,35 +654,103 @@ pub fn file_logging_enabled() -> bool {
     GLOBAL_CONFIG.lock().unwrap().get_flag("log_to_file")
 }
 
+/// Whether every log record is also written as a line of JSON, next to the regular log file
+pub fn json_logging_enabled() -> bool {
+    GLOBAL_CONFIG.lock().unwrap().get_flag("log_to_json")
+}
+
+/// Gets how many log files of each kind are kept in the log folder, the oldest ones being deleted first
+pub fn log_retention() -> usize {
+    GLOBAL_CONFIG.lock().unwrap().get_field("log_retention").unwrap_or(10)
+}
+
+/// Gets how big a log file can grow, in bytes, before the logger moves on to a new one
+pub fn log_max_size() -> usize {
+    let megabytes: usize = GLOBAL_CONFIG.lock().unwrap().get_field("log_max_size").unwrap_or(8);
+    megabytes * 0x10_0000
+}
+
+/// Gets the logging level of specific modules, such as `arcropolis::fs` => `Trace`, which take precedence over the logging level
+pub fn module_log_levels() -> HashMap<String, String> {
+    GLOBAL_CONFIG.lock().unwrap().get_field_json("module_log_levels").unwrap_or_default()
+}
+
 pub fn legacy_discovery() -> bool {
     GLOBAL_CONFIG.lock().unwrap().get_flag("legacy_discovery")
 }
 
-pub struct ArcStorage(std::path::PathBuf);
+/// Whether PRC patches from different mods are refused when they edit the same param
+pub fn strict_param_patches() -> bool {
+    GLOBAL_CONFIG.lock().unwrap().get_flag("strict_param_patches")
+}
 
-impl ArcStorage {
-    pub fn new() -> Self {
-        let mut uid = nn::account::Uid { id: [0; 2] };
-        let mut handle = UserHandle::new();
+/// The default of `size_patch_budget`, in megabytes.
+///
+/// Neither this nor `DEFAULT_DIRECTORY_SIZE_PATCH_BUDGET` comes from a measurement: how much of the resource heap the game
+/// leaves free depends on the stage, the fighters and the menus loaded, and has not been profiled. They are margins
+/// picked to flag setups that grow the game by hundreds of megabytes, not limits past which it is known to crash.
+/// Users whose setup loads fine over them, or crashes under them, can tune both fields with the extra memory listed
+/// for every directory in the size patch report.
+const DEFAULT_SIZE_PATCH_BUDGET: usize = 256;
+
+/// The default of `directory_size_patch_budget`, in megabytes. A directory is loaded as a whole, so it gets a smaller
+/// share of the total margin.
+const DEFAULT_DIRECTORY_SIZE_PATCH_BUDGET: usize = 32;
+
+/// Gets how much memory, in bytes, the size patches of every file can demand before the user is warned
+pub fn size_patch_budget() -> usize {
+    let megabytes: usize = GLOBAL_CONFIG.lock().unwrap().get_field("size_patch_budget").unwrap_or(DEFAULT_SIZE_PATCH_BUDGET);
+    megabytes * 0x10_0000
+}
 
-        unsafe {
-            // It is safe to initialize multiple times.
-            nn::account::Initialize();
+/// Gets how much memory, in bytes, the size patches of the files of a single directory can demand before the user is warned
+pub fn directory_size_patch_budget() -> usize {
+    let megabytes: usize = GLOBAL_CONFIG
+        .lock()
+        .unwrap()
+        .get_field("directory_size_patch_budget")
+        .unwrap_or(DEFAULT_DIRECTORY_SIZE_PATCH_BUDGET);
+    megabytes * 0x10_0000
+}
 
-            // This provides a UserHandle and sets the User in a Open state to be used.
-            if !open_preselected_user(&mut handle) {
-                panic!("OpenPreselectedUser returned false");
-            }
+/// Gets the path of the storage of the preselected account, relative to the root of the configuration
+fn account_path() -> PathBuf {
+    let mut uid = nn::account::Uid { id: [0; 2] };
+    let mut handle = UserHandle::new();
 
-            // Obtain the UID for this user
-            get_user_id(&mut uid, &handle);
-            // This closes the UserHandle, making it unusable, and sets the User in a Closed state.
-            close_user(&handle);
+    unsafe {
+        // It is safe to initialize multiple times.
+        nn::account::Initialize();
+
+        // This provides a UserHandle and sets the User in a Open state to be used.
+        if !open_preselected_user(&mut handle) {
+            panic!("OpenPreselectedUser returned false");
         }
 
-        let path = PathBuf::from(uid.id[0].to_string()).join(uid.id[1].to_string());
+        // Obtain the UID for this user
+        get_user_id(&mut uid, &handle);
+        // This closes the UserHandle, making it unusable, and sets the User in a Closed state.
+        close_user(&handle);
+    }
+
+    PathBuf::from(uid.id[0].to_string()).join(uid.id[1].to_string())
+}
+
+/// Identifies the preselected account in the workspace assignments of the household
+pub fn account_id() -> String {
+    account_path().to_string_lossy().into_owned()
+}
+
+pub struct ArcStorage(std::path::PathBuf);
 
-        Self(path)
+impl ArcStorage {
+    pub fn new() -> Self {
+        Self(account_path())
+    }
+
+    /// The storage shared by the accounts which follow the household
+    pub fn household() -> Self {
+        Self(PathBuf::from("household"))
     }
 }
 
@@ -352,3 +774,36 @@ impl ConfigStorage for ArcStorage {
         self.root_path().join(&self.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifies_installed_legacy_mods() {
+        let ids: HashMap<Hash40, String> = vec![
+            (Hash40::from("sd:/ultimate/mods/Cool Mario"), String::from("cool-mario")),
+            (Hash40::from("sd:/ultimate/mods/Stages"), String::from("Stages")),
+        ]
+        .into_iter()
+        .collect();
+
+        let hashes: HashSet<Hash40> =
+            ["sd:/ultimate/mods/Cool Mario", "sd:/ultimate/mods/Removed"].iter().map(|path| Hash40::from(*path)).collect();
+        let (found, missing) = identify_legacy_mods(hashes, &ids);
+
+        assert_eq!(found, vec![String::from("cool-mario")]);
+        assert_eq!(missing, std::iter::once(Hash40::from("sd:/ultimate/mods/Removed")).collect::<HashSet<Hash40>>());
+    }
+
+    #[test]
+    fn nothing_is_left_once_every_mod_is_installed() {
+        let ids: HashMap<Hash40, String> = std::iter::once((Hash40::from("sd:/ultimate/mods/Stages"), String::from("Stages"))).collect();
+
+        let (found, missing) = identify_legacy_mods(ids.keys().copied().collect(), &ids);
+
+        assert_eq!(found, vec![String::from("Stages")]);
+        assert!(missing.is_empty());
+        assert_eq!(legacy_presets_field("presets"), "presets_legacy");
+    }
+}
@@ -6,11 +6,12 @@ use std::{
     ops::Deref,
     path::{Path, PathBuf},
     sync::atomic::{AtomicBool, Ordering},
+    time::SystemTime,
 };
 
-use orbits::{orbit::LaunchPad, Error, FileEntryType, FileLoader, Orbit, StandardLoader, Tree};
+use orbits::{Error, FileEntryType, FileLoader, Orbit, Tree};
 use owo_colors::OwoColorize;
-use smash_arc::{serde::Hash40String, ArcLookup, Hash40, LoadedArc, LoadedSearchSection, LookupError, SearchLookup};
+use smash_arc::{ArcLookup, Hash40, LoadedArc, LoadedSearchSection, LookupError, SearchLookup};
 use thiserror::Error;
 
 // pub mod api;
@@ -21,9 +22,20 @@ use crate::{
     resource, PathExtension,
 };
 
+pub mod archive;
+mod budget;
+mod cache;
+mod conflicts;
+mod dependencies;
 mod discover;
+pub mod pipeline;
+mod reload;
 mod utils;
+pub use budget::*;
+pub use conflicts::*;
+pub use dependencies::*;
 pub use discover::*;
+pub use reload::*;
 pub mod loaders;
 pub use loaders::*;
 
@@ -31,7 +43,7 @@ static DEFAULT_CONFIG: &str = include_str!("../resources/override.json");
 static IS_INIT: AtomicBool = AtomicBool::new(false);
 // pub type ApiLoader = StandardLoader; // temporary until an actual ApiLoader is implemented
 
-pub type ArcropolisOrbit = Orbit<ArcLoader, StandardLoader, ApiLoader>;
+pub type ArcropolisOrbit = Orbit<ArcLoader, archive::ModLoader, ApiLoader>;
 
 pub struct FilesystemUninitializedError;
 
@@ -46,18 +58,25 @@ pub struct CachedFilesystem {
     config: replacement::config::ModConfig,
     hash_lookup: HashMap<Hash40, PathBuf>,
     hash_size_cache: HashMap<Hash40, usize>,
+    /// The size files had in the data.arc before their size was patched
+    patched_sizes: HashMap<Hash40, usize>,
     incoming_load: Option<Hash40>,
     bytes_remaining: usize,
     current_nus3bank_id: u32,
     nus3banks: HashMap<Hash40, u32>,
+    roots: Vec<PathBuf>,
+    last_reload: SystemTime,
 }
 
 impl CachedFilesystem {
-    /// Load all configs that were found during discovery and join them into a singular config
-    fn load_remaining_configs(current: &mut ModConfig, launchpad: &LaunchPad<StandardLoader>) {
-        for (root, local) in launchpad.collected_paths().iter() {
+    /// Load all configs that were found during discovery, and the one of the active workspace, and join them into a singular config
+    fn load_remaining_configs(current: &mut ModConfig, collected: &[(PathBuf, PathBuf)]) {
+        // The configs which pair extensions for 'preprocess-reshare', reported if no config has any directories to reshare
+        let mut reshare_ext_sources = Vec::new();
+
+        for (root, local) in collected.iter() {
             let full_path = root.join(local);
-            if !full_path.exists() {
+            if !archive::mod_file_exists(&full_path) {
                 warn!("Collected path at {} does not exist.", full_path.display());
                 continue;
             }
@@ -68,22 +87,70 @@ impl CachedFilesystem {
             }
 
             // Read the file data and map it to a json. If that fails, just skip this current JSON.
-            let cfg = std::fs::read_to_string(&full_path)
+            let value = archive::read_mod_file(&full_path)
                 .ok()
-                .and_then(|x| serde_json::from_str::<ModConfig>(x.as_str()).ok());
+                .and_then(|x| serde_json::from_slice::<serde_json::Value>(&x).ok());
 
-            if let Some(cfg) = cfg {
-                current.merge(cfg);
+            let value = if let Some(value) = value {
+                value
             } else {
                 warn!("Could not read/parse JSON data from file {}", full_path.display());
+                continue;
+            };
+
+            let source = format!("the config of mod root '{}'", root.display());
+
+            if Self::merge_config(current, value, &source) {
+                reshare_ext_sources.push(source);
+            }
+        }
+
+        // The active workspace can add its own config on top of the ones of its mods
+        if let Some(value) = config::workspace_mod_config() {
+            let source = String::from("the config of the workspace");
+
+            if Self::merge_config(current, value, &source) {
+                reshare_ext_sources.push(source);
+            }
+        }
+
+        if current.preprocess_reshare.is_empty() {
+            for source in reshare_ext_sources {
+                warn!(
+                    "The 'preprocess-reshare-ext' key in {} has no effect without any 'preprocess-reshare' directories and will be ignored.",
+                    source
+                );
             }
         }
     }
 
-    /// Get a list of all PRC patch files and add them to the virtual tree
-    fn initialize_prc_patches(launchpad: &LaunchPad<StandardLoader>, api_tree: &mut Tree<ApiLoader>) -> HashSet<Hash40> {
-        let mut set = HashSet::new();
-        for (root, path) in launchpad.collected_paths().iter() {
+    /// Merges a raw config.json into the current config, and returns whether it has any 'preprocess-reshare-ext' pairs
+    fn merge_config(current: &mut ModConfig, value: serde_json::Value, source: &str) -> bool {
+        for key in ModConfig::unknown_keys(&value) {
+            warn!("Unknown key '{}' in {} will be ignored.", key, source);
+        }
+
+        match serde_json::from_value::<ModConfig>(value) {
+            Ok(cfg) => {
+                let has_reshare_ext = !cfg.preprocess_reshare_ext.is_empty();
+                current.merge(cfg);
+                has_reshare_ext
+            },
+            Err(e) => {
+                warn!("Could not parse JSON data from {}. Reason: {:?}", source, e);
+                false
+            },
+        }
+    }
+
+    /// Get a list of all PRC patch files and add them to the virtual tree, in the order of the mod priority
+    fn initialize_prc_patches(collected: &[(PathBuf, PathBuf)], api_tree: &mut Tree<ApiLoader>) -> HashSet<Hash40> {
+        // Roots which were never given a priority come after the others, like they do during discovery
+        let priority = config::mod_priority();
+        let mut ranks: HashMap<&Path, usize> = HashMap::new();
+        let mut patches: Vec<(usize, &Path, &Path)> = Vec::new();
+
+        for (root, path) in collected.iter() {
             // The collected paths gives us everything so we only want these extensions
             if path.has_extension("prcx")
                 || path.has_extension("prcxml")
@@ -92,18 +159,68 @@ impl CachedFilesystem {
                 || path.has_extension("stprmx")
                 || path.has_extension("stprmxml")
             {
-                if let Some(hash) = utils::add_prc_patch(api_tree, root, path) {
-                    set.insert(hash);
-                }
+                let rank = *ranks.entry(root.as_path()).or_insert_with(|| {
+                    let id = cached_mod_id(root);
+                    priority.iter().position(|x| *x == id).unwrap_or(priority.len())
+                });
+
+                patches.push((rank, root, path));
+            }
+        }
+
+        patches.sort();
+
+        let strict = config::strict_param_patches();
+        let mut report = ParamConflictReport::default();
+        let mut accepted = Vec::new();
+
+        for (_, root, path) in patches.into_iter() {
+            let keys = match ApiLoader::open_prc_patch(&root.join(path)) {
+                Ok(patch) => ApiLoader::prc_patch_keys(&patch),
+                Err(e) => {
+                    error!("Failed to read param patch '{}'. Reason: {:?}", root.join(path).display(), e);
+                    Vec::new()
+                },
+            };
+
+            let (applied, overlapping) = report.add_patch(pipeline::patch_target(path).unwrap_or_else(|| path.to_path_buf()), root, &keys, strict);
+
+            if !applied {
+                error!(
+                    "Param patch '{}' was refused because it edits the same params as {:?}.",
+                    root.join(path).display(),
+                    overlapping
+                );
+                continue;
+            }
+
+            if !overlapping.is_empty() {
+                warn!(
+                    "Param patch '{}' edits the same params as {:?}, which take priority over it.",
+                    root.join(path).display(),
+                    overlapping
+                );
+            }
+
+            accepted.push((root, path));
+        }
+
+        report.save();
+
+        // The patches are inserted from lowest to highest priority so that the patches with the highest priority are applied last
+        let mut set = HashSet::new();
+        for (root, path) in accepted.into_iter().rev() {
+            if let Some(hash) = utils::add_prc_patch(api_tree, root, path) {
+                set.insert(hash);
             }
         }
         set
     }
 
     /// Get a list of all MSBT patch files and add them to the virtual tree
-    fn initialize_msbt_patches(launchpad: &LaunchPad<StandardLoader>, api_tree: &mut Tree<ApiLoader>) -> HashSet<Hash40> {
+    fn initialize_msbt_patches(collected: &[(PathBuf, PathBuf)], api_tree: &mut Tree<ApiLoader>) -> HashSet<Hash40> {
         let mut set = HashSet::new();
-        for (root, path) in launchpad.collected_paths().iter() {
+        for (root, path) in collected.iter() {
             // The collected paths gives us everything so we only want these extensions
             if path.has_extension("xmsbt") {
                 if let Some(hash) = utils::add_msbt_patch(api_tree, root, path) {
@@ -116,7 +233,7 @@ impl CachedFilesystem {
 
     /// Parse a pending API call and add it to the API tree. This function returns the hash, as well as the size (if needed)
     /// so that the caller can insert those into the global structs depending on the time that this call is handled
-    fn handle_panding_api_call(api_tree: &mut Tree<ApiLoader>, pending: api::PendingApiCall) -> ApiCallResult {
+    fn handle_panding_api_call(api_tree: &mut Tree<ApiLoader>, pending: api::PendingApiCall) -> Vec<ApiCallResult> {
         use api::PendingApiCall;
 
         match pending {
@@ -125,29 +242,50 @@ impl CachedFilesystem {
 
                 utils::add_file_to_api_tree(api_tree, "api:/generic-cb", &path, ApiCallback::GenericCallback(callback));
 
-                ApiCallResult {
+                vec![ApiCallResult {
                     hash,
                     path,
                     size: Some(max_size),
-                }
+                }]
             },
             PendingApiCall::StreamCallback { hash, callback } => {
                 let path = get_path_from_hash(hash);
 
                 utils::add_file_to_api_tree(api_tree, "api:/stream-cb", &path, ApiCallback::StreamCallback(callback));
 
-                ApiCallResult { hash, path, size: None }
+                vec![ApiCallResult { hash, path, size: None }]
             },
+            PendingApiCall::ExtensionCallback { ext, max_size, callback } => {
+                // Every file of the game with the extension is routed through the callback
+                replacement::lookup::files_with_extension(ext)
+                    .iter()
+                    .map(|&hash| {
+                        let path = get_path_from_hash(hash);
+
+                        utils::add_file_to_api_tree(api_tree, "api:/extension-cb", &path, ApiCallback::ExtensionCallback(callback));
+
+                        ApiCallResult { hash, path, size: max_size }
+                    })
+                    .collect()
+            },
+            // The scratch area is discovered with the mods, so there is nothing to refresh before the filesystem exists
+            PendingApiCall::ScratchRefresh => Vec::new(),
         }
     }
 
     /// Use the file information that was generated during file discovery to fill out a GlobalFilesystem struct
-    fn make_from_promise(launchpad: LaunchPad<StandardLoader>) -> CachedFilesystem {
+    fn make_from_promise(discovery: Discovery) -> CachedFilesystem {
         let arc = resource::arc();
-        // Provide the discovered tree and get two hashmaps, one of the sizes of each file discovered (for patching)
-        // and also get hash40 -> PathBuf lookup, since it's going to be a lot faster when the game is loading
+        // Discovery provides two hashmaps, one of the sizes of each file discovered (for patching)
+        // and also a hash40 -> PathBuf lookup, since it's going to be a lot faster when the game is loading
         // individual files
-        let (mut hashed_sizes, mut hashed_paths) = utils::make_hash_maps(launchpad.tree());
+        let Discovery {
+            launchpad,
+            collected,
+            mut hashed_sizes,
+            mut hashed_paths,
+            roots,
+        } = discovery;
 
         // Add the discovered paths to the global hashes, so that when a file is loading that *we have discovered* we can guarantee
         // that we are printing the real path in the logger.
@@ -167,25 +305,26 @@ impl CachedFilesystem {
         };
 
         // Load all of the user configs into the main config
-        Self::load_remaining_configs(&mut config, &launchpad);
+        Self::load_remaining_configs(&mut config, &collected);
 
         // Collect all of the NUS3BANK dependencies that audio files have in order to be unshared
         // Note that we pass the unshare blacklist because if the NUS3AUDIO files are blacklisted then we shouldn't unshare the
         // actual nus3bank either
-        let nus3audio_deps = utils::get_required_nus3banks(launchpad.tree(), &config.unshare_blacklist);
+        let nus3audio_deps = pipeline::required_nus3banks(launchpad.tree(), &config.unshare_blacklist);
 
         // Create the API file tree and start adding things to it
         let mut api_tree = Tree::new(ApiLoader::default());
 
         // Set up the API tree with prc patch files (soon to be more)
-        let mut hashes = Self::initialize_prc_patches(&launchpad, &mut api_tree);
-        hashes.extend(Self::initialize_msbt_patches(&launchpad, &mut api_tree));
+        let mut hashes = Self::initialize_prc_patches(&collected, &mut api_tree);
+        hashes.extend(Self::initialize_msbt_patches(&collected, &mut api_tree));
 
-        // Add the hash files and set the new size to 10x the original files
+        // Add the hash files, whose size is measured from the patched output once the filesystem is ready
         for hash in hashes {
             if let Ok(data) = arc.get_file_data_from_hash(hash, config::region()) {
                 hashed_paths.insert(hash, get_path_from_hash(hash));
-                hashed_sizes.insert(hash, (data.decomp_size as usize) * 10);
+                let size = hashed_sizes.entry(hash).or_default();
+                *size = (*size).max(data.decomp_size as usize);
             }
         }
 
@@ -206,11 +345,13 @@ impl CachedFilesystem {
 
         // Go through each API call, insert it into the api tree, and then insert it's info into the global data
         for call in calls {
-            let ApiCallResult { hash, path, size } = Self::handle_panding_api_call(&mut api_tree, call);
-
-            hashed_paths.insert(hash, path);
-            if let Some(size) = size {
-                hashed_sizes.insert(hash, size);
+            for ApiCallResult { hash, path, size } in Self::handle_panding_api_call(&mut api_tree, call) {
+                hashed_paths.insert(hash, path);
+                // A callback only makes a file bigger, the file it is given by the layer beneath it has to fit as well
+                if let Some(size) = size {
+                    let entry = hashed_sizes.entry(hash).or_default();
+                    *entry = (*entry).max(size);
+                }
             }
         }
 
@@ -223,10 +364,13 @@ impl CachedFilesystem {
             config,
             hash_lookup: hashed_paths,
             hash_size_cache: hashed_sizes,
+            patched_sizes: HashMap::new(),
             incoming_load: None,
             bytes_remaining: 0,
             current_nus3bank_id: 7420,
             nus3banks: HashMap::new(),
+            roots,
+            last_reload: SystemTime::now(),
         }
     }
 
@@ -282,6 +426,7 @@ impl CachedFilesystem {
             path
         } else {
             error!(
+                hash = hash.0;
                 "Failed to load data for '{}' ({:#x}) because the filesystem does not contain it!",
                 hashes::find(hash),
                 hash.0
@@ -293,16 +438,23 @@ impl CachedFilesystem {
             Ok(data) => Some(data),
             Err(Error::Virtual(ApiLoaderError::NoVirtFile)) => {
                 if let Ok(data) = self.loader.load_patch(path) {
-                    Some(data)
+                    // Mod files can be compressed to save space on the SD card
+                    match utils::decompress_if_compressed(path, data) {
+                        Ok(data) => Some(data),
+                        Err(e) => {
+                            error!(hash = hash.0, path:% = path.display(); "Failed to decompress {}. Reason: {:?}", path.display(), e);
+                            None
+                        },
+                    }
                 } else if let Ok(data) = ArcLoader(resource::arc()).load_path(Path::new(""), path) {
                     Some(data)
                 } else {
-                    error!("Failed to load data for {} because all load paths failed.", path.display());
+                    error!(hash = hash.0, path:% = path.display(); "Failed to load data for {} because all load paths failed.", path.display());
                     None
                 }
             },
             Err(e) => {
-                error!("Failed to load data for {}. Reason: {:?}", path.display(), e);
+                error!(hash = hash.0, path:% = path.display(); "Failed to load data for {}. Reason: {:?}", path.display(), e);
                 None
             },
         }
@@ -313,6 +465,7 @@ impl CachedFilesystem {
         if let Some(data) = self.load(hash) {
             if buffer.len() < data.len() {
                 error!(
+                    hash = hash.0;
                     "The size of the file data is larger than the size of the provided buffer when loading file '{}' ({:#x}).",
                     hashes::find(hash),
                     hash.0
@@ -361,16 +514,77 @@ impl CachedFilesystem {
         }
     }
 
+    /// Measures the files generated by PRC and MSBT patches, so that their size is patched to exactly what they need.
+    /// Patching is slow, so the sizes measured on the previous boots are used for the files whose patches did not change.
+    fn measure_patched_files(&mut self) {
+        let arc = resource::arc();
+        let api = &self.loader.virt().loader;
+        let targets: HashSet<Hash40> = api.patched_files().collect();
+        let mut cache = cache::PatchedSizeCache::load();
+        cache.retain(&targets);
+
+        for hash in targets {
+            // The size depends on the patches and on the file they are applied to, unless a plugin also edits the file
+            let base = self.hash_lookup.get(&hash).and_then(|local| self.loader.patch().query_actual_path(local));
+            let sources = if api.has_callback(hash) {
+                None
+            } else {
+                cache::patch_sources(api.patches_for(hash).chain(base.as_ref()).map(PathBuf::as_path))
+            };
+
+            let cached = sources.as_ref().and_then(|sources| cache.get(hash, sources));
+
+            let size = match cached.or_else(|| self.load(hash).map(|data| data.len())) {
+                Some(size) => {
+                    if let (Some(sources), None) = (sources, cached) {
+                        cache.insert(hash, sources, size);
+                    }
+                    size
+                },
+                None => {
+                    // The patches will fail the same way when the game loads the file, this only leaves room for them in case they don't
+                    warn!(
+                        "Failed to measure patched file '{}' ({:#x}), its size will be patched to ten times its original size.",
+                        hashes::find(hash),
+                        hash.0
+                    );
+                    arc.get_file_data_from_hash(hash, config::region()).map_or(0, |data| data.decomp_size as usize * 10)
+                },
+            };
+
+            let cached = self.hash_size_cache.entry(hash).or_default();
+            *cached = (*cached).max(size);
+        }
+
+        cache.save();
+    }
+
     // Patch all files in the hash size cache
     pub fn patch_files(&mut self) {
+        self.measure_patched_files();
+
         let mut hash_cache = HashMap::new();
+        let mut patches = Vec::new();
         std::mem::swap(&mut hash_cache, &mut self.hash_size_cache);
         for (hash, size) in hash_cache.iter_mut() {
             if let Some(old_size) = self.patch_file(*hash, *size) {
+                patches.push((*hash, old_size, *size));
+                self.patched_sizes.insert(*hash, old_size);
                 *size = old_size;
             }
         }
         self.hash_size_cache = hash_cache;
+
+        let report = SizePatchReport::new(patches);
+        report.save();
+
+        if report.is_over_budget() {
+            warn!("Size patches demand {:#x} extra bytes, see {} for the details.", report.total_extra, SIZE_PATCHES_PATH);
+
+            if !discover::is_emulator() {
+                skyline_web::DialogOk::ok(report.warning());
+            }
+        }
     }
 
     // Reshares all hashes that still need to be shared, so that we don't get fake one-slot behavior
@@ -401,53 +615,57 @@ impl CachedFilesystem {
         // This is mostly used for Dark Samus because of her victory bunshin article
         for (dep, source) in self.config.preprocess_reshare.iter() {
             hash_ignore.extend(replacement::preprocess::reshare_contained_files(&mut context, dep.0, source.0).into_iter());
+
+            // Some files share their data with several files in the source directory, so point them at the one with the right extension
+            for (dep_ext, source_ext) in self.config.preprocess_reshare_ext.iter() {
+                hash_ignore.extend(
+                    replacement::preprocess::reshare_contained_files_by_ext(&mut context, dep.0, source.0, dep_ext.0, source_ext.0).into_iter(),
+                );
+            }
         }
 
-        // Go through and add any files that were not found in the data.arc
+        let mut locals = Vec::new();
         self.loader.walk_patch(|node, ty| {
-            if node.get_local().is_stream() || !ty.is_file() {
-                return;
+            if ty.is_file() {
+                locals.push(node.get_local().to_path_buf());
             }
-
-            let _hash = if let Ok(hash) = node.get_local().smash_hash() {
-                if context.contains_file(hash) {
-                    return;
-                }
-                hash
-            } else {
-                return;
-            };
-
-            replacement::addition::add_file(&mut context, node.get_local());
-            replacement::addition::add_searchable_file_recursive(&mut search_context, node.get_local());
         });
 
-        // Don't unshare any files in the unshare blacklist (nus3audio handled during filesystem finish)
-        let files = self.hash_lookup.iter().filter_map(
-            |(hash, _path)| {
-                if self.config.unshare_blacklist.contains(&Hash40String(*hash)) {
-                    None
-                } else {
-                    Some(*hash)
-                }
-            },
+        let plan = pipeline::TablePlan::new(
+            &ConsolePlatform,
+            &self.config,
+            &locals,
+            self.hash_lookup.keys().copied(),
+            |hash| self.hash_lookup.get(&hash).cloned().or_else(|| hashes::try_find(hash).map(PathBuf::from)),
+            replacement::lookup::is_shared_file,
         );
 
-        for (hash, pathset) in self.config.new_shared_files.iter() {
-            for path in pathset.iter() {
-                replacement::addition::add_shared_file(&mut context, path, hash.0);
-                replacement::addition::add_searchable_file_recursive(&mut search_context, path);
-            }
+        for hash in plan.unknown_files.iter() {
+            warn!(
+                "Cannot add new file ({:#x}) because its path is unknown. Add it to hashes.txt or to a mod folder.",
+                hash.0
+            );
+        }
+
+        // Go through and add any files that were not found in the data.arc
+        for path in plan.added_files.iter() {
+            replacement::addition::add_file(&mut context, path);
+            replacement::addition::add_searchable_file_recursive(&mut search_context, path);
+        }
+
+        for (path, hash) in plan.shared_files.iter() {
+            replacement::addition::add_shared_file(&mut context, path, *hash);
+            replacement::addition::add_searchable_file_recursive(&mut search_context, path);
         }
 
         // Reshare any files that depend on files in file groups, as we need to get rid of those else we crash.
         replacement::unshare::reshare_file_groups(&mut context);
 
-        replacement::unshare::unshare_files(&mut context, hash_ignore, files);
+        replacement::unshare::unshare_files(&mut context, hash_ignore, plan.unshared_files.into_iter());
 
-        // Add new files to the dir infos
-        for (hash, files) in self.config.new_dir_files.iter() {
-            replacement::addition::add_files_to_directory(&mut context, hash.0, files.iter().map(|x| x.0).collect());
+        // Add new files to the dir infos, and the new files from the configs to the directories that depend on them
+        for (hash, files) in plan.directory_additions {
+            replacement::addition::add_files_to_directory(&mut context, hash, files);
         }
 
         resource::arc_mut().take_context(context);
@@ -461,22 +679,81 @@ impl CachedFilesystem {
 
     /// Handles late API calls
     pub fn handle_late_api_call(&mut self, call: api::PendingApiCall) {
-        let ApiCallResult { hash, path, size } = Self::handle_panding_api_call(self.loader.virt_mut(), call);
-
-        self.hash_lookup.insert(hash, path);
-        if let Some(size) = size {
-            if let Some(old_size) = self.patch_file(hash, size) {
-                if let Some(size_mut) = self.hash_size_cache.get_mut(&hash) {
-                    if *size_mut > old_size {
-                        *size_mut = old_size;
+        let results = match call {
+            api::PendingApiCall::ScratchRefresh => self.refresh_scratch(),
+            call => Self::handle_panding_api_call(self.loader.virt_mut(), call),
+        };
+
+        for ApiCallResult { hash, path, size } in results {
+            self.hash_lookup.insert(hash, path);
+            if let Some(size) = size {
+                if let Some(old_size) = self.patch_file(hash, size) {
+                    self.patched_sizes.entry(hash).or_insert(old_size);
+
+                    if let Some(size_mut) = self.hash_size_cache.get_mut(&hash) {
+                        if *size_mut > old_size {
+                            *size_mut = old_size;
+                        }
+                    } else {
+                        self.hash_size_cache.insert(hash, size);
                     }
-                } else {
-                    self.hash_size_cache.insert(hash, size);
                 }
             }
         }
     }
 
+    /// Adds the files written to the scratch area since it was last read to the filesystem
+    fn refresh_scratch(&mut self) -> Vec<ApiCallResult> {
+        let scratch = config::scratch_path();
+        let arc = resource::arc();
+        let mut results = Vec::new();
+
+        for entry in walkdir::WalkDir::new(&scratch).into_iter().filter_map(Result::ok) {
+            if !entry.file_type().is_file() {
+                continue;
+            }
+
+            let local = match entry.path().strip_prefix(&scratch) {
+                Ok(local) => local.to_path_buf(),
+                Err(_) => continue,
+            };
+
+            let hash = match local.smash_hash() {
+                Ok(hash) => hash,
+                Err(e) => {
+                    error!("Failed to get hash for scratch file {}. Reason: {:?}", local.display(), e);
+                    continue;
+                },
+            };
+
+            // Files which were already known are read from the SD card on every load, so only new ones are of interest
+            if self.hash_lookup.contains_key(&hash) {
+                continue;
+            }
+
+            if arc.get_file_path_index_from_hash(hash).is_err() {
+                warn!("Scratch file {} is not a file of the game, it will be added on the next boot.", local.display());
+                continue;
+            }
+
+            let size = entry.metadata().map_or(0, |metadata| metadata.len() as usize);
+
+            self.loader.patch_mut().insert_file(&scratch, &local);
+
+            if let Some(string) = local.to_str() {
+                hashes::add(string);
+            }
+
+            results.push(ApiCallResult {
+                hash,
+                path: local,
+                size: Some(size),
+            });
+        }
+
+        results
+    }
+
     /// Gets the cached size
     pub fn get_cached_size(&self, hash: Hash40) -> Option<usize> {
         self.hash_size_cache.get(&hash).copied()
@@ -485,7 +762,7 @@ impl CachedFilesystem {
 
 pub enum GlobalFilesystem {
     Uninitialized,
-    Promised(std::thread::JoinHandle<LaunchPad<StandardLoader>>),
+    Promised(std::thread::JoinHandle<Discovery>),
     Initialized(Box<CachedFilesystem>),
 }
 
@@ -500,7 +777,7 @@ impl GlobalFilesystem {
         match self {
             Self::Uninitialized => Err(FilesystemUninitializedError),
             Self::Promised(promise) => match promise.join() {
-                Ok(launchpad) => Ok(Self::Initialized(Box::new(CachedFilesystem::make_from_promise(launchpad)))),
+                Ok(discovery) => Ok(Self::Initialized(Box::new(CachedFilesystem::make_from_promise(discovery)))),
                 Err(_) => Err(FilesystemUninitializedError),
             },
             Self::Initialized(filesystem) => Ok(Self::Initialized(filesystem)),
@@ -550,6 +827,7 @@ impl GlobalFilesystem {
             Self::Initialized(fs) => fs.load_into(hash, buffer),
             _ => {
                 error!(
+                    hash = hash.0;
                     "Cannot load data for '{}' ({:#x}) because the filesystem is not initialized!",
                     hashes::find(hash),
                     hash.0
@@ -564,6 +842,7 @@ impl GlobalFilesystem {
             Self::Initialized(fs) => fs.load(hash),
             _ => {
                 error!(
+                    hash = hash.0;
                     "Cannot load data for '{}' ({:#x}) because the filesystem is not initialized!",
                     hashes::find(hash),
                     hash.0
@@ -662,4 +941,14 @@ impl GlobalFilesystem {
             _ => None,
         }
     }
+
+    pub fn reload_mods(&mut self) -> Option<ReloadReport> {
+        match self {
+            Self::Initialized(fs) => Some(fs.reload_mods()),
+            _ => {
+                error!("Cannot reload the mods because the filesystem is not initialized!");
+                None
+            },
+        }
+    }
 }
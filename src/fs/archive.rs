@@ -0,0 +1,419 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use once_cell::sync::Lazy;
+use orbits::{ConflictKind, FileEntryType, FileLoader, LaunchPad, StandardLoader};
+use parking_lot::{Mutex, RwLock};
+use zip::ZipArchive;
+
+use super::cache;
+pub use super::pipeline::is_archive;
+use crate::PathExtension;
+
+/// Every archive that was opened, so that the files of a mod are indexed once and read without looking for them again
+static ARCHIVES: Lazy<RwLock<HashMap<PathBuf, Arc<ModArchive>>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// The mod roots which are archives, registered when the mods are listed so that finding the archive of a file does not
+/// have to look at the SD card
+static ARCHIVE_ROOTS: Lazy<RwLock<HashSet<PathBuf>>> = Lazy::new(|| RwLock::new(HashSet::new()));
+
+/// The top-level directories of the data.arc, which a mod can have as its only directory without being wrapped in a folder
+static GAME_DIRECTORIES: &[&str] = &[
+    "assist",
+    "boss",
+    "camera",
+    "campaign",
+    "common",
+    "effect",
+    "enemy",
+    "fighter",
+    "finalsmash",
+    "item",
+    "miihat",
+    "param",
+    "pokemon",
+    "prebuilt",
+    "render",
+    "snapshot",
+    "sound",
+    "spirits",
+    "stage",
+    "standard",
+    "stream",
+    "ui",
+];
+
+/// A mod which is loaded straight from a zip archive, instead of being extracted to a folder
+pub struct ModArchive {
+    root: PathBuf,
+    /// The opened readers of the archive, each used by one read at a time so that files of the same archive can be read
+    /// from several threads. A reader is only opened again when every other one is in use.
+    readers: Mutex<Vec<ZipArchive<File>>>,
+    /// The index of every file in the archive and its decompressed size, keyed by local path
+    files: HashMap<PathBuf, (usize, usize)>,
+    /// The modification time and size of the archive when it was opened, to notice when it is replaced
+    stamp: Option<(SystemTime, u64)>,
+}
+
+/// Gets what tells apart two versions of an archive without reading it
+fn archive_stamp(root: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(root).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Gets the folder every file of an archive is in, when the mod was zipped along with the folder it was in
+fn wrapping_directory(files: &HashMap<PathBuf, (usize, usize)>) -> Option<PathBuf> {
+    let mut tops = files.keys().map(|local| local.components().next());
+    let top = tops.next()??;
+
+    if !tops.all(|other| other == Some(top)) {
+        return None;
+    }
+
+    // A directory of the game such as 'fighter' or 'stream;' is part of the paths of the mod
+    let name = top.as_os_str().to_str()?;
+    let is_game_directory = GAME_DIRECTORIES.contains(&name.trim_end_matches(|c| c == ';' || c == ':'));
+
+    if is_game_directory || files.keys().any(|local| local.components().count() < 2) {
+        None
+    } else {
+        Some(PathBuf::from(name))
+    }
+}
+
+fn invalid_data(e: zip::result::ZipError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+fn open_reader(root: &Path) -> io::Result<ZipArchive<File>> {
+    ZipArchive::new(File::open(root)?).map_err(invalid_data)
+}
+
+impl ModArchive {
+    fn open(root: &Path) -> io::Result<Self> {
+        let stamp = archive_stamp(root);
+        let mut archive = open_reader(root)?;
+        let mut files = HashMap::new();
+
+        for idx in 0..archive.len() {
+            let file = archive.by_index(idx).map_err(invalid_data)?;
+
+            if file.is_dir() {
+                continue;
+            }
+
+            // Entries which would point outside of the archive are not part of the mod
+            match file.enclosed_name() {
+                Some(local) => {
+                    files.insert(local.to_path_buf(), (idx, file.size() as usize));
+                },
+                None => warn!("Skipping file '{}' in archive '{}' because of its path.", file.name(), root.display()),
+            }
+        }
+
+        // The most common way to zip a mod also stores the folder it is in, which is not part of the paths of the game
+        if let Some(wrapper) = wrapping_directory(&files) {
+            debug!("Leaving out the folder '{}' of archive '{}'.", wrapper.display(), root.display());
+
+            files = files
+                .into_iter()
+                .map(|(local, entry)| (local.strip_prefix(&wrapper).map(Path::to_path_buf).unwrap_or(local), entry))
+                .collect();
+        }
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            readers: Mutex::new(vec![archive]),
+            files,
+            stamp,
+        })
+    }
+
+    /// Runs a read of the file at an index with a reader nothing else is using
+    fn with_file<T, F: FnOnce(zip::read::ZipFile) -> io::Result<T>>(&self, idx: usize, f: F) -> io::Result<T> {
+        let reader = self.readers.lock().pop();
+        let mut reader = match reader {
+            Some(reader) => reader,
+            None => open_reader(&self.root)?,
+        };
+
+        let result = reader.by_index(idx).map_err(invalid_data).and_then(f);
+        self.readers.lock().push(reader);
+        result
+    }
+
+    pub fn read<P: AsRef<Path>>(&self, local: P) -> io::Result<Vec<u8>> {
+        let (idx, size) = *self.files.get(local.as_ref()).ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+
+        self.with_file(idx, |mut file| {
+            let mut data = Vec::with_capacity(size);
+            file.read_to_end(&mut data)?;
+            Ok(data)
+        })
+    }
+
+    /// Decompresses a file into a writer, without keeping all of it in memory
+    pub fn copy_to<P: AsRef<Path>, W: Write>(&self, local: P, writer: &mut W) -> io::Result<u64> {
+        let (idx, _) = *self.files.get(local.as_ref()).ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+
+        self.with_file(idx, |mut file| io::copy(&mut file, writer))
+    }
+
+    /// Reads the start of a file, without decompressing the rest of it
+    pub fn read_header<P: AsRef<Path>>(&self, local: P, len: usize) -> io::Result<Vec<u8>> {
+        let (idx, _) = *self.files.get(local.as_ref()).ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+
+        self.with_file(idx, |file| {
+            let mut data = Vec::with_capacity(len);
+            file.take(len as u64).read_to_end(&mut data)?;
+            Ok(data)
+        })
+    }
+
+    pub fn file_size<P: AsRef<Path>>(&self, local: P) -> Option<usize> {
+        self.files.get(local.as_ref()).map(|(_, size)| *size)
+    }
+
+    pub fn entry_type<P: AsRef<Path>>(&self, local: P) -> Option<FileEntryType> {
+        let local = local.as_ref();
+
+        if self.files.contains_key(local) {
+            Some(FileEntryType::File)
+        } else if self.files.keys().any(|path| path.starts_with(local)) {
+            Some(FileEntryType::Directory)
+        } else {
+            None
+        }
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &PathBuf> {
+        self.files.keys()
+    }
+}
+
+/// Gets an archive, indexing it the first time it is needed and again whenever it is replaced
+pub fn get_archive<P: AsRef<Path>>(root: P) -> io::Result<Arc<ModArchive>> {
+    let root = root.as_ref();
+
+    if let Some(archive) = ARCHIVES.read().get(root) {
+        if archive.stamp.is_some() && archive.stamp == archive_stamp(root) {
+            return Ok(archive.clone());
+        }
+    }
+
+    let archive = Arc::new(ModArchive::open(root)?);
+    ARCHIVES.write().insert(root.to_path_buf(), archive.clone());
+    Ok(archive)
+}
+
+/// Remembers that a mod root is an archive, for its files to be read out of it
+pub fn register_archive_root<P: AsRef<Path>>(root: P) {
+    ARCHIVE_ROOTS.write().insert(root.as_ref().to_path_buf());
+}
+
+/// Splits the path of a file inside of an archive into the path of the archive and the local path of the file
+fn split_archive_path(path: &Path) -> Option<(&Path, &Path)> {
+    let roots = ARCHIVE_ROOTS.read();
+    let root = path.ancestors().find(|ancestor| is_archive(ancestor) && roots.contains(*ancestor))?;
+    Some((root, path.strip_prefix(root).ok()?))
+}
+
+/// Gets the file a mod file is stored in on the SD card, which is its archive for the files of mod archives
+pub fn containing_file(path: &Path) -> &Path {
+    split_archive_path(path).map_or(path, |(root, _)| root)
+}
+
+/// Reads a file of a mod, whether the mod is a folder or an archive
+pub fn read_mod_file<P: AsRef<Path>>(path: P) -> io::Result<Vec<u8>> {
+    let path = path.as_ref();
+
+    match split_archive_path(path) {
+        Some((root, local)) => get_archive(root)?.read(local),
+        None => std::fs::read(path),
+    }
+}
+
+/// Copies a file of a mod into a writer, whether the mod is a folder or an archive
+pub fn copy_mod_file<P: AsRef<Path>, W: Write>(path: P, writer: &mut W) -> io::Result<u64> {
+    let path = path.as_ref();
+
+    match split_archive_path(path) {
+        Some((root, local)) => get_archive(root)?.copy_to(local, writer),
+        None => io::copy(&mut File::open(path)?, writer),
+    }
+}
+
+/// Reads the start of a file of a mod, whether the mod is a folder or an archive
+pub fn read_mod_file_header<P: AsRef<Path>>(path: P, len: usize) -> io::Result<Vec<u8>> {
+    let path = path.as_ref();
+
+    match split_archive_path(path) {
+        Some((root, local)) => get_archive(root)?.read_header(local, len),
+        None => {
+            let mut data = Vec::with_capacity(len);
+            File::open(path)?.take(len as u64).read_to_end(&mut data)?;
+            Ok(data)
+        },
+    }
+}
+
+/// Whether a file of a mod exists, whether the mod is a folder or an archive
+pub fn mod_file_exists<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref();
+
+    match split_archive_path(path) {
+        Some((root, local)) => get_archive(root).map_or(false, |archive| archive.file_size(local).is_some()),
+        None => path.exists(),
+    }
+}
+
+/// Gets the size of a file of a mod, whether the mod is a folder or an archive
+pub fn mod_file_size<P: AsRef<Path>>(path: P) -> Option<usize> {
+    let path = path.as_ref();
+
+    match split_archive_path(path) {
+        Some((root, local)) => get_archive(root).ok()?.file_size(local),
+        None => std::fs::metadata(path).ok().map(|metadata| metadata.len() as usize),
+    }
+}
+
+/// Whether a file is hidden, or is in a hidden directory, which leaves it out of the mod like in a folder
+fn is_hidden(local: &Path) -> bool {
+    local
+        .iter()
+        .any(|component| component.to_str().map_or(false, |name| name.starts_with('.')))
+}
+
+/// Adds the files of an archive to the tree the same way a folder is discovered, returning the conflicts and the collected paths
+pub fn discover_archive<I, C>(launchpad: &mut LaunchPad<ModLoader>, root: &Path, ignore: I, collect: C) -> (Vec<ConflictKind>, Vec<PathBuf>)
+where
+    I: Fn(&Path) -> bool,
+    C: Fn(&Path) -> bool,
+{
+    let archive = match get_archive(root) {
+        Ok(archive) => archive,
+        Err(e) => {
+            error!("Failed to open mod archive '{}'. Reason: {:?}", root.display(), e);
+            return (Vec::new(), Vec::new());
+        },
+    };
+
+    let mut files = Vec::new();
+    let mut collected = Vec::new();
+
+    for local in archive.files() {
+        if is_hidden(local) || ignore(local) {
+            continue;
+        }
+
+        if collect(local) {
+            collected.push(local.clone());
+        } else if local.is_stream() {
+            // Streams are read by the game through their path, which cannot point inside of an archive
+            warn!(
+                "Skipping stream file '{}' in archive '{}', streams have to be extracted.",
+                local.display(),
+                root.display()
+            );
+        } else {
+            files.push(local.clone());
+        }
+    }
+
+    files.sort();
+    collected.sort();
+
+    (cache::insert_files(launchpad, root, &files), collected)
+}
+
+/// Loads the files of mods, reading the ones in archives out of them and leaving the rest to the standard loader
+pub struct ModLoader;
+
+impl FileLoader for ModLoader {
+    type ErrorType = io::Error;
+
+    fn path_exists(&self, root_path: &Path, local_path: &Path) -> bool {
+        if is_archive(root_path) {
+            get_archive(root_path).map_or(false, |archive| archive.entry_type(local_path).is_some())
+        } else {
+            StandardLoader.path_exists(root_path, local_path)
+        }
+    }
+
+    fn get_file_size(&self, root_path: &Path, local_path: &Path) -> Option<usize> {
+        if is_archive(root_path) {
+            get_archive(root_path).ok()?.file_size(local_path)
+        } else {
+            StandardLoader.get_file_size(root_path, local_path)
+        }
+    }
+
+    fn get_path_type(&self, root_path: &Path, local_path: &Path) -> Result<FileEntryType, Self::ErrorType> {
+        if is_archive(root_path) {
+            get_archive(root_path)?
+                .entry_type(local_path)
+                .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+        } else {
+            StandardLoader.get_path_type(root_path, local_path)
+        }
+    }
+
+    fn load_path(&self, root_path: &Path, local_path: &Path) -> Result<Vec<u8>, Self::ErrorType> {
+        if is_archive(root_path) {
+            get_archive(root_path)?.read(local_path)
+        } else {
+            StandardLoader.load_path(root_path, local_path)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(paths: &[&str]) -> HashMap<PathBuf, (usize, usize)> {
+        paths.iter().enumerate().map(|(idx, path)| (PathBuf::from(path), (idx, 0))).collect()
+    }
+
+    #[test]
+    fn finds_the_folder_a_mod_was_zipped_in() {
+        let wrapped = files(&["Cool Mario/fighter/mario/model/body/c00/model.numdlb", "Cool Mario/info.toml"]);
+        assert_eq!(wrapping_directory(&wrapped), Some(PathBuf::from("Cool Mario")));
+    }
+
+    #[test]
+    fn keeps_the_directories_of_the_game() {
+        assert_eq!(wrapping_directory(&files(&["fighter/mario/model/body/c00/model.numdlb"])), None);
+        assert_eq!(wrapping_directory(&files(&["stream;/sound/bgm/bgm_crs2_01_menu.nus3audio"])), None);
+    }
+
+    #[test]
+    fn keeps_mods_which_are_not_wrapped() {
+        // Files at the top of the archive, or several top-level directories, are part of the mod
+        assert_eq!(wrapping_directory(&files(&["Cool Mario/fighter/mario/model.numdlb", "info.toml"])), None);
+        assert_eq!(wrapping_directory(&files(&["fighter/mario/model.numdlb", "ui/message/msg_name.xmsbt"])), None);
+        assert_eq!(wrapping_directory(&files(&["info.toml"])), None);
+        assert_eq!(wrapping_directory(&files(&[])), None);
+    }
+
+    #[test]
+    fn splits_paths_inside_of_registered_archives() {
+        let root = Path::new("sd:/ultimate/mods/Registered Mario.zip");
+        register_archive_root(root);
+
+        let path = root.join("fighter/mario/model/body/c00/model.numdlb");
+        assert_eq!(split_archive_path(&path), Some((root, Path::new("fighter/mario/model/body/c00/model.numdlb"))));
+        assert_eq!(containing_file(&path), root);
+
+        // Folders named like archives, and archives which were never listed, are read from the SD card
+        let unregistered = Path::new("sd:/ultimate/mods/Other Mario.zip/fighter/mario/model/body/c00/model.numdlb");
+        assert_eq!(split_archive_path(unregistered), None);
+        assert_eq!(containing_file(unregistered), unregistered);
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+use smash_arc::Hash40;
+
+use crate::{config, hashes, replacement::lookup};
+
+pub static SIZE_PATCHES_PATH: &str = "sd:/ultimate/arcropolis/size_patches.json";
+
+/// How many of the directories demanding the most memory are listed in the warning
+const WORST_OFFENDER_COUNT: usize = 5;
+
+/// A file whose decompressed size was grown to fit a modded file
+#[derive(Serialize, Debug)]
+pub struct SizePatch {
+    pub path: String,
+    pub hash: u64,
+    pub vanilla_size: usize,
+    pub size: usize,
+}
+
+/// The size patches of the files that are loaded along with a directory
+#[derive(Serialize, Debug, Default)]
+pub struct DirectoryBudget {
+    pub directory: String,
+    /// The extra memory the size patches demand when the directory is loaded
+    pub extra: usize,
+    pub over_budget: bool,
+    pub files: Vec<SizePatch>,
+}
+
+/// The extra memory demanded by every size patch, compared against the configured budgets (see `config::size_patch_budget`),
+/// which are estimates that nothing guarantees the game to crash past
+#[derive(Serialize, Debug, Default)]
+pub struct SizePatchReport {
+    pub total_extra: usize,
+    pub total_budget: usize,
+    pub directory_budget: usize,
+    /// Directories from the most to the least extra memory demanded
+    pub directories: Vec<DirectoryBudget>,
+}
+
+impl SizePatchReport {
+    pub fn new(patches: Vec<(Hash40, usize, usize)>) -> Self {
+        let directory_budget = config::directory_size_patch_budget();
+        let mut directories: HashMap<Hash40, DirectoryBudget> = HashMap::new();
+
+        for (hash, vanilla_size, size) in patches.into_iter() {
+            // Files that are not part of a directory, such as streams, are grouped together
+            let dir_hash = lookup::get_dir_entry_for_file(hash).map_or(Hash40(0), |(dir_hash, _)| dir_hash);
+
+            let directory = directories.entry(dir_hash).or_insert_with(|| DirectoryBudget {
+                directory: if dir_hash == Hash40(0) { String::from("(none)") } else { hashes::find(dir_hash).to_string() },
+                ..Default::default()
+            });
+
+            directory.extra += size - vanilla_size;
+            directory.files.push(SizePatch {
+                path: hashes::find(hash).to_string(),
+                hash: hash.0,
+                vanilla_size,
+                size,
+            });
+        }
+
+        let mut directories: Vec<DirectoryBudget> = directories.into_values().collect();
+
+        for directory in directories.iter_mut() {
+            directory.over_budget = directory.extra > directory_budget;
+            directory.files.sort_by(|a, b| (b.size - b.vanilla_size).cmp(&(a.size - a.vanilla_size)));
+        }
+
+        directories.sort_by(|a, b| b.extra.cmp(&a.extra));
+
+        Self {
+            total_extra: directories.iter().map(|directory| directory.extra).sum(),
+            total_budget: config::size_patch_budget(),
+            directory_budget,
+            directories,
+        }
+    }
+
+    pub fn is_over_budget(&self) -> bool {
+        self.total_extra > self.total_budget || self.directories.iter().any(|directory| directory.over_budget)
+    }
+
+    pub fn save(&self) {
+        match serde_json::to_string_pretty(self) {
+            Ok(json) => {
+                if let Err(e) = std::fs::write(SIZE_PATCHES_PATH, json.as_bytes()) {
+                    error!("Failed to write size patch report to {}. Reason: {:?}", SIZE_PATCHES_PATH, e);
+                }
+            },
+            Err(e) => error!("Failed to serialize size patch report to JSON. {:?}", e),
+        }
+    }
+
+    /// Lists the directories that demand the most memory, for the user to know which mods to look at first if the game crashes.
+    /// The budgets are estimates rather than known limits of the heap, which the dialog says instead of predicting a crash.
+    pub fn warning(&self) -> String {
+        let offenders = self
+            .directories
+            .iter()
+            .take(WORST_OFFENDER_COUNT)
+            .map(|directory| format!("{} (+{:.1} MB)", directory.directory, directory.extra as f32 / 1_000_000.0))
+            .collect::<Vec<String>>()
+            .join("<br>* ");
+
+        format!(
+            "Your mods make the game load {:.1} MB more than it was made to, which is over the budgets ARCropolis estimates for it. These budgets are rough margins rather than known limits of the memory of the game, so your setup may load fine.<br>The directories demanding the most are:<br><br>* {}<br><br>If the game crashes while loading, consider disabling some of these mods first. See {} for the details.",
+            self.total_extra as f32 / 1_000_000.0,
+            offenders,
+            SIZE_PATCHES_PATH
+        )
+    }
+}
@@ -0,0 +1,498 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use orbits::{ConflictKind, FileLoader, LaunchPad, Tree};
+use serde::{Deserialize, Serialize};
+use smash_arc::Hash40;
+
+use super::{
+    archive::{self, ModLoader},
+    utils,
+};
+use crate::{config, PathExtension};
+
+static CACHE_FILE_NAME: &str = "discovery.bin";
+static PATCHED_SIZES_FILE_NAME: &str = "patched_sizes.bin";
+
+/// Every directory of a mod root and its `info.toml`, with their modification time and their size, which is the amount of
+/// entries for directories
+pub type Fingerprint = Vec<(PathBuf, u64, u64)>;
+
+/// The files a patched file is generated from, with their modification time and size
+pub type PatchSources = Vec<(PathBuf, u64, u64)>;
+
+/// What a mod root looked like when it was last discovered
+#[derive(Serialize, Deserialize, Default)]
+pub struct RootCache {
+    fingerprint: Fingerprint,
+    /// Every file provided by the mod root, including the ones that lost a conflict
+    files: Vec<PathBuf>,
+    collected: Vec<PathBuf>,
+    hashed_sizes: HashMap<u64, usize>,
+    hashed_paths: HashMap<u64, PathBuf>,
+}
+
+/// The results of the previous discoveries, so that only the mod roots which changed since then have to be discovered again
+#[derive(Serialize, Deserialize, Default)]
+pub struct DiscoveryCache {
+    game_version: String,
+    region: String,
+    workspace: String,
+    /// The identity of the mods, from highest to lowest load priority
+    priority: Vec<String>,
+    roots: HashMap<PathBuf, RootCache>,
+}
+
+impl DiscoveryCache {
+    /// Reads the cache from the previous boot, discarding it if it was made for another game version, region or workspace
+    pub fn load() -> Self {
+        let mut current = Self {
+            game_version: crate::get_version_string(),
+            region: config::region_str(),
+            workspace: config::workspace_name(),
+            priority: config::mod_priority(),
+            roots: HashMap::new(),
+        };
+
+        let path = crate::CACHE_PATH.join(CACHE_FILE_NAME);
+
+        let cache: Self = match std::fs::read(&path) {
+            Ok(data) => match bincode::deserialize(&data) {
+                Ok(cache) => cache,
+                Err(e) => {
+                    error!(
+                        "Unable to parse '{}' for discovery. Reason: {:?}. Every mod will be discovered again.",
+                        path.display(),
+                        *e
+                    );
+                    return current;
+                },
+            },
+            Err(_) => return current,
+        };
+
+        if cache.game_version == current.game_version
+            && cache.region == current.region
+            && cache.workspace == current.workspace
+            && cache.priority == current.priority
+        {
+            current.roots = cache.roots;
+        } else {
+            info!("Discovery cache was made for another game version, region or workspace. Every mod will be discovered again.");
+        }
+
+        current
+    }
+
+    pub fn save(&self) {
+        match bincode::serialize(self) {
+            Ok(data) => {
+                let path = crate::CACHE_PATH.join(CACHE_FILE_NAME);
+                if let Err(e) = std::fs::write(&path, data) {
+                    error!("Failed to write discovery cache to '{}'. Reason: {:?}", path.display(), e);
+                }
+            },
+            Err(e) => error!("Failed to serialize discovery cache into bytes. Reason: {:?}", *e),
+        }
+    }
+
+    /// Gets the cached discovery of a mod root, if it did not change since then
+    pub fn get<P: AsRef<Path>>(&self, root: P, fingerprint: &Fingerprint) -> Option<&RootCache> {
+        self.roots.get(root.as_ref()).filter(|cached| cached.fingerprint == *fingerprint)
+    }
+
+    /// Stores the discovery of the mod roots that were discovered again, and forgets the ones which are gone
+    pub fn update(
+        &mut self,
+        tree: &Tree<ModLoader>,
+        discovered: Vec<(PathBuf, Fingerprint, Vec<PathBuf>)>,
+        collected: &[(PathBuf, PathBuf)],
+        roots: &[PathBuf],
+    ) {
+        self.roots.retain(|root, _| roots.contains(root));
+
+        let discovered_roots: HashSet<&Path> = discovered.iter().map(|(root, ..)| root.as_path()).collect();
+        let mut files_by_root = files_by_root(tree, &discovered_roots);
+
+        for (root, fingerprint, lost) in discovered.into_iter() {
+            let mut files = files_by_root.remove(&root).unwrap_or_default();
+
+            // Files that lost a conflict are not part of the tree, but the mod root still provides them
+            files.extend(lost.into_iter().filter_map(|local| {
+                let full_path = root.join(&local);
+                let size = match utils::decompressed_size(&full_path) {
+                    Some(size) => size,
+                    None => archive::mod_file_size(&full_path)?,
+                };
+                Some((local, size))
+            }));
+
+            let (hashed_sizes, hashed_paths) = make_root_hash_maps(&files);
+
+            let entry = RootCache {
+                fingerprint,
+                files: files.into_iter().map(|(local, _)| local).collect(),
+                collected: collected.iter().filter(|(x, _)| *x == root).map(|(_, local)| local.clone()).collect(),
+                hashed_sizes,
+                hashed_paths,
+            };
+
+            self.roots.insert(root, entry);
+        }
+    }
+
+    /// Discards the discovery of mod roots, so that they are discovered again on the next boot
+    pub fn forget(&mut self, roots: &HashSet<PathBuf>) {
+        self.roots.retain(|root, _| !roots.contains(root));
+    }
+
+    /// Merges the hash maps of the mod roots, from highest to lowest priority
+    pub fn hash_maps(&self, roots: &[PathBuf]) -> (HashMap<Hash40, usize>, HashMap<Hash40, PathBuf>) {
+        let mut size_map = HashMap::new();
+        let mut path_map: HashMap<Hash40, PathBuf> = HashMap::new();
+
+        for cached in roots.iter().filter_map(|root| self.roots.get(root)) {
+            for (hash, path) in cached.hashed_paths.iter() {
+                let hash = Hash40(*hash);
+
+                // Same as in `make_root_hash_maps`, a regional variant takes priority over the regular file
+                let replace = match path_map.get(&hash) {
+                    Some(existing) => !is_regional(existing) && is_regional(path),
+                    None => true,
+                };
+
+                if replace {
+                    path_map.insert(hash, path.clone());
+                    if let Some(size) = cached.hashed_sizes.get(&hash.0) {
+                        size_map.insert(hash, *size);
+                    }
+                }
+            }
+        }
+
+        (size_map, path_map)
+    }
+}
+
+/// The sizes of the files generated by PRC and MSBT patches on the previous boots, so that only the files whose patches
+/// changed since then have to be patched to be measured
+#[derive(Serialize, Deserialize, Default)]
+pub struct PatchedSizeCache {
+    game_version: String,
+    region: String,
+    sizes: HashMap<u64, (PatchSources, usize)>,
+}
+
+impl PatchedSizeCache {
+    /// Reads the sizes measured on the previous boot, discarding them if they were measured for another game version or region
+    pub fn load() -> Self {
+        let current = Self {
+            game_version: crate::get_version_string(),
+            region: config::region_str(),
+            sizes: HashMap::new(),
+        };
+
+        let cache: Self = match std::fs::read(crate::CACHE_PATH.join(PATCHED_SIZES_FILE_NAME)) {
+            Ok(data) => bincode::deserialize(&data).unwrap_or_default(),
+            Err(_) => return current,
+        };
+
+        if cache.game_version == current.game_version && cache.region == current.region {
+            cache
+        } else {
+            current
+        }
+    }
+
+    pub fn save(&self) {
+        match bincode::serialize(self) {
+            Ok(data) => {
+                let path = crate::CACHE_PATH.join(PATCHED_SIZES_FILE_NAME);
+                if let Err(e) = std::fs::write(&path, data) {
+                    error!("Failed to write patched file sizes to '{}'. Reason: {:?}", path.display(), e);
+                }
+            },
+            Err(e) => error!("Failed to serialize patched file sizes into bytes. Reason: {:?}", *e),
+        }
+    }
+
+    /// Gets the measured size of a patched file, if the files it is generated from did not change since then
+    pub fn get(&self, hash: Hash40, sources: &PatchSources) -> Option<usize> {
+        self.sizes.get(&hash.0).filter(|(cached, _)| cached == sources).map(|(_, size)| *size)
+    }
+
+    pub fn insert(&mut self, hash: Hash40, sources: PatchSources, size: usize) {
+        self.sizes.insert(hash.0, (sources, size));
+    }
+
+    /// Forgets the files which are not patched anymore
+    pub fn retain(&mut self, hashes: &HashSet<Hash40>) {
+        self.sizes.retain(|hash, _| hashes.contains(&Hash40(*hash)));
+    }
+}
+
+/// Gets the modification time and size of the files a patched file is generated from, or None if one of them cannot be read.
+/// Files of mod archives go by the time and size of their archive.
+pub fn patch_sources<'a, I: IntoIterator<Item = &'a Path>>(paths: I) -> Option<PatchSources> {
+    paths
+        .into_iter()
+        .map(|path| {
+            let metadata = std::fs::metadata(archive::containing_file(path)).ok()?;
+            Some((path.to_path_buf(), modified_secs(&metadata), metadata.len()))
+        })
+        .collect()
+}
+
+impl RootCache {
+    /// Adds the cached files of the mod root to the tree, returning the files it lost to a mod root with a higher priority
+    pub fn insert_into(&self, launchpad: &mut LaunchPad<ModLoader>, root: &Path) -> Vec<ConflictKind> {
+        insert_files(launchpad, root, &self.files)
+    }
+
+    pub fn collected(&self) -> &[PathBuf] {
+        &self.collected
+    }
+}
+
+/// Adds files of a mod root to the tree, returning the files it lost to a mod root with a higher priority
+pub fn insert_files(launchpad: &mut LaunchPad<ModLoader>, root: &Path, files: &[PathBuf]) -> Vec<ConflictKind> {
+    let mut conflicts = Vec::new();
+
+    for local in files.iter() {
+        match launchpad.tree().query_actual_path(local) {
+            Some(full_path) => {
+                let source_root = full_path
+                    .ancestors()
+                    .find(|x| x.join(local) == full_path)
+                    .map(Path::to_path_buf)
+                    .unwrap_or_default();
+
+                conflicts.push(ConflictKind::StandardConflict {
+                    error_root: root.to_path_buf(),
+                    source_root,
+                    local: local.clone(),
+                });
+            },
+            None => launchpad.tree_mut().insert_file(root, local),
+        }
+    }
+
+    conflicts
+}
+
+fn modified_secs(metadata: &std::fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |time| time.as_secs())
+}
+
+/// Gets the modification time and the amount of entries of every directory in the mod root, which change whenever a file
+/// is added, removed or renamed, along with the modification time and size of its `info.toml`. The files themselves are
+/// not looked at, since that is most of what discovering the mod root costs on the SD card: a file overwritten in place
+/// is only noticed once the `info.toml` of the mod changes, which mod managers and updates do.
+pub fn fingerprint<P: AsRef<Path>>(root: P) -> Fingerprint {
+    let root = root.as_ref();
+    let mut fingerprint = Vec::new();
+
+    let metadata = match std::fs::metadata(root) {
+        Ok(metadata) => metadata,
+        Err(_) => return fingerprint,
+    };
+
+    // Mods packed in archives are a single file
+    if !metadata.is_dir() {
+        fingerprint.push((PathBuf::new(), modified_secs(&metadata), metadata.len()));
+        return fingerprint;
+    }
+
+    if let Ok(info) = std::fs::metadata(root.join("info.toml")) {
+        fingerprint.push((PathBuf::from("info.toml"), modified_secs(&info), info.len()));
+    }
+
+    let mut pending = vec![(root.to_path_buf(), modified_secs(&metadata))];
+
+    while let Some((dir, modified)) = pending.pop() {
+        let mut count = 0;
+
+        if let Ok(entries) = std::fs::read_dir(&dir) {
+            for entry in entries.filter_map(Result::ok) {
+                count += 1;
+
+                if entry.file_type().map_or(false, |ty| ty.is_dir()) {
+                    let modified = entry.metadata().map_or(0, |metadata| modified_secs(&metadata));
+                    pending.push((entry.path(), modified));
+                }
+            }
+        }
+
+        fingerprint.push((dir.strip_prefix(root).unwrap_or(&dir).to_path_buf(), modified, count));
+    }
+
+    fingerprint.sort();
+    fingerprint
+}
+
+fn is_regional(path: &Path) -> bool {
+    path.to_str().map_or(false, |x| x.contains('+'))
+}
+
+/// Gets the files in the tree of every provided mod root, along with their size
+fn files_by_root<L: FileLoader>(tree: &Tree<L>, roots: &HashSet<&Path>) -> HashMap<PathBuf, Vec<(PathBuf, usize)>>
+where
+    <L as FileLoader>::ErrorType: Debug,
+{
+    let mut files: HashMap<PathBuf, Vec<(PathBuf, usize)>> = HashMap::new();
+
+    tree.walk_paths(|node, ty| {
+        if !ty.is_file() {
+            return;
+        }
+
+        let local = node.get_local();
+        let full_path = node.full_path();
+
+        let root = match full_path.ancestors().find(|x| roots.contains(x)) {
+            Some(root) => root.to_path_buf(),
+            None => return,
+        };
+
+        // Compressed files are patched to the size they have once decompressed
+        if let Some(size) = utils::decompressed_size(&full_path).or_else(|| tree.query_filesize(local)) {
+            files.entry(root).or_default().push((local.to_path_buf(), size));
+        }
+    });
+
+    files
+}
+
+/// Gets two hashmaps for the files of a mod root, one of the sizes of each file (for patching) and a hash40 -> PathBuf lookup
+fn make_root_hash_maps(files: &[(PathBuf, usize)]) -> (HashMap<u64, usize>, HashMap<u64, PathBuf>) {
+    // A regional variant such as ui/message/msg_menu+us_en.msbt takes priority over ui/message/msg_menu.msbt, whichever is found first
+    let mut size_map = HashMap::new();
+    let mut path_map: HashMap<u64, PathBuf> = HashMap::new();
+
+    for (local, size) in files.iter() {
+        match local.smash_hash() {
+            Ok(hash) => {
+                if path_map.get(&hash.0).map_or(false, |existing| is_regional(existing) && !is_regional(local)) {
+                    continue;
+                }
+
+                size_map.insert(hash.0, *size);
+                path_map.insert(hash.0, local.clone());
+            },
+            Err(e) => error!("Failed to get hash for {}. Reason: {:?}", local.display(), e),
+        }
+    }
+
+    (size_map, path_map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A mod root in a directory of its own, which is removed once the test is over
+    struct TempRoot(PathBuf);
+
+    impl TempRoot {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("arcropolis-cache-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(path.join("fighter/mario/model")).unwrap();
+            std::fs::write(path.join("fighter/mario/model/body.nutexb"), [0; 16]).unwrap();
+            std::fs::write(path.join("info.toml"), "display_name = \"Mario\"").unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempRoot {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn fingerprint_is_stable() {
+        let root = TempRoot::new("unchanged");
+        assert_eq!(fingerprint(&root.0), fingerprint(&root.0));
+    }
+
+    #[test]
+    fn fingerprint_notices_added_files() {
+        let root = TempRoot::new("added");
+        let before = fingerprint(&root.0);
+
+        std::fs::write(root.0.join("fighter/mario/model/hair.nutexb"), [0; 16]).unwrap();
+        assert_ne!(fingerprint(&root.0), before);
+    }
+
+    #[test]
+    fn fingerprint_notices_changed_info() {
+        let root = TempRoot::new("info");
+        let before = fingerprint(&root.0);
+
+        std::fs::write(root.0.join("info.toml"), "display_name = \"Mario\"\nversion = \"1.1\"").unwrap();
+        assert_ne!(fingerprint(&root.0), before);
+    }
+
+    #[test]
+    fn fingerprint_of_a_missing_root_is_empty() {
+        assert!(fingerprint(std::env::temp_dir().join("arcropolis-cache-missing")).is_empty());
+    }
+
+    fn cached_root(files: &[(&str, usize)]) -> RootCache {
+        let files: Vec<(PathBuf, usize)> = files.iter().map(|(local, size)| (PathBuf::from(local), *size)).collect();
+        let (hashed_sizes, hashed_paths) = make_root_hash_maps(&files);
+
+        RootCache {
+            files: files.into_iter().map(|(local, _)| local).collect(),
+            hashed_sizes,
+            hashed_paths,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn hash_maps_keep_the_file_of_the_highest_priority() {
+        let mut cache = DiscoveryCache::default();
+        cache.roots.insert(PathBuf::from("mods/a"), cached_root(&[("fighter/mario/model/body.nutexb", 0x100)]));
+        cache.roots.insert(
+            PathBuf::from("mods/b"),
+            cached_root(&[("fighter/mario/model/body.nutexb", 0x200), ("fighter/luigi/model/body.nutexb", 0x300)]),
+        );
+
+        let roots = [PathBuf::from("mods/a"), PathBuf::from("mods/b"), PathBuf::from("mods/missing")];
+        let (sizes, paths) = cache.hash_maps(&roots);
+
+        let mario = Hash40::from("fighter/mario/model/body.nutexb");
+        let luigi = Hash40::from("fighter/luigi/model/body.nutexb");
+
+        assert_eq!(sizes[&mario], 0x100);
+        assert_eq!(sizes[&luigi], 0x300);
+        assert_eq!(paths.len(), 2);
+
+        // Only the roots being loaded are merged
+        let (sizes, _) = cache.hash_maps(&roots[1..]);
+        assert_eq!(sizes[&mario], 0x200);
+    }
+
+    #[test]
+    fn hash_maps_prefer_regional_variants() {
+        let mut cache = DiscoveryCache::default();
+        cache.roots.insert(PathBuf::from("mods/a"), cached_root(&[("ui/message/msg_menu.msbt", 0x100)]));
+        cache.roots.insert(PathBuf::from("mods/b"), cached_root(&[("ui/message/msg_menu+us_en.msbt", 0x200)]));
+
+        let (sizes, paths) = cache.hash_maps(&[PathBuf::from("mods/a"), PathBuf::from("mods/b")]);
+        let hash = Hash40::from("ui/message/msg_menu.msbt");
+
+        assert_eq!(sizes[&hash], 0x200);
+        assert_eq!(paths[&hash], Path::new("ui/message/msg_menu+us_en.msbt"));
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use orbits::ConflictKind;
+use serde::{Deserialize, Serialize};
+
+pub static CONFLICTS_PATH: &str = "sd:/ultimate/arcropolis/conflicts.json";
+pub static PARAM_CONFLICTS_PATH: &str = "sd:/ultimate/arcropolis/param_conflicts.json";
+
+/// The outcome of a file which was provided by more than one mod root
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConflictResolution {
+    /// The mod root which the file is loaded from
+    pub winner: PathBuf,
+    /// The mod roots which also provided the file, from highest to lowest priority
+    pub losers: Vec<PathBuf>,
+}
+
+/// Every file conflict found during discovery, keyed by the local path of the file
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ConflictReport(HashMap<PathBuf, ConflictResolution>);
+
+impl ConflictReport {
+    pub fn from_conflicts(conflicts: Vec<ConflictKind>) -> Self {
+        let mut report: HashMap<PathBuf, ConflictResolution> = HashMap::new();
+
+        for conflict in conflicts.into_iter() {
+            match conflict {
+                ConflictKind::StandardConflict {
+                    error_root,
+                    source_root,
+                    local,
+                } => {
+                    warn!(
+                        "File '{}' was rejected for file '{}' during discovery.",
+                        error_root.join(&local).display(),
+                        source_root.join(&local).display()
+                    );
+
+                    if let Some(resolution) = report.get_mut(&local) {
+                        resolution.losers.push(error_root);
+                    } else {
+                        report.insert(
+                            local,
+                            ConflictResolution {
+                                winner: source_root,
+                                losers: vec![error_root],
+                            },
+                        );
+                    }
+                },
+                ConflictKind::RootConflict(root_path, kept) => {
+                    warn!(
+                        "Mod root '{}' was rejected for a file conflict with '{}' during discovery.",
+                        root_path.display(),
+                        kept.display()
+                    )
+                },
+            }
+        }
+
+        Self(report)
+    }
+
+    /// Reads the report that was written during the last discovery
+    pub fn load() -> Self {
+        std::fs::read_to_string(CONFLICTS_PATH)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        match serde_json::to_string_pretty(self) {
+            Ok(json) => {
+                if let Err(e) = std::fs::write(CONFLICTS_PATH, json.as_bytes()) {
+                    error!("Failed to write conflict report to {}. Reason: {:?}", CONFLICTS_PATH, e);
+                }
+            },
+            Err(e) => error!("Failed to serialize conflict report to JSON. {:?}", e),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&PathBuf, &ConflictResolution)> {
+        self.0.iter()
+    }
+
+    /// Gets every contested file that the mod root is part of, with the mod root that won it
+    pub fn contested_by<P: AsRef<Path>>(&self, root: P) -> Vec<(&Path, &Path)> {
+        let root = root.as_ref();
+        let mut contested: Vec<(&Path, &Path)> = self
+            .0
+            .iter()
+            .filter(|(_, resolution)| resolution.winner == root || resolution.losers.iter().any(|loser| loser == root))
+            .map(|(local, resolution)| (local.as_path(), resolution.winner.as_path()))
+            .collect();
+        contested.sort();
+        contested
+    }
+}
+
+/// Every param key that was edited by the patches of more than one mod root, keyed by the patched file and then by the key
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ParamConflictReport(HashMap<PathBuf, HashMap<String, Vec<PathBuf>>>);
+
+impl ParamConflictReport {
+    /// Gets the mod roots other than `root` which already edited any of the keys of a file
+    pub fn overlapping_edits<P: AsRef<Path>>(&self, file: P, root: &Path, keys: &[String]) -> Vec<PathBuf> {
+        let mut overlapping = Vec::new();
+        let file_keys = match self.0.get(file.as_ref()) {
+            Some(file_keys) => file_keys,
+            None => return overlapping,
+        };
+
+        for roots in keys.iter().filter_map(|key| file_keys.get(key)) {
+            for other in roots.iter() {
+                if other != root && !overlapping.contains(other) {
+                    overlapping.push(other.clone());
+                }
+            }
+        }
+
+        overlapping
+    }
+
+    /// Adds the keys edited by a mod root to the report, returning the mod roots which already edited any of the keys
+    pub fn add_edits<P: AsRef<Path>>(&mut self, file: P, root: &Path, keys: &[String]) -> Vec<PathBuf> {
+        let overlapping = self.overlapping_edits(&file, root, keys);
+        let file_keys = self.0.entry(file.as_ref().to_path_buf()).or_default();
+
+        for key in keys.iter() {
+            let roots = file_keys.entry(key.clone()).or_default();
+            if !roots.iter().any(|x| x == root) {
+                roots.push(root.to_path_buf());
+            }
+        }
+
+        overlapping
+    }
+
+    /// Adds the keys edited by a param patch, patches being added from the highest to the lowest priority. Returns whether
+    /// the patch is applied, along with the mod roots which already edited any of the keys. In strict mode, such a patch
+    /// is refused and its keys are left out of the report, so that it does not get the patches after it refused.
+    pub fn add_patch<P: AsRef<Path>>(&mut self, file: P, root: &Path, keys: &[String], strict: bool) -> (bool, Vec<PathBuf>) {
+        if strict {
+            let overlapping = self.overlapping_edits(&file, root, keys);
+            if !overlapping.is_empty() {
+                return (false, overlapping);
+            }
+        }
+
+        (true, self.add_edits(file, root, keys))
+    }
+
+    pub fn save(mut self) {
+        // Only the keys which were edited by several mod roots are of interest
+        for keys in self.0.values_mut() {
+            keys.retain(|_, roots| roots.len() > 1);
+        }
+        self.0.retain(|_, keys| !keys.is_empty());
+
+        match serde_json::to_string_pretty(&self) {
+            Ok(json) => {
+                if let Err(e) = std::fs::write(PARAM_CONFLICTS_PATH, json.as_bytes()) {
+                    error!("Failed to write param conflict report to {}. Reason: {:?}", PARAM_CONFLICTS_PATH, e);
+                }
+            },
+            Err(e) => error!("Failed to serialize param conflict report to JSON. {:?}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(keys: &[&str]) -> Vec<String> {
+        keys.iter().map(|key| key.to_string()).collect()
+    }
+
+    const FILE: &str = "fighter/common/param/fighter_param.prc";
+
+    #[test]
+    fn reports_the_roots_editing_the_same_keys() {
+        let mut report = ParamConflictReport::default();
+        let (a, b, c) = (Path::new("mods/a"), Path::new("mods/b"), Path::new("mods/c"));
+
+        assert!(report.add_edits(FILE, a, &keys(&["walk_speed", "jump_count"])).is_empty());
+        assert_eq!(report.add_edits(FILE, b, &keys(&["jump_count"])), vec![a.to_path_buf()]);
+        assert_eq!(report.add_edits(FILE, c, &keys(&["walk_speed", "jump_count"])), vec![a.to_path_buf(), b.to_path_buf()]);
+
+        // Another file, and the other patches of the same mod root, do not overlap
+        assert!(report.add_edits("fighter/common/param/common.prc", b, &keys(&["walk_speed"])).is_empty());
+        assert!(report.add_edits(FILE, a, &keys(&["walk_speed"])).is_empty());
+
+        assert_eq!(report.0[Path::new(FILE)]["jump_count"], vec![a.to_path_buf(), b.to_path_buf(), c.to_path_buf()]);
+    }
+
+    #[test]
+    fn lenient_mode_applies_overlapping_patches() {
+        let mut report = ParamConflictReport::default();
+        let (a, b) = (Path::new("mods/a"), Path::new("mods/b"));
+
+        assert_eq!(report.add_patch(FILE, a, &keys(&["walk_speed"]), false), (true, Vec::new()));
+        assert_eq!(report.add_patch(FILE, b, &keys(&["walk_speed"]), false), (true, vec![a.to_path_buf()]));
+    }
+
+    #[test]
+    fn strict_mode_refuses_overlapping_patches() {
+        let mut report = ParamConflictReport::default();
+        let (a, b, c) = (Path::new("mods/a"), Path::new("mods/b"), Path::new("mods/c"));
+
+        assert_eq!(report.add_patch(FILE, a, &keys(&["walk_speed"]), true), (true, Vec::new()));
+        assert_eq!(report.add_patch(FILE, b, &keys(&["walk_speed", "jump_count"]), true), (false, vec![a.to_path_buf()]));
+
+        // The refused patch does not get the patches after it refused
+        assert_eq!(report.add_patch(FILE, c, &keys(&["jump_count"]), true), (true, Vec::new()));
+    }
+}
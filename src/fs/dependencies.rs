@@ -0,0 +1,410 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+pub use super::pipeline::folder_name;
+use super::pipeline::mod_id_from_info;
+
+/// The relationships a mod declares with other mods in its `info.toml`, referencing them by folder name
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct ModDependencies {
+    /// Mods that have to be enabled for this mod to work
+    #[serde(default)]
+    pub requires: Vec<String>,
+    /// Mods that cannot be enabled alongside this mod
+    #[serde(default)]
+    pub conflicts_with: Vec<String>,
+    /// Mods that this mod has to be loaded after, so that its files take priority over theirs
+    #[serde(default)]
+    pub load_after: Vec<String>,
+}
+
+impl ModDependencies {
+    pub fn read<P: AsRef<Path>>(root: P) -> Self {
+        let info_path = root.as_ref().join("info.toml");
+
+        match super::archive::read_mod_file(&info_path).map(|data| String::from_utf8_lossy(&data).into_owned()) {
+            Ok(text) => toml::from_str(&text).unwrap_or_else(|e| {
+                warn!("Failed to read the dependencies of '{}'. Reason: {:?}", info_path.display(), e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DependencyViolation {
+    /// A required mod is installed but was not enabled, so it has been enabled
+    AutoEnabled { name: String, required_by: String },
+    /// A required mod is not installed
+    MissingRequirement { name: String, required_by: String },
+    /// A required mod is installed but was not enabled, and could not be enabled automatically
+    DisabledRequirement { name: String, required_by: String },
+    /// Two enabled mods declared that they cannot be used together
+    Incompatible { name: String, other: String },
+}
+
+impl fmt::Display for DependencyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AutoEnabled { name, required_by } => write!(f, "'{}' was enabled because '{}' requires it", name, required_by),
+            Self::MissingRequirement { name, required_by } => write!(f, "'{}' requires '{}', which is not installed", required_by, name),
+            Self::DisabledRequirement { name, required_by } => write!(f, "'{}' requires '{}', which is disabled", required_by, name),
+            Self::Incompatible { name, other } => write!(f, "'{}' cannot be used with '{}'", name, other),
+        }
+    }
+}
+
+/// Formats the violations for the dialogs reporting them, followed by what they mean for the user
+pub fn violations_dialog_text<'a, I: IntoIterator<Item = &'a DependencyViolation>>(violations: I, conclusion: &str) -> String {
+    let violations: Vec<String> = violations.into_iter().map(|x| x.to_string()).collect();
+
+    format!(
+        "Some of your enabled mods have unfulfilled dependencies:<br><br>* {}<br><br>{}",
+        violations.join("<br>* "),
+        conclusion
+    )
+}
+
+/// Gets what identifies a mod in the presets, see `pipeline::mod_id_from_info`
+pub fn mod_id(root: &Path) -> String {
+    let info = super::archive::read_mod_file(root.join("info.toml")).ok();
+    mod_id_from_info(root, info.as_deref())
+}
+
+/// Whether a name in a declaration refers to a mod root, by the id of the mod or, for mods declared before they had an id,
+/// by its folder name
+fn refers_to(root: &Path, id: &str, name: &str) -> bool {
+    id == name || folder_name(root) == name
+}
+
+/// Checks the dependencies of the enabled mod roots. Required mods which are installed are added to the enabled mod roots
+/// when `auto_enable` is set, and reported as missing otherwise.
+pub fn resolve_dependencies(enabled: &mut Vec<PathBuf>, installed: &[PathBuf], auto_enable: bool) -> Vec<DependencyViolation> {
+    resolve_dependencies_with(enabled, installed, auto_enable, ModDependencies::read, super::cached_mod_id)
+}
+
+fn resolve_dependencies_with<R, I>(enabled: &mut Vec<PathBuf>, installed: &[PathBuf], auto_enable: bool, read: R, id: I) -> Vec<DependencyViolation>
+where
+    R: Fn(&Path) -> ModDependencies,
+    I: Fn(&Path) -> String,
+{
+    let ids: HashMap<PathBuf, String> = installed.iter().chain(enabled.iter()).map(|root| (root.clone(), id(root))).collect();
+    let is_named = |root: &Path, name: &str| ids.get(root).map_or(false, |id| refers_to(root, id, name));
+
+    let mut dependencies: HashMap<PathBuf, ModDependencies> = HashMap::new();
+    let mut violations = Vec::new();
+    let mut idx = 0;
+
+    // Enabled requirements can require other mods, so the list is walked while it grows
+    while idx < enabled.len() {
+        let root = enabled[idx].clone();
+        let requires = dependencies.entry(root.clone()).or_insert_with(|| read(&root)).requires.clone();
+
+        for name in requires.iter() {
+            if enabled.iter().any(|x| is_named(x, name)) {
+                continue;
+            }
+
+            let required_by = folder_name(&root).to_string();
+
+            match installed.iter().find(|x| is_named(x, name)) {
+                Some(required) if auto_enable => {
+                    enabled.push(required.clone());
+                    violations.push(DependencyViolation::AutoEnabled {
+                        name: name.clone(),
+                        required_by,
+                    });
+                },
+                Some(_) => {
+                    violations.push(DependencyViolation::DisabledRequirement {
+                        name: name.clone(),
+                        required_by,
+                    })
+                },
+                None => {
+                    violations.push(DependencyViolation::MissingRequirement {
+                        name: name.clone(),
+                        required_by,
+                    })
+                },
+            }
+        }
+
+        idx += 1;
+    }
+
+    for root in enabled.iter() {
+        let name = folder_name(root);
+
+        for other in dependencies[root].conflicts_with.iter() {
+            let other_root = match enabled.iter().find(|x| is_named(x, other)) {
+                Some(other_root) => folder_name(other_root),
+                None => continue,
+            };
+
+            let flagged = violations.iter().any(|x| match x {
+                DependencyViolation::Incompatible { name: a, other: b } => (a == other_root && b == name) || (a == name && b == other_root),
+                _ => false,
+            });
+
+            if !flagged {
+                violations.push(DependencyViolation::Incompatible {
+                    name: name.to_string(),
+                    other: other_root.to_string(),
+                });
+            }
+        }
+    }
+
+    violations
+}
+
+/// Moves every mod root ahead of the mods it declared to be loaded after, since the first mod root to provide a file keeps it.
+/// The roots are otherwise kept in the order they are in, and a warning is logged for every declaration which goes against
+/// the order the user gave to both mods in the `priority` of the workspace.
+pub fn apply_load_after(roots: &mut Vec<PathBuf>, priority: &[String]) {
+    apply_load_after_with(roots, priority, |root| ModDependencies::read(root).load_after, super::cached_mod_id)
+}
+
+fn apply_load_after_with<R, I>(roots: &mut Vec<PathBuf>, priority: &[String], read: R, id: I)
+where
+    R: Fn(&Path) -> Vec<String>,
+    I: Fn(&Path) -> String,
+{
+    let ids: Vec<String> = roots.iter().map(|root| id(root)).collect();
+
+    // Names to the index of the root they refer to, by id first so that a folder named like the id of another mod loses
+    let mut indices: HashMap<&str, usize> = HashMap::new();
+    for (idx, root) in roots.iter().enumerate() {
+        indices.entry(folder_name(root)).or_insert(idx);
+    }
+    for (idx, id) in ids.iter().enumerate() {
+        indices.insert(id.as_str(), idx);
+    }
+
+    // The roots each root has to be placed ahead of
+    let mut ahead_of: Vec<Vec<usize>> = vec![Vec::new(); roots.len()];
+    let mut behind_count = vec![0usize; roots.len()];
+
+    for (idx, root) in roots.iter().enumerate() {
+        for name in read(root).iter() {
+            let other = match indices.get(name.as_str()) {
+                Some(&other) if other != idx => other,
+                _ => continue,
+            };
+
+            if other < idx {
+                let ranked = |idx: usize| priority.iter().position(|x| *x == ids[idx]);
+
+                if let (Some(_), Some(_)) = (ranked(idx), ranked(other)) {
+                    warn!(
+                        "'{}' is loaded after '{}' as it declares, which goes against the priority of the workspace.",
+                        folder_name(root),
+                        folder_name(&roots[other])
+                    );
+                }
+            }
+
+            ahead_of[idx].push(other);
+            behind_count[other] += 1;
+        }
+    }
+
+    // The roots are placed in their current order, except that a root waits for every root it has to be placed behind
+    let mut ready: BinaryHeap<Reverse<usize>> = (0..roots.len()).filter(|idx| behind_count[*idx] == 0).map(Reverse).collect();
+    let mut order = Vec::with_capacity(roots.len());
+
+    while let Some(Reverse(idx)) = ready.pop() {
+        order.push(idx);
+
+        for other in ahead_of[idx].iter() {
+            behind_count[*other] -= 1;
+            if behind_count[*other] == 0 {
+                ready.push(Reverse(*other));
+            }
+        }
+    }
+
+    if order.len() < roots.len() {
+        warn!("The load_after declarations of the enabled mods form a cycle, the load order might not be respected.");
+
+        // The roots of the cycle, and the ones waiting on them, keep their current order after the others
+        let placed: HashSet<usize> = order.iter().copied().collect();
+        order.extend((0..roots.len()).filter(|idx| !placed.contains(idx)));
+    }
+
+    let mut previous: Vec<Option<PathBuf>> = roots.drain(..).map(Some).collect();
+    roots.extend(order.into_iter().filter_map(|idx| previous[idx].take()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roots(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(|name| Path::new("sd:/ultimate/mods").join(name)).collect()
+    }
+
+    fn names(roots: &[PathBuf]) -> Vec<&str> {
+        roots.iter().map(|root| folder_name(root)).collect()
+    }
+
+    fn strings(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    /// Reads the declarations from a table of folder names instead of their `info.toml`
+    fn declarations<'a>(table: &'a [(&'a str, ModDependencies)]) -> impl Fn(&Path) -> ModDependencies + 'a {
+        move |root| {
+            table
+                .iter()
+                .find(|(name, _)| *name == folder_name(root))
+                .map(|(_, dependencies)| dependencies.clone())
+                .unwrap_or_default()
+        }
+    }
+
+    /// Mods whose folder name ends with `-renamed` have the id of the folder they were renamed from
+    fn id(root: &Path) -> String {
+        folder_name(root).trim_end_matches("-renamed").to_string()
+    }
+
+    fn requires(names: &[&str]) -> ModDependencies {
+        ModDependencies {
+            requires: strings(names),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn enables_requirements_of_requirements() {
+        let installed = roots(&["skin", "lib", "core", "other"]);
+        let mut enabled = roots(&["skin"]);
+        let table = [("skin", requires(&["lib"])), ("lib", requires(&["core"]))];
+
+        let violations = resolve_dependencies_with(&mut enabled, &installed, true, declarations(&table), id);
+
+        assert_eq!(names(&enabled), vec!["skin", "lib", "core"]);
+        assert_eq!(violations, vec![
+            DependencyViolation::AutoEnabled {
+                name: "lib".into(),
+                required_by: "skin".into()
+            },
+            DependencyViolation::AutoEnabled {
+                name: "core".into(),
+                required_by: "lib".into()
+            },
+        ]);
+    }
+
+    #[test]
+    fn reports_missing_and_disabled_requirements() {
+        let installed = roots(&["skin", "lib"]);
+        let mut enabled = roots(&["skin"]);
+        let table = [("skin", requires(&["lib", "gone"]))];
+
+        let violations = resolve_dependencies_with(&mut enabled, &installed, false, declarations(&table), id);
+
+        assert_eq!(names(&enabled), vec!["skin"]);
+        assert_eq!(violations, vec![
+            DependencyViolation::DisabledRequirement {
+                name: "lib".into(),
+                required_by: "skin".into()
+            },
+            DependencyViolation::MissingRequirement {
+                name: "gone".into(),
+                required_by: "skin".into()
+            },
+        ]);
+    }
+
+    #[test]
+    fn matches_renamed_mods_by_id() {
+        let installed = roots(&["skin", "lib-renamed"]);
+        let mut enabled = roots(&["skin", "lib-renamed"]);
+        let table = [("skin", requires(&["lib"]))];
+
+        assert!(resolve_dependencies_with(&mut enabled, &installed, true, declarations(&table), id).is_empty());
+        assert_eq!(enabled.len(), 2);
+    }
+
+    #[test]
+    fn reports_incompatible_mods_once() {
+        let installed = roots(&["a", "b"]);
+        let mut enabled = installed.clone();
+        let conflicts = |other: &str| ModDependencies {
+            conflicts_with: strings(&[other]),
+            ..Default::default()
+        };
+        let table = [("a", conflicts("b")), ("b", conflicts("a"))];
+
+        let violations = resolve_dependencies_with(&mut enabled, &installed, true, declarations(&table), id);
+
+        assert_eq!(violations, vec![DependencyViolation::Incompatible {
+            name: "a".into(),
+            other: "b".into()
+        }]);
+    }
+
+    fn load_after<'a>(table: &'a [(&'a str, &'a [&'a str])]) -> impl Fn(&Path) -> Vec<String> + 'a {
+        move |root| {
+            table
+                .iter()
+                .find(|(name, _)| *name == folder_name(root))
+                .map_or_else(Vec::new, |(_, after)| strings(after))
+        }
+    }
+
+    #[test]
+    fn loads_mods_ahead_of_the_mods_they_load_after() {
+        let mut order = roots(&["a", "b", "c", "d"]);
+        let table: [(&str, &[&str]); 2] = [("d", &["b"]), ("c", &["a"])];
+
+        apply_load_after_with(&mut order, &[], load_after(&table), id);
+
+        // The other mods keep their order
+        assert_eq!(names(&order), vec!["c", "a", "d", "b"]);
+    }
+
+    #[test]
+    fn loads_after_renamed_mods_by_id() {
+        let mut order = roots(&["lib-renamed", "skin"]);
+        let table: [(&str, &[&str]); 1] = [("skin", &["lib"])];
+
+        apply_load_after_with(&mut order, &[], load_after(&table), id);
+
+        assert_eq!(names(&order), vec!["skin", "lib-renamed"]);
+    }
+
+    #[test]
+    fn keeps_the_order_of_a_cycle() {
+        let mut order = roots(&["a", "b", "c"]);
+        let table: [(&str, &[&str]); 2] = [("b", &["c"]), ("c", &["b"])];
+
+        apply_load_after_with(&mut order, &[], load_after(&table), id);
+
+        assert_eq!(names(&order), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn overrides_the_workspace_priority() {
+        let mut order = roots(&["a", "b"]);
+        let table: [(&str, &[&str]); 1] = [("b", &["a"])];
+
+        apply_load_after_with(&mut order, &strings(&["a", "b"]), load_after(&table), id);
+
+        assert_eq!(names(&order), vec!["b", "a"]);
+    }
+
+    #[test]
+    fn mods_without_info_are_identified_by_folder_name() {
+        assert_eq!(mod_id(Path::new("sd:/ultimate/mods/Not Installed")), "Not Installed");
+    }
+}
@@ -4,29 +4,30 @@ use std::{
 };
 
 use once_cell::sync::Lazy;
-use orbits::{ConflictHandler, ConflictKind, FileLoader, LaunchPad, StandardLoader, Tree};
+use orbits::{ConflictHandler, ConflictKind, FileLoader, LaunchPad, Tree};
 use skyline::nn::{self, ro::*};
-use smash_arc::Hash40;
-
-use crate::{chainloader::*, config};
+use smash_arc::{Hash40, LoadedArc};
+
+use super::{
+    archive::{self, ModLoader},
+    cache::{self, DiscoveryCache},
+    dependencies::{apply_load_after, mod_id, resolve_dependencies, violations_dialog_text, DependencyViolation},
+    pipeline::{self, Platform},
+    ConflictReport, CONFLICTS_PATH,
+};
+use crate::{chainloader::*, config, resource};
 
-static PRESET_HASHES: Lazy<HashSet<Hash40>> = Lazy::new(|| {
+static PRESETS: Lazy<HashSet<String>> = Lazy::new(|| {
     let mut storage = config::GLOBAL_CONFIG.lock().unwrap();
 
-    let workspace_name: String = storage.get_field("workspace").unwrap_or_else(|_| "Default".to_string());
-    let workspace_list: HashMap<String, String> = storage.get_field_json("workspace_list").unwrap_or_default();
-
-    // Get the name of the preset file from the workspace list
-    let presets: String = workspace_list.get(&workspace_name).unwrap_or(&"presets".to_string()).to_string();
-
-    let presets = match storage.get_field_json(&presets) {
+    let presets = match storage.get_field_json(config::active_preset_name(&storage)) {
         Ok(presets) => {
             trace!("Preset properly deserialized");
             presets
         },
         Err(err) => {
             trace!("Preset deserialize error: {:?}", err);
-            let empty_presets: HashSet<Hash40> = HashSet::new();
+            let empty_presets: HashSet<String> = HashSet::new();
             storage.set_field_json("presets", &empty_presets).unwrap();
             empty_presets
         },
@@ -36,76 +37,119 @@ static PRESET_HASHES: Lazy<HashSet<Hash40>> = Lazy::new(|| {
     presets
 });
 
-pub fn perform_discovery() -> LaunchPad<StandardLoader> {
-    let is_emulator = unsafe { skyline::hooks::getRegionAddress(skyline::hooks::Region::Text) as u64 } == 0x8004000;
+/// Every installed mod root along with its identity. The identity comes from the `info.toml` of the mod, so it is read
+/// once, by discovery or by the first plugin asking whether a mod is enabled.
+static INSTALLED_MODS: Lazy<Vec<(PathBuf, String)>> = Lazy::new(|| {
+    installed_mod_roots(&config::extra_paths())
+        .into_iter()
+        .map(|root| {
+            let id = mod_id(&root);
+            (root, id)
+        })
+        .collect()
+});
 
-    if is_emulator {
-        info!("Emulator usage detected in perform_discovery, reverting to old behavior.");
+/// Gets every installed mod root along with its identity, see `mod_id`
+pub fn installed_mod_ids() -> &'static [(PathBuf, String)] {
+    &INSTALLED_MODS
+}
+
+/// Gets the identity of a mod root, without reading its `info.toml` again if it is installed
+pub fn cached_mod_id(root: &Path) -> String {
+    INSTALLED_MODS
+        .iter()
+        .find(|(installed, _)| installed == root)
+        .map_or_else(|| mod_id(root), |(_, id)| id.clone())
+}
+
+pub fn is_emulator() -> bool {
+    unsafe { skyline::hooks::getRegionAddress(skyline::hooks::Region::Text) as u64 == 0x8004000 }
+}
+
+/// Answers the pipeline with the configuration and the tables of the running game
+pub struct ConsolePlatform;
+
+impl Platform for ConsolePlatform {
+    type Arc = LoadedArc;
+
+    fn region_str(&self) -> String {
+        config::region_str()
     }
 
-    let legacy_discovery = config::legacy_discovery();
+    fn mod_priority(&self) -> Vec<String> {
+        config::mod_priority()
+    }
 
-    if !is_emulator {
-        // Open the ARCropolis menu if Minus is held before mod discovery
-        if ninput::any::is_down(ninput::Buttons::PLUS) {
-            crate::menus::show_main_menu();
-        }
+    fn mod_id(&self, root: &Path) -> String {
+        cached_mod_id(root)
     }
 
-    let filter = |path: &Path| {
-        // If we're not running on emulator
-        if !is_emulator && !legacy_discovery {
-            // If it's not in the presets, don't load
-            PRESET_HASHES.contains(&Hash40::from(path.to_str().unwrap()))
+    fn is_mod_enabled(&self, root: &Path) -> bool {
+        // Emulators can't use presets
+        if !is_emulator() && !config::legacy_discovery() {
+            PRESETS.contains(&cached_mod_id(root))
         } else {
-            // Legacy filter, load the mod except if it has a period at the start of the name
-
-            path.file_name()
-                .and_then(|name| name.to_str())
-                .map(|name| !name.starts_with('.'))
-                .unwrap_or(false)
+            pipeline::is_enabled_by_name(root)
         }
-    };
+    }
 
-    let ignore = |path: &Path| {
-        let name = if let Some(name) = path.file_name().and_then(|x| x.to_str()) { name } else { return false };
+    fn dialog_error(&self, msg: &str) {
+        crate::dialog_error(msg)
+    }
 
-        let is_root = path.parent().map(|parent| parent.as_os_str().is_empty()).unwrap_or(true);
+    fn arc(&self) -> &LoadedArc {
+        resource::arc()
+    }
+}
 
-        let is_dot = name.starts_with('.');
+/// Mods are enabled by identity, so mods which share one are always enabled and disabled together
+fn warn_duplicate_ids(mods: &[(PathBuf, String)]) {
+    let mut roots_by_id: HashMap<&str, Vec<&PathBuf>> = HashMap::new();
 
-        let is_out_of_region = if let Some(index) = name.find('+') {
-            let (_, end) = name.split_at(index + 1);
-            !end.starts_with(&config::region_str())
-        } else {
-            false
-        };
+    for (root, id) in mods.iter() {
+        roots_by_id.entry(id.as_str()).or_default().push(root);
+    }
 
-        is_root || is_dot || is_out_of_region
-    };
+    let mut duplicates: Vec<(&str, Vec<&PathBuf>)> = roots_by_id.into_iter().filter(|(_, roots)| roots.len() > 1).collect();
+    duplicates.sort_unstable();
 
-    let collect = |x: &Path| {
-        match x.file_name() {
-            Some(name) if let Some(name) = name.to_str() => {
-                static RESERVED_NAMES: &[&str] = &[
-                    "config.json",
-                    "plugin.nro",
-                ];
-                static PATCH_EXTENSIONS: &[&str] = &[
-                    "prcx",
-                    "prcxml",
-                    "stdatx",
-                    "stdatxml",
-                    "stprmx",
-                    "stprmxml",
-
-                    "xmsbt"
-                ];
-                RESERVED_NAMES.contains(&name) || PATCH_EXTENSIONS.iter().any(|x| name.ends_with(x))
-            },
-            _ => false
+    for (id, roots) in duplicates {
+        let roots: Vec<String> = roots.iter().map(|root| format!("'{}'", root.display())).collect();
+        warn!(
+            "The mods {} all have the id '{}', so they can only be enabled together. Give them a different 'id' in their info.toml.",
+            roots.join(", "),
+            id
+        );
+    }
+}
+
+/// The outcome of the file discovery, for the filesystem to be built from
+pub struct Discovery {
+    pub launchpad: LaunchPad<ModLoader>,
+    /// Every collected path, from the highest to the lowest priority mod root
+    pub collected: Vec<(PathBuf, PathBuf)>,
+    pub hashed_sizes: HashMap<Hash40, usize>,
+    pub hashed_paths: HashMap<Hash40, PathBuf>,
+    /// Every discovered root, from the highest to the lowest priority
+    pub roots: Vec<PathBuf>,
+}
+
+pub fn perform_discovery() -> Discovery {
+    let platform = ConsolePlatform;
+    let is_emulator = is_emulator();
+
+    if is_emulator {
+        info!("Emulator usage detected in perform_discovery, reverting to old behavior.");
+    }
+
+    let legacy_discovery = config::legacy_discovery();
+
+    if !is_emulator {
+        // Open the ARCropolis menu if Minus is held before mod discovery
+        if ninput::any::is_down(ninput::Buttons::PLUS) {
+            crate::menus::show_main_menu();
         }
-    };
+    }
 
     let arc_path = config::arc_path();
     let umm_path = config::umm_path();
@@ -114,30 +158,25 @@ pub fn perform_discovery() -> LaunchPad<StandardLoader> {
     if !is_emulator && !legacy_discovery {
         let mut storage = config::GLOBAL_CONFIG.lock().unwrap();
         // Get the mod cache from last run
-        let mod_cache: HashSet<Hash40> = storage.get_field_json("mod_cache").unwrap_or_default();
+        let mod_cache: HashSet<String> = storage.get_field_json("mod_cache").unwrap_or_default();
 
         // Inspect the list of mods to see if some are new ones
-        let new_cache: HashSet<Hash40> = std::fs::read_dir(&umm_path)
+        let new_cache: HashSet<String> = std::fs::read_dir(&umm_path)
             .unwrap()
             .filter_map(|path| {
                 let path = PathBuf::from(&umm_path).join(path.unwrap().path());
 
-                if path.is_file() {
+                if path.is_file() && !archive::is_archive(&path) {
                     None
                 } else {
-                    Some(Hash40::from(path.to_str().unwrap()))
+                    Some(mod_id(&path))
                 }
             })
             .collect();
 
-        // Get the workspace name and workspace list
-        let workspace_name: String = storage.get_field("workspace").unwrap_or_else(|_| "Default".to_string());
-        let workspace_list: HashMap<String, String> = storage.get_field_json("workspace_list").unwrap_or_default();
-
-        // Get the preset name from the workspace list
-        let preset_name = &workspace_list[&workspace_name];
-        let mut presets: HashSet<Hash40> = storage.get_field_json(preset_name).unwrap_or_default();
-        let new_mods: HashSet<&Hash40> = new_cache
+        let preset_name = config::active_preset_name(&storage);
+        let mut presets: HashSet<String> = storage.get_field_json(&preset_name).unwrap_or_default();
+        let new_mods: Vec<&String> = new_cache
             .iter()
             .filter(|cached_mod| !mod_cache.contains(cached_mod) && !presets.contains(cached_mod))
             .collect();
@@ -145,9 +184,9 @@ pub fn perform_discovery() -> LaunchPad<StandardLoader> {
         // We found hashes that weren't in the cache
         if !new_mods.is_empty() && skyline_web::Dialog::yes_no("New mods have been detected.\nWould you like to enable them?") {
             // Add the new mods to the presets file
-            presets.extend(new_mods);
+            presets.extend(new_mods.into_iter().cloned());
             // Save it back
-            storage.set_field_json(preset_name, &presets).unwrap();
+            storage.set_field_json(&preset_name, &presets).unwrap();
         }
 
         // No matter what, the cache has to be updated
@@ -167,141 +206,187 @@ pub fn perform_discovery() -> LaunchPad<StandardLoader> {
 
     drop(storage);
 
-    let mut launchpad = LaunchPad::new(StandardLoader, ConflictHandler::NoRoot);
+    // The region is read once the user had the chance to adjust it
+    let region = platform.region_str();
+    let ignore = |path: &Path| pipeline::is_ignored(path, &region);
+
+    // Conflicts are resolved per file, so the first root to provide a file (the one with the highest priority) keeps it
+    let mut launchpad = LaunchPad::new(ModLoader, ConflictHandler::First);
 
-    launchpad.collecting(collect);
+    launchpad.collecting(pipeline::is_collected);
     launchpad.ignoring(ignore);
 
-    let mut conflicts = if std::fs::try_exists(arc_path).unwrap_or(false) {
-        launchpad.discover_in_root(config::arc_path())
-    } else {
-        Vec::new()
-    };
+    let mut roots = Vec::new();
 
-    if std::fs::try_exists(&umm_path).unwrap_or(false) {
-        conflicts.extend(launchpad.discover_roots(&umm_path, 1, filter));
+    if std::fs::try_exists(&arc_path).unwrap_or(false) {
+        roots.push(arc_path);
     }
 
-    for path in config::extra_paths() {
-        if std::fs::try_exists(&path).unwrap_or(false) {
-            conflicts.extend(launchpad.discover_roots(&path, 1, filter));
-        }
-    }
+    warn_duplicate_ids(installed_mod_ids());
 
-    let should_prompt = !conflicts.is_empty();
-
-    for conflict in conflicts.into_iter() {
-        match conflict {
-            ConflictKind::StandardConflict {
-                error_root,
-                source_root,
-                local,
-            } => {
-                warn!(
-                    "File '{}' was rejected for file '{}' during discovery.",
-                    error_root.join(&local).display(),
-                    source_root.join(local).display()
-                )
-            },
-            ConflictKind::RootConflict(root_path, kept) => {
-                warn!(
-                    "Mod root '{}' was rejected for a file conflict with '{}' during discovery.",
-                    root_path.display(),
-                    kept.display()
-                )
-            },
-        }
-    }
+    let installed: Vec<PathBuf> = installed_mod_ids().iter().map(|(root, _)| root.clone()).collect();
+
+    let mut mod_roots: Vec<PathBuf> = installed.iter().filter(|root| platform.is_mod_enabled(root)).cloned().collect();
+
+    // Required mods can only be enabled automatically when presets are in use
+    let violations = resolve_dependencies(&mut mod_roots, &installed, !is_emulator && !legacy_discovery);
+    report_dependency_violations(&violations, &mod_roots);
+
+    let mut mod_roots = pipeline::sort_by_priority(&platform, mod_roots);
+    apply_load_after(&mut mod_roots, &platform.mod_priority());
+    roots.extend(mod_roots);
+
+    // Files generated by plugins come last, so that they never override the mods picked by the user
+    roots.push(config::scratch_path());
+
+    // Only the roots which changed since the last boot have to be discovered again
+    let mut cache = DiscoveryCache::load();
+    let mut discovered = Vec::new();
+    let mut collected = Vec::new();
+    let mut conflicts = Vec::new();
 
-    if should_prompt
-        && skyline_web::Dialog::yes_no(
-            "During file discovery, ARCropolis encountered file conflicts.<br>Do you want to run it again to list all file conflicts?",
-        )
-    {
-        let mut launchpad = LaunchPad::new(StandardLoader, ConflictHandler::First);
+    for root in roots.iter() {
+        let fingerprint = cache::fingerprint(root);
 
-        let arc_path = config::arc_path();
+        if let Some(cached) = cache.get(root, &fingerprint) {
+            conflicts.extend(cached.insert_into(&mut launchpad, root));
+            collected.extend(cached.collected().iter().map(|local| (root.clone(), local.clone())));
+        } else if archive::is_archive(root) {
+            let (root_conflicts, root_collected) = archive::discover_archive(&mut launchpad, root, ignore, pipeline::is_collected);
 
-        launchpad.collecting(collect);
-        launchpad.ignoring(ignore);
+            let lost = lost_files(&root_conflicts, root);
 
-        let mut conflicts = if std::fs::try_exists(arc_path).unwrap_or(false) {
-            launchpad.discover_in_root(config::arc_path())
+            collected.extend(root_collected.into_iter().map(|local| (root.clone(), local)));
+            conflicts.extend(root_conflicts);
+            discovered.push((root.clone(), fingerprint, lost));
         } else {
-            Vec::new()
-        };
+            let previous = launchpad.collected_paths().len();
+            let root_conflicts = launchpad.discover_in_root(root);
 
-        if std::fs::try_exists(umm_path).unwrap_or(false) {
-            conflicts.extend(launchpad.discover_roots(config::umm_path(), 1, filter));
-        }
+            let lost = lost_files(&root_conflicts, root);
 
-        for path in config::extra_paths() {
-            if std::fs::try_exists(&path).unwrap_or(false) {
-                conflicts.extend(launchpad.discover_roots(&path, 1, filter));
-            }
+            collected.extend(launchpad.collected_paths()[previous..].iter().cloned());
+            conflicts.extend(root_conflicts);
+            discovered.push((root.clone(), fingerprint, lost));
         }
+    }
 
-        let mut conflict_map: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
-
-        for conflict in conflicts.into_iter() {
-            if let ConflictKind::StandardConflict {
-                error_root,
-                local,
-                source_root,
-            } = conflict
-            {
-                if let Some(conflicting_mods) = conflict_map.get_mut(&local) {
-                    conflicting_mods.push(error_root);
-                } else {
-                    conflict_map.insert(local, vec![source_root, error_root]);
-                }
-            }
-        }
+    if !discovered.is_empty() {
+        info!("Discovered {} mod roots which changed since the last boot.", discovered.len());
+    }
 
-        let should_log = match serde_json::to_string_pretty(&conflict_map) {
-            Ok(json) => match std::fs::write("sd:/ultimate/arcropolis/conflicts.json", json.as_bytes()) {
-                Ok(_) => {
-                    crate::dialog_error("Please check sd:/ultimate/arcropolis/conflicts.json for all of the file conflicts.");
-                    false
-                },
-                Err(e) => {
-                    crate::dialog_error(format!(
-                        "Failed to write conflict map to sd:/ultimate/arcropolis/conflicts.json<br>{:?}",
-                        e
-                    ));
-                    true
-                },
-            },
-            Err(e) => {
-                crate::dialog_error(format!("Failed to serialize conflict map to JSON. {:?}", e));
-                true
-            },
-        };
+    cache.update(launchpad.tree(), discovered, &collected, &roots);
+    cache.save();
 
-        if should_log {
-            for (local, roots) in conflict_map {
-                error!("The file {} is used by the following roots:", local.display());
-                for root in roots {
-                    error!("{}", root.display());
-                }
-            }
-        }
+    let (hashed_sizes, hashed_paths) = cache.hash_maps(&roots);
+
+    let report = ConflictReport::from_conflicts(conflicts);
+
+    if !report.is_empty() {
+        warn!(
+            "During file discovery, ARCropolis resolved file conflicts by mod priority. See {} for the details.",
+            CONFLICTS_PATH
+        );
     }
 
+    report.save();
+
     match mount_prebuilt_nrr(launchpad.tree()) {
         Ok(Some(_)) => info!("Successfully registered fighter modules."),
         Ok(_) => info!("No fighter modules found to register."),
         Err(e) => {
             error!("{:?}", e);
-            crate::dialog_error(
+            platform.dialog_error(
                 "ARCropolis failed to register module information for fighter modules.<br>You may experience infinite loading on some fighters.",
             );
         },
     }
 
-    load_and_run_plugins(launchpad.collected_paths());
+    load_and_run_plugins(&collected);
+
+    Discovery {
+        launchpad,
+        collected,
+        hashed_sizes,
+        hashed_paths,
+        roots,
+    }
+}
+
+/// Gets the files of a mod root which lost a conflict to a mod root with a higher priority
+fn lost_files(conflicts: &[ConflictKind], root: &Path) -> Vec<PathBuf> {
+    conflicts
+        .iter()
+        .filter_map(|conflict| match conflict {
+            ConflictKind::StandardConflict { error_root, local, .. } if error_root == root => Some(local.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Gets every mod root directly inside of the provided directory, in a stable order, registering the ones that are archives
+fn collect_mod_roots<P: AsRef<Path>>(path: P) -> Vec<PathBuf> {
+    let roots = pipeline::collect_mod_roots(path.as_ref(), |_| true).unwrap_or_else(|e| {
+        error!("Failed to read mod directory '{}'. Reason: {:?}", path.as_ref().display(), e);
+        Vec::new()
+    });
+
+    for root in roots.iter().filter(|root| archive::is_archive(root) && root.is_file()) {
+        archive::register_archive_root(root);
+    }
+
+    roots
+}
+
+/// Gets the roots of every installed mod, whether it is enabled or not, from the mods folder and the extra paths
+pub fn installed_mod_roots(extra_paths: &[String]) -> Vec<PathBuf> {
+    let mut installed = Vec::new();
+    let umm_path = config::umm_path();
+
+    if std::fs::try_exists(&umm_path).unwrap_or(false) {
+        installed.extend(collect_mod_roots(&umm_path));
+    }
 
-    launchpad
+    for path in extra_paths.iter() {
+        if std::fs::try_exists(&path).unwrap_or(false) {
+            installed.extend(collect_mod_roots(&path));
+        }
+    }
+
+    installed
+}
+
+/// Logs the dependency violations of the enabled mods and informs the user of the ones that could not be fixed.
+/// Mods that were enabled to fulfill a requirement are added to the active preset.
+fn report_dependency_violations(violations: &[DependencyViolation], enabled: &[PathBuf]) {
+    let mut unresolved = Vec::new();
+
+    for violation in violations.iter() {
+        match violation {
+            DependencyViolation::AutoEnabled { .. } => info!("{}.", violation),
+            _ => {
+                warn!("{}.", violation);
+                unresolved.push(violation);
+            },
+        }
+    }
+
+    if violations.iter().any(|x| matches!(x, DependencyViolation::AutoEnabled { .. })) {
+        // Identified before locking the configuration, which the installed mods are read with
+        let ids: Vec<String> = enabled.iter().map(|root| cached_mod_id(root)).collect();
+
+        let mut storage = config::GLOBAL_CONFIG.lock().unwrap();
+        let preset_name = config::active_preset_name(&storage);
+
+        let mut presets: HashSet<String> = storage.get_field_json(&preset_name).unwrap_or_default();
+        presets.extend(ids);
+        storage.set_field_json(&preset_name, &presets).unwrap();
+        storage.flush();
+    }
+
+    if !unresolved.is_empty() {
+        skyline_web::DialogOk::ok(&violations_dialog_text(unresolved, "These mods might not work properly."));
+    }
 }
 
 fn mount_prebuilt_nrr<A: FileLoader>(tree: &Tree<A>) -> Result<Option<RegistrationInfo>, NrrRegistrationFailedError>
@@ -314,7 +399,7 @@ where
     tree.walk_paths(|node, entry_type| match node.get_local().parent() {
         Some(parent) if entry_type.is_file() && parent == fighter_nro_parent => {
             info!("Reading '{}' for module registration.", node.full_path().display());
-            if let Ok(data) = std::fs::read(node.full_path()) {
+            if let Ok(data) = archive::read_mod_file(node.full_path()) {
                 fighter_nro_nrr.add_module(data.as_slice());
             }
         },
@@ -332,7 +417,7 @@ pub fn load_and_run_plugins(plugins: &[(PathBuf, PathBuf)]) {
         .filter_map(|(root, local)| {
             let full_path = root.join(local);
 
-            if full_path.exists() && full_path.ends_with("plugin.nro") {
+            if archive::mod_file_exists(&full_path) && full_path.ends_with("plugin.nro") {
                 match NroBuilder::open(&full_path) {
                     Ok(builder) => {
                         info!("Loaded plugin at '{}' for chainloading.", full_path.display());
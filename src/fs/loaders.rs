@@ -1,4 +1,4 @@
-use std::collections::VecDeque;
+use std::{cell::RefCell, collections::VecDeque};
 
 use msbt::{builder::MsbtBuilder, Msbt};
 use serde::*;
@@ -17,7 +17,11 @@ pub struct Xmsbt {
 pub struct Entry {
     label: String,
     #[serde(rename = "text")]
-    text: Text,
+    text: Option<Text>,
+    /// Removes the label instead of setting its text. Labels added by earlier patches are dropped entirely, while labels from the
+    /// base MSBT are emptied since the label groups of the MSBT cannot be rebuilt without them.
+    #[serde(default)]
+    remove: bool,
 }
 
 #[derive(Deserialize, Debug)]
@@ -26,6 +30,49 @@ pub struct Text {
     value: String,
 }
 
+impl Xmsbt {
+    /// Decodes the raw bytes of a XMSBT file, which can either be UTF-16 with a byte order mark or UTF-8
+    pub fn decode(data: &[u8]) -> Result<String, ApiLoaderError> {
+        let utf16 = |data: &[u8], from_bytes: fn([u8; 2]) -> u16| {
+            let units: Vec<u16> = data.chunks_exact(2).map(|x| from_bytes([x[0], x[1]])).collect();
+            String::from_utf16(&units).map_err(|_| ApiLoaderError::Other("Invalid UTF-16 data in XMSBT file!".to_string()))
+        };
+
+        match data {
+            [0xFF, 0xFE, rest @ ..] => utf16(rest, u16::from_le_bytes),
+            [0xFE, 0xFF, rest @ ..] => utf16(rest, u16::from_be_bytes),
+            [0xEF, 0xBB, 0xBF, rest @ ..] | rest => {
+                String::from_utf8(rest.to_vec()).map_err(|_| ApiLoaderError::Other("Invalid UTF-8 data in XMSBT file!".to_string()))
+            },
+        }
+    }
+
+    /// Encodes a string the way it is stored in a MSBT, as null terminated UTF-16
+    fn encode_label_value(value: &str) -> Vec<u8> {
+        value.encode_utf16().chain(std::iter::once(0)).flat_map(u16::to_le_bytes).collect()
+    }
+
+    /// Adds the labels set or removed by this patch to the ones of the previous patches, where None means that the label
+    /// was removed. Returns the labels which were skipped because they have no text.
+    fn collect_labels(self, labels: &mut HashMap<String, Option<String>>) -> Vec<String> {
+        let mut skipped = Vec::new();
+
+        for entry in self.entries {
+            match (entry.remove, entry.text) {
+                (true, _) => {
+                    labels.insert(entry.label, None);
+                },
+                (false, Some(text)) => {
+                    labels.insert(entry.label, Some(text.value));
+                },
+                (false, None) => skipped.push(entry.label),
+            }
+        }
+
+        skipped
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum ApiLoaderError {
     #[error("Error loading file from the data.arc.")]
@@ -122,13 +169,7 @@ impl ApiLoadType {
                     .map_err(|_| ApiLoaderError::Other("Unable to parse param data!".to_string()))?;
 
                 for patch_path in patches.iter() {
-                    let patch = if let Ok(patch) = prcx::open(patch_path) {
-                        patch
-                    } else {
-                        let file = std::fs::File::open(patch_path)?;
-                        let mut reader = std::io::BufReader::new(file);
-                        prcx::read_xml(&mut reader).map_err(|_| ApiLoaderError::Other("Unable to parse param patch data!".to_string()))?
-                    };
+                    let patch = ApiLoader::open_prc_patch(patch_path)?;
                     prcx::apply_patch(&patch, &mut param_data).map_err(|_| ApiLoaderError::Other("Unable to patch param data!".to_string()))?;
                 }
 
@@ -144,101 +185,67 @@ impl ApiLoadType {
                     return Err(ApiLoaderError::Other("No patches found for file in MSBT patch!".to_string()));
                 };
 
-                let mut labels: HashMap<String, String> = HashMap::new();
+                // None means that the label was removed by one of the patches
+                let mut labels: HashMap<String, Option<String>> = HashMap::new();
 
                 for patch_path in patches.iter() {
-                    let data = &std::fs::read(patch_path).unwrap()[2..];
-
-                    let slice_u16: &[u16] = unsafe {
-                        std::slice::from_raw_parts(
-                            data.as_ptr() as *const u16,
-                            data.len() / std::mem::size_of::<u16>(),
-                        )
+                    let xml = match super::archive::read_mod_file(patch_path).map_err(ApiLoaderError::from).and_then(|data| Xmsbt::decode(&data)) {
+                        Ok(xml) => xml,
+                        Err(e) => {
+                            error!("XMSBT file `{}` could not be read, skipping. Reason: {:?}", patch_path.display(), e);
+                            continue;
+                        },
                     };
 
-                    let xml = String::from_utf16(slice_u16).unwrap();
-
                     let xmsbt: Xmsbt = match serde_xml_rs::from_str(&xml) {
                         Ok(xmsbt) => xmsbt,
                         Err(err) => {
                             match err {
                                 serde_xml_rs::Error::Syntax { source }  => {
                                     let position = source.position();
-                                    warn!("XMSBT file `{}` could not be read due to the following syntax error at line {}, column {}: `{}`, skipping.", patch_path.display(), position.row + 1, position.column, source.msg())
+                                    error!("XMSBT file `{}` could not be read due to the following syntax error at line {}, column {}: `{}`, skipping.", patch_path.display(), position.row + 1, position.column, source.msg())
                                 },
-                                _ => warn!("XMSBT file `{}` is malformed, skipping.", patch_path.display()),
+                                _ => error!("XMSBT file `{}` is malformed, skipping.", patch_path.display()),
                             }
                             continue;
                         },
                     };
 
-                    for entry in &xmsbt.entries {
-                        labels.insert(entry.label.to_owned(), entry.text.value.to_owned());
+                    for label in xmsbt.collect_labels(&mut labels) {
+                        warn!("Label `{}` in XMSBT file `{}` has no text, skipping.", label, patch_path.display());
                     }
                 }
 
                 let data = ApiLoader::handle_load_base_file(local)?;
 
-                let mut msbt = Msbt::from_reader(std::io::Cursor::new(&data)).unwrap();
-
-                for lbl in msbt.lbl1_mut().unwrap().labels_mut() {
-                    if labels.contains_key(&lbl.name().to_owned()) {
-                        let mut str_val: Vec<u16> = labels[&lbl.name().to_owned()].encode_utf16().collect();
-                        str_val.push(0);
+                let mut msbt = Msbt::from_reader(std::io::Cursor::new(&data)).map_err(|e| ApiLoaderError::Other(format!("Unable to parse MSBT data! {:?}", e)))?;
 
-                        let slice_u8: &[u8] = unsafe {
-                            std::slice::from_raw_parts(
-                                str_val.as_ptr() as *const u8,
-                                str_val.len() * std::mem::size_of::<u16>(),
-                            )
-                        };
-
-                        lbl.set_value_raw(slice_u8).unwrap();
-                        labels.remove(&lbl.name().to_owned());
+                // Overwriting the value of an existing label keeps its attributes and style
+                let lbl1 = msbt.lbl1_mut().ok_or_else(|| ApiLoaderError::Other("MSBT file has no label section!".to_string()))?;
+                for lbl in lbl1.labels_mut() {
+                    if let Some(value) = labels.remove(lbl.name()) {
+                        let value = Xmsbt::encode_label_value(value.as_deref().unwrap_or_default());
+                        if let Err(e) = lbl.set_value_raw(value.as_slice()) {
+                            error!("Failed to set the value of label `{}` in `{}`. Reason: {:?}", lbl.name(), local.display(), e);
+                        }
                     }
                 }
 
                 let mut builder = MsbtBuilder::from(msbt);
 
-                for lbl in labels {
-                    let mut str_val: Vec<u16> = lbl.1
-                            .encode_utf16()
-                            .collect();
-                        str_val.push(0);
-
-                        let slice_u8: &[u8] = unsafe {
-                            std::slice::from_raw_parts(
-                                str_val.as_ptr() as *const u8,
-                                str_val.len() * std::mem::size_of::<u16>(),
-                            )
-                        };
-                    builder = builder.add_label(lbl.0, slice_u8);
+                for (label, value) in labels {
+                    if let Some(value) = value {
+                        builder = builder.add_label(label, Xmsbt::encode_label_value(&value).as_slice());
+                    }
                 }
 
                 let out_msbt = builder.build();
 
                 let mut cursor = std::io::Cursor::new(vec![]);
-                out_msbt.write_to(&mut cursor).unwrap();
+                out_msbt.write_to(&mut cursor).map_err(|e| ApiLoaderError::Other(format!("Unable to write MSBT data! {:?}", e)))?;
                 let vec = cursor.into_inner();
                 Ok((vec.len(), vec))
             },
-            ApiLoadType::Generic if let ApiCallback::GenericCallback(cb) = usr_fn => {
-                let hash = local.smash_hash()?;
-                let mut size = 0;
-                if !crate::api::file::arcrop_get_decompressed_size(hash, &mut size) {
-                    return Err(ApiLoaderError::Other("Unable to create buffer!".to_string()));
-                }
-                let mut vec = Vec::with_capacity(size);
-                unsafe {
-                    let mut new_len = size;
-                    if !cb(hash.0, vec.as_mut_ptr(), size, &mut new_len) {
-                        return Err(ApiLoaderError::Other("Callback did not load file!".to_string()));
-                    }
-                    vec.set_len(new_len);
-                }
-                Ok((size, vec))
-            },
-            ApiLoadType::Generic => Err(ApiLoaderError::InvalidCb),
             ApiLoadType::Stream if let ApiCallback::StreamCallback(cb) = usr_fn => {
                 let hash = local.smash_hash()?;
                 let mut vec = Vec::with_capacity(0x100);
@@ -252,16 +259,24 @@ impl ApiLoadType {
                 Ok((file_size, vec))
             },
             ApiLoadType::Stream => Err(ApiLoaderError::InvalidCb),
+            // Generic and extension callbacks receive the data of the layer beneath them, so they are handled by the loader itself
+            ApiLoadType::Generic | ApiLoadType::Extension => Err(ApiLoaderError::InvalidCb),
             _ => Err(ApiLoaderError::Other("Unimplemented ApiLoadType!".to_string()))
         }
     }
 }
 
+thread_local! {
+    /// The data beneath the generic and extension callbacks running on this thread, from the outermost to the innermost callback
+    static NEXT_LAYERS: RefCell<Vec<(Hash40, Vec<u8>)>> = RefCell::new(Vec::new());
+}
+
 #[derive(Copy, Clone)]
 pub enum ApiCallback {
     None,
     GenericCallback(arcropolis_api::CallbackFn),
     StreamCallback(arcropolis_api::StreamCallbackFn),
+    ExtensionCallback(arcropolis_api::CallbackFn),
 }
 
 #[repr(transparent)]
@@ -337,7 +352,8 @@ impl ApiLoader {
 
         let cached = filesystem.get();
         if cached.get_patch_entry_type(local).is_ok() {
-            cached.load_patch(local).map_err(|x| ApiLoaderError::Other(format!("{:?}", x)))
+            let data = cached.load_patch(local).map_err(|x| ApiLoaderError::Other(format!("{:?}", x)))?;
+            super::utils::decompress_if_compressed(local, data)
         } else {
             Self::handle_load_vanilla_file(local)
         }
@@ -359,6 +375,44 @@ impl ApiLoader {
         cached.virt().loader.msbt_patches.get(&hash)
     }
 
+    /// Reads a PRC patch file, in either the binary or the XML format
+    pub fn open_prc_patch(path: &Path) -> Result<prcx::ParamStruct, ApiLoaderError> {
+        // Patches can be packed in a mod archive, so they are read in full before being parsed
+        let data = super::archive::read_mod_file(path)?;
+
+        if let Ok(patch) = prcx::read_stream(&mut std::io::Cursor::new(&data)) {
+            Ok(patch)
+        } else {
+            prcx::read_xml(&mut std::io::Cursor::new(&data)).map_err(|_| ApiLoaderError::Other("Unable to parse param patch data!".to_string()))
+        }
+    }
+
+    /// Gets the path of every param edited by a PRC patch, such as `.0x0123456789[2].0x0abcdef012`
+    pub fn prc_patch_keys(patch: &prcx::ParamStruct) -> Vec<String> {
+        fn walk(param: &prcx::ParamKind, key: String, keys: &mut Vec<String>) {
+            match param {
+                prcx::ParamKind::Struct(children) => {
+                    for (hash, child) in children.0.iter() {
+                        walk(child, format!("{}.{:#x}", key, hash.0), keys);
+                    }
+                },
+                prcx::ParamKind::List(children) => {
+                    for (idx, child) in children.0.iter().enumerate() {
+                        walk(child, format!("{}[{}]", key, idx), keys);
+                    }
+                },
+                _ => keys.push(key),
+            }
+        }
+
+        let mut keys = Vec::new();
+        for (hash, child) in patch.0.iter() {
+            walk(child, format!(".{:#x}", hash.0), &mut keys);
+        }
+        keys
+    }
+
+    /// Patches are applied in the order they are inserted, so the last one inserted wins any overlapping edit
     pub fn insert_prc_patch(&mut self, hash: Hash40, path: &Path) {
         if let Some(list) = self.param_patches.get_mut(&hash) {
             list.push(path.to_path_buf())
@@ -368,13 +422,95 @@ impl ApiLoader {
     }
 
     pub fn insert_msbt_patch(&mut self, hash: Hash40, path: &Path) {
-        if let Some(list) = self.msbt_patches.get_mut(&hash) {
-            list.push(path.to_path_buf())
+        let list = self.msbt_patches.entry(hash).or_default();
+
+        // Regional patches are applied after the regular ones so that they take priority, like regional files do
+        if path.file_name().and_then(|x| x.to_str()).map_or(false, |x| x.contains('+')) {
+            list.push(path.to_path_buf());
+        } else {
+            let idx = list
+                .iter()
+                .position(|x| x.file_name().and_then(|x| x.to_str()).map_or(false, |x| x.contains('+')))
+                .unwrap_or(list.len());
+            list.insert(idx, path.to_path_buf());
+        }
+    }
+
+    /// Gets every file that is generated by applying PRC or MSBT patches to it
+    pub fn patched_files(&self) -> impl Iterator<Item = Hash40> + '_ {
+        self.param_patches.keys().chain(self.msbt_patches.keys()).copied()
+    }
+
+    /// Gets the PRC and MSBT patches applied to a file
+    pub fn patches_for(&self, hash: Hash40) -> impl Iterator<Item = &PathBuf> {
+        self.param_patches.get(&hash).into_iter().chain(self.msbt_patches.get(&hash)).flatten()
+    }
+
+    /// Whether a plugin registered a callback for a file, whose data cannot be known without running it
+    pub fn has_callback(&self, hash: Hash40) -> bool {
+        self.function_map.get(&hash).map_or(false, |entry| {
+            unsafe { (*entry.get()).functions.iter().any(|(_, cb)| !matches!(cb, ApiCallback::None)) }
+        })
+    }
+
+    /// Loads the data beneath the current entry of the function queue: the rest of the queue, then the mods, then the game
+    fn load_next_layer(&self, root_path: &Path, local: &Path) -> Result<Vec<u8>, ApiLoaderError> {
+        match self.load_path(root_path, local) {
+            Err(ApiLoaderError::NoVirtFile) => Self::handle_load_base_file(local),
+            result => result,
+        }
+    }
+
+    /// Runs a generic or extension callback over the data of the layer beneath it, which is also available to the callback
+    /// through `arcrop_load_next_layer`. Since the queue is walked from the last registered callback, the callbacks run in
+    /// registration order. A callback that doesn't load the file leaves the data of the layer beneath untouched.
+    fn load_layered_callback(&self, root_path: &Path, local: &Path, usr_fn: ApiCallback) -> Result<Vec<u8>, ApiLoaderError> {
+        let cb = match usr_fn {
+            ApiCallback::GenericCallback(cb) | ApiCallback::ExtensionCallback(cb) => cb,
+            _ => return Err(ApiLoaderError::InvalidCb),
+        };
+
+        let hash = local.smash_hash()?;
+
+        // Files which only exist through a callback have nothing beneath them
+        let data = self.load_next_layer(root_path, local).unwrap_or_default();
+        let data_len = data.len();
+
+        // The file can be as big as the buffer the game allocates for it, which is the size in the tables once they are patched.
+        // They are read directly, since the API functions are not meant to be called from inside of a load.
+        let capacity = resource::arc()
+            .get_file_data_from_hash(hash, config::region())
+            .map_or(0, |data| data.decomp_size as usize)
+            .max(data_len);
+
+        let mut buffer = data.clone();
+        buffer.resize(capacity, 0);
+
+        NEXT_LAYERS.with(|layers| layers.borrow_mut().push((hash, data)));
+
+        let mut new_len = data_len;
+        let loaded = unsafe { cb(hash.0, buffer.as_mut_ptr(), capacity, &mut new_len) };
+
+        let data = NEXT_LAYERS.with(|layers| layers.borrow_mut().pop()).map(|(_, data)| data).unwrap_or_default();
+
+        if loaded {
+            buffer.truncate(new_len.min(capacity));
+            Ok(buffer)
         } else {
-            self.msbt_patches.insert(hash, vec![path.to_path_buf()]);
+            Ok(data)
         }
     }
 
+    /// Whether a generic or extension callback is running on this thread
+    pub fn in_layered_callback() -> bool {
+        NEXT_LAYERS.with(|layers| !layers.borrow().is_empty())
+    }
+
+    /// Gets the data of the layer beneath the callback currently running for the file on this thread
+    pub fn next_layer(hash: Hash40) -> Option<Vec<u8>> {
+        NEXT_LAYERS.with(|layers| layers.borrow().iter().rev().find(|(x, _)| *x == hash).map(|(_, data)| data.clone()))
+    }
+
     fn get_stream_cb_path(&self, local: &Path) -> Option<String> {
         if let Some((root_path, callback)) = self.use_virtual_file(local) {
             let result = match ApiLoadType::from_root(root_path) {
@@ -449,6 +585,7 @@ impl FileLoader for ApiLoader {
     fn load_path(&self, _root_path: &Path, local_path: &Path) -> Result<Vec<u8>, Self::ErrorType> {
         if let Some((root_path, callback)) = self.use_virtual_file(local_path) {
             let result = match ApiLoadType::from_root(root_path) {
+                Ok(ApiLoadType::Generic | ApiLoadType::Extension) => self.load_layered_callback(root_path, local_path, callback),
                 Ok(ty) => ty
                     .load_path(local_path, callback)
                     .map_or_else(|_| self.load_path(root_path, local_path), |(_, data)| Ok(data)),
@@ -526,3 +663,64 @@ impl FileLoader for ArcLoader {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(text: &str, bom: [u8; 2], to_bytes: fn(u16) -> [u8; 2]) -> Vec<u8> {
+        bom.iter().copied().chain(text.encode_utf16().flat_map(to_bytes)).collect()
+    }
+
+    #[test]
+    fn decodes_every_supported_encoding() {
+        let text = "<xmsbt>Lüigi</xmsbt>";
+
+        assert_eq!(Xmsbt::decode(&utf16(text, [0xFF, 0xFE], u16::to_le_bytes)).unwrap(), text);
+        assert_eq!(Xmsbt::decode(&utf16(text, [0xFE, 0xFF], u16::to_be_bytes)).unwrap(), text);
+        assert_eq!(Xmsbt::decode(text.as_bytes()).unwrap(), text);
+
+        let with_bom: Vec<u8> = [0xEF, 0xBB, 0xBF].iter().chain(text.as_bytes()).copied().collect();
+        assert_eq!(Xmsbt::decode(&with_bom).unwrap(), text);
+    }
+
+    #[test]
+    fn refuses_invalid_data() {
+        // An unpaired surrogate
+        assert!(Xmsbt::decode(&[0xFF, 0xFE, 0x00, 0xD8]).is_err());
+        assert!(Xmsbt::decode(&[0xC3, 0x28]).is_err());
+    }
+
+    #[test]
+    fn encodes_label_values_as_null_terminated_utf16() {
+        assert_eq!(Xmsbt::encode_label_value("Hi"), vec![b'H', 0, b'i', 0, 0, 0]);
+        assert_eq!(Xmsbt::encode_label_value(""), vec![0, 0]);
+    }
+
+    #[test]
+    fn later_patches_set_and_remove_labels() {
+        let first: Xmsbt = serde_xml_rs::from_str(
+            r#"<xmsbt>
+                <entry label="nam_chr1_00_mario"><text>Mario</text></entry>
+                <entry label="nam_chr1_00_luigi"><text>Luigi</text></entry>
+                <entry label="nam_chr1_00_peach"></entry>
+            </xmsbt>"#,
+        )
+        .unwrap();
+        let second: Xmsbt = serde_xml_rs::from_str(
+            r#"<xmsbt>
+                <entry label="nam_chr1_00_mario" remove="true"/>
+                <entry label="nam_chr1_00_luigi"><text>Weegee</text></entry>
+            </xmsbt>"#,
+        )
+        .unwrap();
+
+        let mut labels = HashMap::new();
+        assert_eq!(first.collect_labels(&mut labels), vec![String::from("nam_chr1_00_peach")]);
+        assert!(second.collect_labels(&mut labels).is_empty());
+
+        assert_eq!(labels.len(), 2);
+        assert_eq!(labels["nam_chr1_00_mario"], None);
+        assert_eq!(labels["nam_chr1_00_luigi"].as_deref(), Some("Weegee"));
+    }
+}
@@ -0,0 +1,493 @@
+//! The parts of the discovery and patching pipeline which only work over paths, the configs of the mods and the tables of a
+//! `data.arc`. The plugin and the simulator both build on them, and ask everything else they need of a `Platform`.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    io,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use orbits::{FileLoader, Tree};
+use serde::Deserialize;
+use smash_arc::{serde::Hash40String, ArcLookup, FilePathIdx, Hash40, LookupError, Region};
+
+use crate::replacement::config::ModConfig;
+
+pub const REGIONS: &[&str] = &[
+    "jp_ja", "us_en", "us_fr", "us_es", "eu_en", "eu_fr", "eu_es", "eu_de", "eu_nl", "eu_it", "eu_ru", "kr_ko", "zh_cn", "zh_tw",
+];
+
+/// Files that are collected for ARCropolis itself instead of being loaded by the game
+pub static RESERVED_NAMES: &[&str] = &["config.json", "plugin.nro"];
+
+/// Files that patch the file of the game they are named after, see `patch_target`
+pub static PATCH_EXTENSIONS: &[&str] = &["prcx", "prcxml", "stdatx", "stdatxml", "stprmx", "stprmxml", "xmsbt"];
+
+/// Everything the pipeline asks of the console, so that it can be answered by something else
+pub trait Platform {
+    type Arc: ArcLookup;
+
+    /// The region and language of the game, such as `us_en`
+    fn region_str(&self) -> String;
+    /// The mod roots of the active workspace, from highest to lowest load priority, by identity (see `mod_id`)
+    fn mod_priority(&self) -> Vec<String>;
+    /// Gets what identifies a mod root in the presets and the priority
+    fn mod_id(&self, root: &Path) -> String;
+    /// Whether the mod root is enabled in the active workspace
+    fn is_mod_enabled(&self, root: &Path) -> bool;
+    /// Informs the user of an issue
+    fn dialog_error(&self, msg: &str);
+    /// The tables of the game, before any mod edits them
+    fn arc(&self) -> &Self::Arc;
+}
+
+/// Gets the region of the game from its name, or `Region::None` for unknown regions
+pub fn region_from_str(region: &str) -> Region {
+    Region::from(REGIONS.iter().position(|&x| x == region).map(|x| (x + 1) as u32).unwrap_or(0))
+}
+
+/// Whether a mod root is a zip archive rather than a folder
+pub fn is_archive<P: AsRef<Path>>(root: P) -> bool {
+    root.as_ref().extension().map_or(false, |ext| ext == "zip")
+}
+
+/// Gets the name other mods refer to a mod root by, which leaves out the extension of mod archives
+pub fn folder_name(root: &Path) -> &str {
+    let name = if is_archive(root) { root.file_stem() } else { root.file_name() };
+    name.and_then(|name| name.to_str()).unwrap_or_default()
+}
+
+#[derive(Deserialize)]
+struct ModIdentity {
+    id: Option<String>,
+}
+
+/// Gets what identifies a mod from the content of its `info.toml`, which is the `id` of it or its folder name when it has none.
+/// Unlike the path of the mod, this does not change when the mod is moved to another mods folder.
+pub fn mod_id_from_info(root: &Path, info: Option<&[u8]>) -> String {
+    info.and_then(|data| toml::from_str::<ModIdentity>(&String::from_utf8_lossy(data)).ok())
+        .and_then(|info| info.id)
+        .filter(|id| !id.trim().is_empty())
+        .unwrap_or_else(|| folder_name(root).to_string())
+}
+
+/// Legacy filter, used when presets are not, which loads the mod except if it has a period at the start of the name
+pub fn is_enabled_by_name(root: &Path) -> bool {
+    root.file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| !name.starts_with('.'))
+        .unwrap_or(false)
+}
+
+/// Gets every mod root directly inside of the provided directory which passes the filter, in a stable order
+pub fn collect_mod_roots<P: AsRef<Path>, F: Fn(&Path) -> bool>(path: P, filter: F) -> io::Result<Vec<PathBuf>> {
+    let mut roots: Vec<PathBuf> = std::fs::read_dir(path.as_ref())?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| (path.is_dir() || is_archive(path)) && filter(path))
+        .collect();
+
+    roots.sort();
+    Ok(roots)
+}
+
+/// Orders the mod roots by the priority of their identity, with the roots that have no priority being placed after the others
+pub fn sort_by_priority<P: Platform>(platform: &P, mut roots: Vec<PathBuf>) -> Vec<PathBuf> {
+    let priority = platform.mod_priority();
+
+    roots.sort_by_cached_key(|root| {
+        let id = platform.mod_id(root);
+        priority.iter().position(|x| *x == id).unwrap_or(priority.len())
+    });
+    roots
+}
+
+/// Whether a path of a mod root is left out of the discovery, which is the case for the files at the top of the root,
+/// hidden files and files which are specific to another region
+pub fn is_ignored(path: &Path, region: &str) -> bool {
+    let name = if let Some(name) = path.file_name().and_then(|x| x.to_str()) { name } else { return false };
+
+    let is_root = path.parent().map(|parent| parent.as_os_str().is_empty()).unwrap_or(true);
+
+    let is_dot = name.starts_with('.');
+
+    let is_out_of_region = if let Some(index) = name.find('+') {
+        let (_, end) = name.split_at(index + 1);
+        !end.starts_with(region)
+    } else {
+        false
+    };
+
+    is_root || is_dot || is_out_of_region
+}
+
+/// Whether a path of a mod root is collected for ARCropolis instead of being added to the filesystem
+pub fn is_collected(path: &Path) -> bool {
+    match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => RESERVED_NAMES.contains(&name) || PATCH_EXTENSIONS.iter().any(|x| name.ends_with(x)),
+        None => false,
+    }
+}
+
+pub fn is_stream(path: &Path) -> bool {
+    static VALID_PREFIXES: &[&str] = &["/stream;", "/stream:", "stream;", "stream:"];
+
+    VALID_PREFIXES.iter().any(|x| path.starts_with(*x))
+}
+
+/// Whether a mod file is compressed with zstd, which is told by its `.zst` suffix rather than by its data, since a file
+/// of the game could start with the same bytes as a zstd frame
+pub fn is_compressed(local: &Path) -> bool {
+    local.extension().map_or(false, |ext| ext == "zst")
+}
+
+/// Gets the path of the file that a mod file stands for, which is the path without the suffix for compressed files
+pub fn uncompressed_path(local: &Path) -> PathBuf {
+    if is_compressed(local) {
+        local.with_extension("")
+    } else {
+        local.to_path_buf()
+    }
+}
+
+/// The magic number every zstd frame starts with
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+pub fn is_zstd(data: &[u8]) -> bool {
+    data.starts_with(&ZSTD_MAGIC)
+}
+
+/// Reads the decompressed size from the header of a zstd frame, if the compressor stored it
+pub fn zstd_content_size(header: &[u8]) -> Option<usize> {
+    if !is_zstd(header) {
+        return None;
+    }
+
+    let descriptor = *header.get(4)?;
+    let single_segment = descriptor & 0x20 != 0;
+    let dictionary_id_size = [0, 1, 2, 4][(descriptor & 0x3) as usize];
+    let content_size_size = match descriptor >> 6 {
+        0 if single_segment => 1,
+        0 => return None,
+        1 => 2,
+        2 => 4,
+        _ => 8,
+    };
+
+    // The window descriptor is only there when the frame is not a single segment
+    let start = 5 + usize::from(!single_segment) + dictionary_id_size;
+    let field = header.get(start..start + content_size_size)?;
+    let size = field.iter().rev().fold(0u64, |size, byte| (size << 8) | u64::from(*byte));
+
+    // Two byte sizes are stored with an offset of 256, since smaller sizes fit in a single byte
+    Some(if content_size_size == 2 { size + 256 } else { size } as usize)
+}
+
+/// Gets where the region suffix of a path is, such as the `+us_en` of `model+us_en.numdlb`. Suffixes are a `+` followed by
+/// five characters, but the range stops at the end of the path when fewer follow.
+fn region_suffix(path: &str) -> Option<Range<usize>> {
+    let start = path.find('+')?;
+    let end = path[start..].char_indices().nth(6).map_or(path.len(), |(idx, _)| start + idx);
+    Some(start..end)
+}
+
+/// Gets the region a file is specific to, such as `us_en` for `msg_name+us_en.xmsbt`
+#[cfg_attr(not(target_os = "switch"), allow(dead_code))]
+pub fn file_region(local: &Path) -> Option<&str> {
+    let name = local.file_name()?.to_str()?;
+    region_suffix(name).map(|range| &name[range.start + 1..range.end])
+}
+
+/// Removes the region suffix of a file name, if it has one
+fn strip_region(name: &str) -> String {
+    let mut name = name.to_string();
+
+    if let Some(range) = region_suffix(&name) {
+        name.replace_range(range, "");
+    }
+
+    name
+}
+
+/// Hashes a local path into the hash of the file it stands for, ignoring the region of regional files
+pub fn smash_hash(path: &Path) -> Option<Hash40> {
+    if path.extension().is_none() {
+        let hash = path
+            .file_name()
+            .and_then(|x| x.to_str())
+            .filter(|x| x.starts_with("0x"))
+            .and_then(|x| u64::from_str_radix(x.trim_start_matches("0x"), 16).ok());
+
+        if let Some(hash) = hash {
+            return Some(Hash40(hash));
+        }
+    }
+
+    let mut path = path.to_str()?.to_lowercase().replace(';', ":").replace(".mp4", ".webm");
+
+    // Compressed mod files stand for the file without the suffix
+    if path.ends_with(".zst") {
+        path.truncate(path.len() - 4);
+    }
+
+    Some(Hash40::from(strip_region(&path).trim_start_matches('/')))
+}
+
+/// Gets the local path of the file of the game that a patch file applies to, without the region of regional patches
+pub fn patch_target(local: &Path) -> Option<PathBuf> {
+    let ext = match local.extension().and_then(|x| x.to_str())? {
+        "prcx" | "prcxml" => "prc",
+        "stdatx" | "stdatxml" => "stdat",
+        "stprmx" | "stprmxml" => "stprm",
+        "xmsbt" => "msbt",
+        _ => return None,
+    };
+
+    let target = local.with_extension(ext);
+
+    match target.file_name().and_then(|x| x.to_str()) {
+        Some(name) => Some(target.with_file_name(strip_region(name))),
+        None => Some(target),
+    }
+}
+
+/// Gets the NUS3BANK files which have to be unshared for the NUS3AUDIO files that were replaced without them.
+/// The NUS3AUDIO files of the unshare blacklist are left out, since they are not unshared.
+pub fn required_nus3banks<L: FileLoader>(tree: &Tree<L>, unshare_blacklist: &HashSet<Hash40String>) -> HashSet<PathBuf>
+where
+    <L as FileLoader>::ErrorType: Debug,
+{
+    let mut nus3audio_deps = HashSet::new();
+    let mut nus3banks_found = HashSet::new();
+
+    tree.walk_paths(|node, ty| {
+        if !ty.is_file() {
+            return;
+        }
+
+        let local = uncompressed_path(node.get_local());
+        if is_stream(&local) {
+            return;
+        }
+
+        match local.extension().and_then(|ext| ext.to_str()) {
+            Some("nus3audio") => {
+                if smash_hash(&local).map_or(false, |hash| !unshare_blacklist.contains(&Hash40String(hash))) {
+                    nus3audio_deps.insert(local.with_extension("nus3bank"));
+                }
+            },
+            Some("nus3bank") => {
+                nus3banks_found.insert(local);
+            },
+            _ => {},
+        }
+    });
+
+    for bank in nus3banks_found.into_iter() {
+        nus3audio_deps.remove(&bank);
+    }
+
+    nus3audio_deps
+}
+
+/// Follows the chain of shared files starting at a file, up to the file which owns the data
+pub fn shared_file_index<A: ArcLookup + ?Sized>(arc: &A, hash: Hash40) -> Result<FilePathIdx, LookupError> {
+    let file_info = arc.get_file_info_from_hash(hash)?;
+    let new_hash = arc.get_file_paths()[file_info.file_path_index].path.hash40();
+
+    if new_hash != hash {
+        shared_file_index(arc, new_hash)
+    } else {
+        Ok(file_info.file_path_index)
+    }
+}
+
+/// Groups the files of the game which share their data by the file which owns it. The files whose chain could not be
+/// followed are returned separately.
+pub fn shared_file_groups<A: ArcLookup>(arc: &A) -> (HashMap<Hash40, Vec<Hash40>>, Vec<Hash40>) {
+    let mut groups: HashMap<Hash40, Vec<Hash40>> = HashMap::new();
+    let mut failed = Vec::new();
+
+    let filepaths = arc.get_file_paths();
+
+    for (current_index, file_path) in filepaths.iter().enumerate() {
+        let hash = file_path.path.hash40();
+
+        match shared_file_index(arc, hash) {
+            Ok(idx) if usize::from(idx) == current_index => {},
+            Ok(idx) => groups.entry(filepaths[idx].path.hash40()).or_default().push(hash),
+            Err(_) => failed.push(hash),
+        }
+    }
+
+    (groups, failed)
+}
+
+/// Gets the directory every file of the game is part of, along with the index of the file in it
+pub fn file_directories<A: ArcLookup>(arc: &A) -> HashMap<Hash40, (Hash40, usize)> {
+    let file_paths = arc.get_file_paths();
+    let mut directories = HashMap::new();
+
+    for dir_info in arc.get_dir_infos() {
+        for (child_index, file_info) in arc.get_file_infos()[dir_info.file_info_range()].iter().enumerate() {
+            directories.insert(file_paths[file_info.file_path_index].path.hash40(), (dir_info.path.hash40(), child_index));
+        }
+    }
+
+    directories
+}
+
+/// The edits of the tables of the game that loading the mods requires, in the order they are made in
+#[derive(Debug, Default)]
+pub struct TablePlan {
+    /// Files which are not part of the game, added with their own data
+    pub added_files: Vec<PathBuf>,
+    /// Files which are not part of the game, added to share the data of a file of the game
+    pub shared_files: Vec<(PathBuf, Hash40)>,
+    /// Replaced files which share their data with other files, and are given data of their own
+    pub unshared_files: Vec<Hash40>,
+    /// Directories of the game, along with the files added to the ones loaded with them
+    pub directory_additions: Vec<(Hash40, Vec<Hash40>)>,
+    /// New files requested by the configs, whose path is unknown so they cannot be added
+    pub unknown_files: Vec<Hash40>,
+}
+
+impl TablePlan {
+    /// Plans the edits for the files of the mods, given by their local path, and for the files the filesystem replaces.
+    /// The paths of new files requested by the configs are looked up with `find_path`, and `is_shared` tells which files
+    /// of the game share their data.
+    pub fn new<P, R, F, S>(platform: &P, config: &ModConfig, locals: &[PathBuf], replaced: R, find_path: F, is_shared: S) -> Self
+    where
+        P: Platform,
+        R: IntoIterator<Item = Hash40>,
+        F: Fn(Hash40) -> Option<PathBuf>,
+        S: Fn(Hash40) -> bool,
+    {
+        let arc = platform.arc();
+        let mut plan = Self::default();
+        let mut added = HashSet::new();
+
+        // Add any files that were not found in the data.arc
+        for local in locals.iter().filter(|local| !is_stream(local)) {
+            if let Some(hash) = smash_hash(local) {
+                if arc.get_file_path_index_from_hash(hash).is_err() && added.insert(hash) {
+                    plan.added_files.push(local.clone());
+                }
+            }
+        }
+
+        for (hash, paths) in config.new_shared_files.iter() {
+            plan.shared_files.extend(paths.iter().map(|path| (path.clone(), hash.0)));
+        }
+
+        plan.shared_files.sort_by(|a, b| a.0.cmp(&b.0));
+
+        // Register the files requested in the configs that were not found during discovery, and collect the directories that depend on them
+        let mut new_dependencies: HashMap<Hash40, Vec<Hash40>> = HashMap::new();
+
+        for (hash, dependencies) in config.new_files.iter() {
+            let path = match find_path(hash.0) {
+                Some(path) => path,
+                None => {
+                    plan.unknown_files.push(hash.0);
+                    continue;
+                },
+            };
+
+            if arc.get_file_path_index_from_hash(hash.0).is_err() && added.insert(hash.0) {
+                plan.added_files.push(path);
+            }
+
+            for dependency in dependencies.iter().flatten() {
+                new_dependencies.entry(dependency.0).or_default().push(hash.0);
+            }
+        }
+
+        // Files that new files are shared to share their data from then on. Don't unshare any files in the unshare blacklist
+        let shared_to: HashSet<Hash40> = plan.shared_files.iter().map(|(_, hash)| *hash).collect();
+
+        plan.unshared_files = replaced
+            .into_iter()
+            .filter(|hash| !config.unshare_blacklist.contains(&Hash40String(*hash)) && (is_shared(*hash) || shared_to.contains(hash)))
+            .collect();
+        plan.unshared_files.sort_by_key(|hash| hash.0);
+
+        // The directories of the configs come before the ones that depend on new files
+        let mut directory_additions: Vec<(Hash40, Vec<Hash40>)> = config
+            .new_dir_files
+            .iter()
+            .map(|(hash, files)| (hash.0, files.iter().map(|x| x.0).collect()))
+            .collect();
+        directory_additions.sort_by_key(|(hash, _)| hash.0);
+
+        let mut dependencies: Vec<(Hash40, Vec<Hash40>)> = new_dependencies.into_iter().collect();
+        dependencies.sort_by_key(|(hash, _)| hash.0);
+
+        plan.directory_additions = directory_additions.into_iter().chain(dependencies).collect();
+        plan.unknown_files.sort_by_key(|hash| hash.0);
+
+        plan
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+    fn frame_header(descriptor: u8, rest: &[u8]) -> Vec<u8> {
+        MAGIC.iter().copied().chain(std::iter::once(descriptor)).chain(rest.iter().copied()).collect()
+    }
+
+    #[test]
+    fn reads_every_size_of_content_size() {
+        // Single segment with a one byte size
+        assert_eq!(zstd_content_size(&frame_header(0x20, &[0x05])), Some(5));
+        // A window descriptor, then a two byte size stored with an offset of 256
+        assert_eq!(zstd_content_size(&frame_header(0x40, &[0x58, 0x34, 0x12])), Some(0x1234 + 256));
+        // A four byte size
+        assert_eq!(zstd_content_size(&frame_header(0x80, &[0x58, 0x78, 0x56, 0x34, 0x12])), Some(0x1234_5678));
+        // An eight byte size, after a one byte dictionary id
+        assert_eq!(
+            zstd_content_size(&frame_header(0xE1, &[0x07, 0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01])),
+            Some(0x0102_0304_0506_0708)
+        );
+    }
+
+    #[test]
+    fn skips_frames_without_a_size() {
+        // No size is stored when neither the flag nor the single segment bit is set
+        assert_eq!(zstd_content_size(&frame_header(0x00, &[0x58])), None);
+        // The header is cut before the end of the size
+        assert_eq!(zstd_content_size(&frame_header(0x80, &[0x58, 0x78])), None);
+        // Not a zstd frame
+        assert_eq!(zstd_content_size(&[0x20, 0x05, 0x00, 0x00, 0x00]), None);
+    }
+
+    #[test]
+    fn only_files_with_the_suffix_are_compressed() {
+        assert!(is_compressed(Path::new("fighter/mario/model/body/c00/def_mario_001_col.nutexb.zst")));
+        assert!(!is_compressed(Path::new("fighter/mario/model/body/c00/def_mario_001_col.nutexb")));
+
+        assert_eq!(
+            uncompressed_path(Path::new("ui/message/msg_name.msbt.zst")),
+            Path::new("ui/message/msg_name.msbt")
+        );
+        assert_eq!(uncompressed_path(Path::new("ui/message/msg_name.msbt")), Path::new("ui/message/msg_name.msbt"));
+    }
+
+    #[test]
+    fn identifies_mods_by_their_id_or_folder_name() {
+        let root = Path::new("sd:/ultimate/mods/Cool Mario");
+        assert_eq!(mod_id_from_info(root, Some(b"id = \"cool-mario\"\ndisplay_name = \"Cool Mario\"")), "cool-mario");
+        // Mods without an id, with an empty one or with an unreadable info are identified by their folder name
+        assert_eq!(mod_id_from_info(root, Some(b"display_name = \"Cool Mario\"")), "Cool Mario");
+        assert_eq!(mod_id_from_info(root, Some(b"id = \"  \"")), "Cool Mario");
+        assert_eq!(mod_id_from_info(root, Some(b"id = ")), "Cool Mario");
+        assert_eq!(mod_id_from_info(root, None), "Cool Mario");
+        // The extension of archives is not part of the folder name
+        assert_eq!(mod_id_from_info(Path::new("sd:/ultimate/mods/Cool Mario.zip"), None), "Cool Mario");
+    }
+}
@@ -0,0 +1,207 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::atomic::Ordering,
+    time::SystemTime,
+};
+
+use skyline::nn;
+use smash_arc::{ArcLookup, Hash40};
+use walkdir::WalkDir;
+
+use super::{archive, cache::DiscoveryCache, pipeline, utils, CachedFilesystem};
+use crate::{
+    config, hashes,
+    resource::{self, LoadState},
+};
+
+/// The files picked up by a reload of the mods
+#[derive(Debug, Default)]
+pub struct ReloadReport {
+    /// Files whose new data is used the next time the game loads them
+    pub reloaded: Vec<PathBuf>,
+    /// Files that could not be reloaded, because they are new to the game or outgrew the size they were given on boot,
+    /// and the mod archives which were modified
+    pub needs_reboot: Vec<PathBuf>,
+    /// The reloaded files, which the game is made to load again once the filesystem is released
+    invalidated: Vec<Hash40>,
+    /// The mod roots with modified files, which are discovered again on the next boot since their fingerprint does not
+    /// cover files overwritten in place
+    changed_roots: HashSet<PathBuf>,
+}
+
+/// Reloads the modified files of the enabled mods. The filesystem is only held while it is updated, since the resource
+/// thread of the game takes its own tables before the filesystem, and they are only edited afterwards.
+pub fn reload_mods() -> Option<ReloadReport> {
+    let report = crate::GLOBAL_FILESYSTEM.write().reload_mods()?;
+
+    for hash in report.invalidated.iter() {
+        invalidate_loaded_file(*hash);
+    }
+
+    if !report.changed_roots.is_empty() {
+        let mut cache = DiscoveryCache::load();
+        cache.forget(&report.changed_roots);
+        cache.save();
+    }
+
+    Some(report)
+}
+
+/// Gets the local path of the file a modified file stands for, which is the file it patches for patch files
+fn reload_target(local: &Path) -> Option<PathBuf> {
+    // Files at the top of a mod root, hidden files and files for other regions are never discovered
+    if local.file_name()?.to_str().is_none() || pipeline::is_ignored(local, &config::region_str()) {
+        return None;
+    }
+
+    match local.extension().and_then(|ext| ext.to_str()) {
+        Some("json" | "nro") => None,
+        _ => Some(pipeline::patch_target(local).unwrap_or_else(|| local.to_path_buf())),
+    }
+}
+
+/// Marks a file that the game is not using as unloaded, so that the next request for it goes through the filesystem again
+fn invalidate_loaded_file(hash: Hash40) {
+    let index = match resource::arc().get_file_path_index_from_hash(hash) {
+        Ok(index) => index.0 as usize,
+        Err(_) => return,
+    };
+
+    let info = resource::filesystem_info_mut();
+
+    // The resource threads of the game update the same tables while they hold this lock
+    unsafe { nn::os::LockMutex(info.mutex) };
+
+    let filepath = info.get_loaded_filepaths()[index];
+    let data = &mut info.get_loaded_datas_mut()[filepath];
+
+    // Files that are in use keep their data until the game releases them, and are reloaded on the next request after that
+    if filepath.is_loaded != 0 && data.ref_count.load(Ordering::SeqCst) == 0 {
+        data.state = LoadState::Unloaded;
+        info.get_loaded_filepaths_mut()[index].is_loaded = 0;
+    }
+
+    unsafe { nn::os::UnlockMutex(info.mutex) };
+}
+
+impl CachedFilesystem {
+    /// Looks for the files of the enabled mods which were modified since the previous reload, or since the boot,
+    /// and makes the game load their new data
+    pub fn reload_mods(&mut self) -> ReloadReport {
+        let since = self.last_reload;
+        let now = SystemTime::now();
+        let arc_path = config::arc_path();
+        let region = config::region();
+        let mut report = ReloadReport::default();
+
+        for root in self.roots.iter().filter(|root| **root != arc_path) {
+            // The files of an archive are only listed when it is discovered, so the whole archive waits for the next boot
+            if archive::is_archive(root) {
+                let modified = std::fs::metadata(root).and_then(|metadata| metadata.modified()).ok();
+
+                if modified.map_or(false, |modified| modified > since) {
+                    warn!("The mod archive '{}' was modified, and needs a reboot to be reloaded.", root.display());
+                    report.needs_reboot.push(root.clone());
+                }
+
+                continue;
+            }
+
+            for entry in WalkDir::new(root).into_iter().filter_map(Result::ok) {
+                if !entry.file_type().is_file() {
+                    continue;
+                }
+
+                let modified = entry.metadata().ok().and_then(|metadata| metadata.modified().ok());
+                if !modified.map_or(false, |modified| modified > since) {
+                    continue;
+                }
+
+                let local = match entry.path().strip_prefix(root) {
+                    Ok(local) => local.to_path_buf(),
+                    Err(_) => continue,
+                };
+
+                let target = match reload_target(&local) {
+                    Some(target) => target,
+                    None => continue,
+                };
+
+                report.changed_roots.insert(root.clone());
+
+                // Hashed like discovery does, which stands compressed files for the file without their suffix
+                let hash = match pipeline::smash_hash(&target) {
+                    Some(hash) => hash,
+                    None => {
+                        error!("Failed to get hash for {}.", target.display());
+                        continue;
+                    },
+                };
+
+                // The size of the file in the tables, which includes the size patches applied on boot
+                let allotted_size = match resource::arc().get_file_data_from_hash(hash, region) {
+                    Ok(data) => data.decomp_size as usize,
+                    Err(_) => {
+                        report.needs_reboot.push(local);
+                        continue;
+                    },
+                };
+
+                let is_modded = self.hash_lookup.contains_key(&hash);
+
+                if !is_modded && target != local {
+                    // New patch files have to be registered to the API tree, which only happens on boot
+                    report.needs_reboot.push(local);
+                    continue;
+                }
+
+                if is_modded && target == local && self.loader.query_actual_path(&local).as_deref() != Some(entry.path()) {
+                    // Another mod root provides this file, so the modified one is not in use
+                    continue;
+                }
+
+                // Patch files are applied on load, so only the size of the files themselves can be checked beforehand.
+                // Compressed files are checked against the size they have once decompressed.
+                let size = if target == local {
+                    utils::decompressed_size(entry.path()).unwrap_or_else(|| entry.metadata().map_or(0, |metadata| metadata.len() as usize))
+                } else {
+                    0
+                };
+
+                if size > allotted_size {
+                    warn!(
+                        "'{}' is now bigger than the {:#x} bytes it was given on boot, and needs a reboot to be reloaded.",
+                        local.display(),
+                        allotted_size
+                    );
+                    report.needs_reboot.push(local);
+                    continue;
+                }
+
+                if !is_modded {
+                    // Files that were not modded on boot are added to the tree of the mod root they come from
+                    self.loader.patch_mut().insert_file(root, &local);
+                    self.hash_lookup.insert(hash, target.clone());
+
+                    if let Some(string) = target.to_str() {
+                        hashes::add(string);
+                    }
+                }
+
+                if target == local {
+                    // Same as on boot, files whose size was patched are still read from the data.arc with their original size
+                    let size = self.patched_sizes.get(&hash).copied().unwrap_or(size);
+                    self.hash_size_cache.insert(hash, size);
+                }
+
+                report.invalidated.push(hash);
+                info!("Reloaded '{}' ({:#x}).", target.display(), hash.0);
+                report.reloaded.push(local);
+            }
+        }
+
+        self.last_reload = now;
+        report
+    }
+}
@@ -1,97 +1,10 @@
-use std::{
-    collections::{HashMap, HashSet},
-    fmt::Debug,
-    path::{Path, PathBuf},
-};
-
-use orbits::{FileLoader, Tree};
-use smash_arc::{serde::Hash40String, Hash40};
-
-use super::{ApiCallback, ApiLoader};
-use crate::{hashes, PathExtension};
-
-pub fn make_hash_maps<L: FileLoader>(tree: &Tree<L>) -> (HashMap<Hash40, usize>, HashMap<Hash40, PathBuf>)
-where
-    <L as FileLoader>::ErrorType: Debug,
-{
-    // This defines the previously undefined behavior of what happens when you have two files that overlap each other due to
-    // regional things
-    // I.E.: ui/message/msg_menu.msbt and ui/message/msg_menu+us_en.msbt
-    // The regional variant should take priority. Since there can only be one regional file, there are only two situations which need to be handled:
-    // 1.) ui/message/msg_menu.msbt is found and then ui/message/msg_menu+us_en.msbt is found. ui/message/msg_menu+us_en.msbt should overwrite the previous file
-    // 2.) ui/message/msg_menu+us_en.msbt is found first, and when ui/message/msg_menu.msbt is found it should be discarded
-    // To solve this I store the hash of every file which has a regional variant which has been found, and then if a non-regional variant is found
-    // it is ignored
-    // - blujay
-    let mut regional_overrides = HashSet::new();
-    let mut size_map = HashMap::new();
-    let mut path_map = HashMap::new();
-    tree.walk_paths(|node, ty| {
-        if !ty.is_file() {
-            return;
-        }
-
-        if let Some(size) = tree.query_filesize(node.get_local()) {
-            match node.get_local().smash_hash() {
-                Ok(hash) => {
-                    if regional_overrides.contains(&hash) {
-                        return;
-                    }
-
-                    let is_regional_variant = if let Some(node) = node.get_local().to_str() { node.contains('+') } else { false };
-
-                    size_map.insert(hash, size);
-                    path_map.insert(hash, node.get_local().to_path_buf());
-
-                    if is_regional_variant {
-                        regional_overrides.insert(hash);
-                    }
-                },
-                Err(e) => error!("Failed to get hash for {}. Reason: {:?}", node.get_local().display(), e),
-            }
-        } else {
-            error!("Failed to stat file {}. This file may have issues.", node.full_path().display());
-        }
-    });
+use std::{io::Read, path::Path};
 
-    (size_map, path_map)
-}
+use orbits::Tree;
+use smash_arc::Hash40;
 
-pub fn get_required_nus3banks<L: FileLoader>(tree: &Tree<L>, unshare_blacklist: &HashSet<Hash40String>) -> HashSet<PathBuf>
-where
-    <L as FileLoader>::ErrorType: Debug,
-{
-    let mut nus3audio_deps = HashSet::new();
-    let mut nus3banks_found = HashSet::new();
-    tree.walk_paths(|node, ty| {
-        if !ty.is_file() {
-            return;
-        }
-
-        let local = node.get_local();
-        if local.is_stream() {
-            return;
-        }
-
-        if local.has_extension("nus3audio") {
-            match local.smash_hash() {
-                Ok(hash) if !unshare_blacklist.contains(&Hash40String(hash)) => {
-                    nus3audio_deps.insert(local.with_extension("nus3bank"));
-                },
-                Err(e) => error!("Failed to get hash for path {}. Reason: {:?}", local.display(), e),
-                _ => {},
-            }
-        } else if local.has_extension("nus3bank") {
-            nus3banks_found.insert(local.to_path_buf());
-        }
-    });
-
-    for bank in nus3banks_found.into_iter() {
-        nus3audio_deps.remove(&bank);
-    }
-
-    nus3audio_deps
-}
+use super::{pipeline, ApiCallback, ApiLoader, ApiLoaderError};
+use crate::{config, hashes, PathExtension};
 
 pub fn add_file_to_api_tree<P: AsRef<Path>, Q: AsRef<Path>>(
     tree: &mut Tree<ApiLoader>,
@@ -117,27 +30,7 @@ pub fn add_file_to_api_tree<P: AsRef<Path>, Q: AsRef<Path>>(
 /// Adds a PRC patch file and information to the API loader
 pub fn add_prc_patch<P: AsRef<Path>, Q: AsRef<Path>>(tree: &mut Tree<ApiLoader>, phys_root: P, local: Q) -> Option<Hash40> {
     let local = local.as_ref();
-    let base_local = if local.has_extension("prcx") || local.has_extension("prcxml") {
-        // patch files have different extensions
-        local.with_extension("prc")
-    } else if local.has_extension("stdatx") || local.has_extension("stdatxml") {
-        local.with_extension("stdat")
-    } else if local.has_extension("stprmx") || local.has_extension("stprmxml") {
-        local.with_extension("stprm")
-    } else {
-        unreachable!()
-    };
-    let base_local = if let Some(name) = base_local.file_name().and_then(|os_str| os_str.to_str()) {
-        if let Some(idx) = name.find('+') {
-            let mut new_name = name.to_string();
-            new_name.replace_range(idx..idx + 6, "");
-            base_local.with_file_name(new_name)
-        } else {
-            base_local
-        }
-    } else {
-        base_local
-    };
+    let base_local = pipeline::patch_target(local)?;
     let full_path = phys_root.as_ref().join(local); // need the full path so that our API loader can load it
     match base_local.smash_hash() {
         Ok(hash) => {
@@ -163,18 +56,14 @@ pub fn add_prc_patch<P: AsRef<Path>, Q: AsRef<Path>>(tree: &mut Tree<ApiLoader>,
 /// Adds a MSBT patch file and information to the API loader
 pub fn add_msbt_patch<P: AsRef<Path>, Q: AsRef<Path>>(tree: &mut Tree<ApiLoader>, phys_root: P, local: Q) -> Option<Hash40> {
     let local = local.as_ref();
-    let base_local = local.with_extension("msbt"); // patch files have different extensions
-    let base_local = if let Some(name) = base_local.file_name().and_then(|os_str| os_str.to_str()) {
-        if let Some(idx) = name.find('+') {
-            let mut new_name = name.to_string();
-            new_name.replace_range(idx..idx + 6, "");
-            base_local.with_file_name(new_name)
-        } else {
-            base_local
-        }
-    } else {
-        base_local
-    };
+
+    // Patches for other regions are not applied, such as msg_name+jp_ja.xmsbt when playing in us_en
+    if pipeline::file_region(local).map_or(false, |region| region != config::region_str()) {
+        trace!("Skipping MSBT patch {} since it is not for the current region.", local.display());
+        return None;
+    }
+
+    let base_local = pipeline::patch_target(local)?;
     let full_path = phys_root.as_ref().join(local); // need the full path so that our API loader can load it
     match base_local.smash_hash() {
         Ok(hash) => {
@@ -196,3 +85,102 @@ pub fn add_msbt_patch<P: AsRef<Path>, Q: AsRef<Path>>(tree: &mut Tree<ApiLoader>
         },
     }
 }
+
+/// Gets the decompressed size of a mod file if it is compressed with zstd, see `pipeline::is_compressed`.
+/// Frames which do not store their size are decompressed to measure it, since the size they have on the SD card is too small.
+pub fn decompressed_size<P: AsRef<Path>>(path: P) -> Option<usize> {
+    let path = path.as_ref();
+
+    if !pipeline::is_compressed(path) {
+        return None;
+    }
+
+    // The largest frame header is 18 bytes long
+    let header = super::archive::read_mod_file_header(path, 18).ok()?;
+
+    if !pipeline::is_zstd(&header) {
+        return None;
+    }
+
+    if let Some(size) = pipeline::zstd_content_size(&header) {
+        return Some(size);
+    }
+
+    let measured = super::archive::read_mod_file(path).map_err(ApiLoaderError::from).and_then(|data| {
+        let mut cursor = std::io::Cursor::new(data);
+        let mut decoder = ruzstd::StreamingDecoder::new(&mut cursor).map_err(ApiLoaderError::Other)?;
+        Ok(std::io::copy(&mut decoder, &mut std::io::sink())? as usize)
+    });
+
+    match measured {
+        Ok(size) => {
+            debug!("'{}' does not store its decompressed size, it was measured to be {:#x} bytes.", path.display(), size);
+            Some(size)
+        },
+        Err(e) => {
+            error!("Failed to decompress '{}' to measure its size, it will not load. Reason: {:?}", path.display(), e);
+            None
+        },
+    }
+}
+
+/// Decompresses the data of a mod file if it is compressed with zstd, see `pipeline::is_compressed`, and leaves it untouched otherwise
+pub fn decompress_if_compressed(local: &Path, data: Vec<u8>) -> Result<Vec<u8>, ApiLoaderError> {
+    if !pipeline::is_compressed(local) {
+        return Ok(data);
+    }
+
+    let mut cursor = std::io::Cursor::new(data);
+    let mut decoder = ruzstd::StreamingDecoder::new(&mut cursor).map_err(ApiLoaderError::Other)?;
+    let mut decompressed = Vec::new();
+    decoder.read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A zstd frame which does not store its size, holding "hello" in a single raw block
+    const UNSIZED_FRAME: &[u8] = &[0x28, 0xB5, 0x2F, 0xFD, 0x00, 0x00, 0x29, 0x00, 0x00, b'h', b'e', b'l', b'l', b'o'];
+
+    /// The same frame, storing its size
+    const SIZED_FRAME: &[u8] = &[0x28, 0xB5, 0x2F, 0xFD, 0x20, 0x05, 0x29, 0x00, 0x00, b'h', b'e', b'l', b'l', b'o'];
+
+    fn temp_file(name: &str, data: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("arcropolis-utils-{}-{}", std::process::id(), name));
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[test]
+    fn measures_compressed_files() {
+        let sized = temp_file("sized.nutexb.zst", SIZED_FRAME);
+        let unsized = temp_file("unsized.nutexb.zst", UNSIZED_FRAME);
+
+        assert_eq!(decompressed_size(&sized), Some(5));
+        assert_eq!(decompressed_size(&unsized), Some(5));
+
+        let _ = std::fs::remove_file(sized);
+        let _ = std::fs::remove_file(unsized);
+    }
+
+    #[test]
+    fn leaves_files_without_the_suffix_alone() {
+        // A file of the game can start with the same bytes as a zstd frame
+        let plain = temp_file("plain.nutexb", SIZED_FRAME);
+
+        assert_eq!(decompressed_size(&plain), None);
+        assert_eq!(decompress_if_compressed(&plain, SIZED_FRAME.to_vec()).unwrap(), SIZED_FRAME);
+
+        let _ = std::fs::remove_file(plain);
+    }
+
+    #[test]
+    fn decompresses_files_with_the_suffix() {
+        let path = Path::new("fighter/mario/model/body/c00/def_mario_001_col.nutexb.zst");
+
+        assert_eq!(decompress_if_compressed(path, UNSIZED_FRAME.to_vec()).unwrap(), b"hello");
+        assert!(decompress_if_compressed(path, b"hello".to_vec()).is_err());
+    }
+}
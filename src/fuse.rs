@@ -1,2 +1,23 @@
+use std::time::Duration;
+
+use nn_fuse::AccessorResult;
+use parking_lot::RwLockReadGuard;
+
+use crate::{fs::GlobalFilesystem, GLOBAL_FILESYSTEM};
+
 pub mod arc;
+pub mod live;
 pub mod mods;
+
+/// How long a mount waits on the filesystem while it is being written to, by a file load or a reload
+const FILESYSTEM_LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Takes the filesystem for the duration of a request to a mount, since reloading mods edits it at runtime.
+/// The resource thread holds it while it loads a file, and an API callback reading a mount from there would never get it,
+/// so the lock is only waited on for a while before the request fails.
+pub fn filesystem() -> Result<RwLockReadGuard<'static, GlobalFilesystem>, AccessorResult> {
+    GLOBAL_FILESYSTEM.try_read_for(FILESYSTEM_LOCK_TIMEOUT).ok_or_else(|| {
+        warn!("Timed out waiting on the filesystem to answer a request to a mount.");
+        AccessorResult::Unexpected
+    })
+}
@@ -1,10 +1,16 @@
-use std::{io::Write, str::FromStr};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
-use nn_fuse::{AccessorResult, DAccessor, DirectoryAccessor, FAccessor, FileAccessor, FileSystemAccessor, FsAccessor, FsEntryType};
+use nn_fuse::{
+    AccessorResult, DAccessor, DirectoryAccessor, DirectoryEntryType, FAccessor, FileAccessor, FileSystemAccessor, FsAccessor, FsEntryType,
+};
 use once_cell::sync::Lazy;
-use smash_arc::{ArcFile, ArcLookup, Hash40, Region};
+use smash_arc::{ArcFile, ArcLookup, Hash40, PathListEntry, Region, SearchLookup};
 
-use crate::PathExtension;
+use crate::{hashes, resource, PathExtension};
 
 pub static ARC_FILE: Lazy<ArcFile> = Lazy::new(|| ArcFile::open("rom:/data.arc").unwrap());
 
@@ -23,15 +29,114 @@ impl FileAccessor for ArcFileAccessor {
     }
 }
 
-pub struct ArcDirAccessor;
+/// The children of a directory of the archive
+pub struct ArcDirAccessor(Vec<(PathBuf, Option<usize>)>);
+
+impl ArcDirAccessor {
+    fn new(path: &Path) -> Result<Self, AccessorResult> {
+        list_directory(path).map(Self)
+    }
+}
+
+/// Gets the children of a directory of the archive, with the size of the files. Directories do not have a size.
+pub fn list_directory(path: &Path) -> Result<Vec<(PathBuf, Option<usize>)>, AccessorResult> {
+    let search = resource::search();
+
+    let children: Vec<&PathListEntry> = if is_root(path) {
+        // Top level folders are not the child of any folder, so they are found through their parent instead
+        let root = Hash40::from("");
+        search
+            .get_folder_path_list()
+            .iter()
+            .filter(|folder| folder.parent.hash40() == root)
+            .filter_map(|folder| search.get_path_list_entry_from_hash(folder.path.hash40()).ok())
+            .collect()
+    } else {
+        let hash = path.smash_hash().map_err(|_| AccessorResult::PathNotFound)?;
+        let folder = search.get_folder_path_entry_from_hash(hash).map_err(|_| AccessorResult::PathNotFound)?;
+
+        let mut children = Vec::new();
+        let mut child = search.get_first_child_in_folder(folder.path.hash40());
+        while let Ok(entry) = child {
+            children.push(entry);
+            child = search.get_next_child_in_folder(entry);
+        }
+        children
+    };
+
+    let mut entries = Vec::new();
+
+    for child in children {
+        let name = child_name(child);
+
+        if child.is_directory() {
+            entries.push((path.join(name), None));
+            continue;
+        }
+
+        let hash = child.path.hash40();
+        let is_regional = ARC_FILE.get_file_info_from_hash(hash).map_or(false, |info| info.flags.is_regional());
+
+        if let Ok(data) = ARC_FILE.get_file_data_from_hash(hash, crate::config::region()) {
+            entries.push((path.join(&name), Some(data.decomp_size as usize)));
+        }
+
+        if is_regional {
+            // Expose every regional variant as a sibling, the same way mods name them
+            for region in crate::REGIONS.iter() {
+                let arc_region = Region::from_str(region).map_err(|_| AccessorResult::Unexpected)?;
+
+                if let Ok(data) = ARC_FILE.get_file_data_from_hash(hash, arc_region) {
+                    entries.push((path.join(regional_name(&name, region)), Some(data.decomp_size as usize)));
+                }
+            }
+        }
+    }
+
+    Ok(entries)
+}
 
 impl DirectoryAccessor for ArcDirAccessor {
-    fn read(&mut self, _buffer: &mut [nn_fuse::DirectoryEntry]) -> Result<usize, AccessorResult> {
-        Err(AccessorResult::Unimplemented)
+    fn read(&mut self, buffer: &mut [nn_fuse::DirectoryEntry]) -> Result<usize, AccessorResult> {
+        debug!("ArcDirAccessor::read - Buffer length: {:x}", buffer.len());
+
+        let count = buffer.len().min(self.0.len());
+
+        for (entry, (path, size)) in buffer.iter_mut().zip(self.0.iter()) {
+            entry.path = path.clone();
+            entry.ty = match size {
+                Some(size) => DirectoryEntryType::File(*size as i64),
+                None => DirectoryEntryType::Directory,
+            };
+        }
+
+        Ok(count)
     }
 
     fn get_entry_count(&mut self) -> Result<usize, AccessorResult> {
-        Err(AccessorResult::Unimplemented)
+        Ok(self.0.len())
+    }
+}
+
+fn is_root(path: &Path) -> bool {
+    path.as_os_str().is_empty() || path == Path::new("/")
+}
+
+/// Gets the name of a child from the hashes, falling back to the hash of its path, which can be opened all the same
+fn child_name(child: &PathListEntry) -> String {
+    let hash = child.path.hash40();
+
+    hashes::try_find(hash)
+        .and_then(|path| Path::new(path).file_name())
+        .and_then(|name| name.to_str())
+        .map_or_else(|| format!("{:#x}", hash.0), String::from)
+}
+
+/// Gets the name of the variant of a file for a region, such as `msg_menu+us_en.msbt` for `msg_menu.msbt`
+fn regional_name(name: &str, region: &str) -> String {
+    match name.find('.') {
+        Some(idx) => format!("{}+{}{}", &name[..idx], region, &name[idx..]),
+        None => format!("{}+{}", name, region),
     }
 }
 
@@ -40,10 +145,16 @@ pub struct ArcFuse;
 impl FileSystemAccessor for ArcFuse {
     fn get_entry_type(&self, path: &std::path::Path) -> Result<FsEntryType, AccessorResult> {
         debug!("Path: {}", path.display());
-        if path.file_name().is_some() {
-            Ok(FsEntryType::File)
-        } else {
-            Err(AccessorResult::Unimplemented)
+        if is_root(path) {
+            return Ok(FsEntryType::Directory);
+        }
+
+        // Regional variants share the hash of the file they are a variant of
+        let hash = path.smash_hash().map_err(|_| AccessorResult::PathNotFound)?;
+        match resource::search().get_path_list_entry_from_hash(hash) {
+            Ok(entry) if entry.is_directory() => Ok(FsEntryType::Directory),
+            Ok(_) => Ok(FsEntryType::File),
+            Err(_) => Err(AccessorResult::PathNotFound),
         }
     }
 
@@ -83,8 +194,9 @@ impl FileSystemAccessor for ArcFuse {
         }
     }
 
-    fn open_directory(&self, _path: &std::path::Path, _mode: skyline::nn::fs::OpenDirectoryMode) -> Result<*mut DAccessor, AccessorResult> {
-        Err(AccessorResult::Unimplemented)
+    fn open_directory(&self, path: &std::path::Path, _mode: skyline::nn::fs::OpenDirectoryMode) -> Result<*mut DAccessor, AccessorResult> {
+        debug!("Path: {}", path.display());
+        Ok(DAccessor::new(ArcDirAccessor::new(path)?))
     }
 }
 
@@ -0,0 +1,165 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use nn_fuse::*;
+use orbits::FileEntryType;
+
+use super::arc::{self, ArcFuse};
+use crate::{fs::GlobalFilesystem, PathExtension};
+
+/// A file as the game receives it, loaded the first time it is needed
+pub struct LiveFileAccessor {
+    path: PathBuf,
+    data: Option<Vec<u8>>,
+}
+
+pub struct LiveDirAccessor(Vec<(PathBuf, Option<usize>)>);
+
+pub struct LiveFsAccessor;
+
+/// Loads a file the same way the game does: the API callbacks, then the mods, then the patched files and finally the archive
+fn load_live(path: &Path) -> Result<Vec<u8>, AccessorResult> {
+    let hash = path.smash_hash().map_err(|_| AccessorResult::PathNotFound)?;
+
+    // The filesystem is held while the file loads, so that a reload cannot change it in the middle of the API callbacks and patches
+    let fs = super::filesystem()?;
+    if fs.local_hash(hash).is_some() {
+        return fs.load(hash).ok_or(AccessorResult::Unexpected);
+    }
+    drop(fs);
+
+    // Regional variants of files that are not modded are read from the archive directly, for the region they are named after
+    let region = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| crate::REGIONS.iter().find(|region| name.contains(&format!("+{}", region))))
+        .and_then(|region| region.parse().ok())
+        .unwrap_or_else(crate::config::region);
+
+    match arc::ARC_FILE.get_file_contents(hash, region) {
+        Ok(data) => Ok(data),
+        Err(_) => arc::ARC_FILE.get_file_contents(hash, smash_arc::Region::None).map_err(|_| AccessorResult::PathNotFound),
+    }
+}
+
+fn mod_entry_type(fs: &GlobalFilesystem, path: &Path) -> Option<FileEntryType> {
+    fs.get().get_virtual_entry_type(path).or_else(|_| fs.get().get_patch_entry_type(path)).ok()
+}
+
+impl LiveFileAccessor {
+    fn data(&mut self) -> Result<&[u8], AccessorResult> {
+        if self.data.is_none() {
+            self.data = Some(load_live(&self.path)?);
+        }
+
+        Ok(self.data.as_deref().unwrap())
+    }
+}
+
+impl FileAccessor for LiveFileAccessor {
+    fn read(&mut self, mut buffer: &mut [u8], offset: usize) -> Result<usize, AccessorResult> {
+        debug!(target: "no-mod-path", "LiveFileAccessor::read - Buffer length: {:#x}", buffer.len());
+
+        let data = self.data()?;
+        buffer.write(data.get(offset..).unwrap_or_default()).map_err(|_| AccessorResult::Unexpected)
+    }
+
+    fn get_size(&mut self) -> Result<usize, AccessorResult> {
+        // Patched files only know their size once they are patched, so this loads the file
+        self.data().map(|data| data.len())
+    }
+}
+
+impl DirectoryAccessor for LiveDirAccessor {
+    fn read(&mut self, buffer: &mut [DirectoryEntry]) -> Result<usize, AccessorResult> {
+        for (entry, (path, size)) in buffer.iter_mut().zip(self.0.iter()) {
+            entry.path = path.clone();
+            entry.ty = match size {
+                Some(size) => DirectoryEntryType::File(*size as i64),
+                None => DirectoryEntryType::Directory,
+            };
+        }
+
+        Ok(buffer.len().min(self.0.len()))
+    }
+
+    fn get_entry_count(&mut self) -> Result<usize, AccessorResult> {
+        Ok(self.0.len())
+    }
+}
+
+impl FileSystemAccessor for LiveFsAccessor {
+    fn get_entry_type(&self, path: &Path) -> Result<FsEntryType, AccessorResult> {
+        debug!(target: "no-mod-path", "LiveFsAccessor::get_entry_type - Path: {}", path.display());
+
+        match mod_entry_type(&super::filesystem()?, path) {
+            Some(FileEntryType::File) => Ok(FsEntryType::File),
+            Some(FileEntryType::Directory) => Ok(FsEntryType::Directory),
+            None => ArcFuse.get_entry_type(path),
+        }
+    }
+
+    fn open_file(&self, path: &Path, mode: skyline::nn::fs::OpenMode) -> Result<*mut FAccessor, AccessorResult> {
+        debug!(target: "no-mod-path", "LiveFsAccessor::open_file - Path: {}", path.display());
+
+        if mode >> 1 & 1 != 0 || mode >> 2 & 1 != 0 {
+            return Err(AccessorResult::Unsupported);
+        }
+
+        match self.get_entry_type(path)? {
+            FsEntryType::File => {
+                Ok(FAccessor::new(
+                    LiveFileAccessor {
+                        path: path.to_path_buf(),
+                        data: None,
+                    },
+                    mode,
+                ))
+            },
+            FsEntryType::Directory => Err(AccessorResult::PathNotFound),
+        }
+    }
+
+    fn open_directory(&self, path: &Path, _mode: skyline::nn::fs::OpenDirectoryMode) -> Result<*mut DAccessor, AccessorResult> {
+        debug!(target: "no-mod-path", "LiveFsAccessor::open_directory - Path: {}", path.display());
+
+        let fs = super::filesystem()?;
+
+        let vanilla = arc::list_directory(path);
+        let is_mod_directory = mod_entry_type(&fs, path).is_some();
+
+        if vanilla.is_err() && !is_mod_directory {
+            return Err(AccessorResult::PathNotFound);
+        }
+
+        let mut entries = vanilla.unwrap_or_default();
+        let children = if is_mod_directory { fs.get().get_children(path) } else { Vec::new() };
+
+        // Modded files replace the vanilla entry of the same name, and new files are added next to them
+        for child in children.iter().map(|child| child.to_path_buf()) {
+            let size = match mod_entry_type(&fs, &child) {
+                Some(FileEntryType::File) => Some(fs.get().query_max_filesize(&child).unwrap_or_default()),
+                Some(FileEntryType::Directory) => None,
+                None => continue,
+            };
+
+            match entries.iter_mut().find(|(path, _)| *path == child) {
+                Some(entry) => entry.1 = size,
+                None => entries.push((child, size)),
+            }
+        }
+
+        // Files that are only patched keep their vanilla size in the listing, the actual size is known once they are opened
+        Ok(DAccessor::new(LiveDirAccessor(entries)))
+    }
+}
+
+pub fn install_live_fs() {
+    let accessor = FsAccessor::new(LiveFsAccessor);
+    unsafe {
+        nn_fuse::mount("live", &mut *accessor).unwrap();
+    }
+    info!("Finished mounting live:/");
+}
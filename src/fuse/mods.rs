@@ -1,10 +1,26 @@
-use std::{io::Write, path::PathBuf};
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+};
 
 use nn_fuse::*;
+use once_cell::sync::Lazy;
 use orbits::FileEntryType;
 
+/// The directory of `mods:/` which is backed by the scratch directory on the SD card, and can be written to
+static SCRATCH_DIR: &str = "scratch";
+
+/// The scratch directory on the SD card, resolved once rather than on every call to the filesystem
+static SCRATCH_ROOT: Lazy<PathBuf> = Lazy::new(crate::config::scratch_path);
+
 pub struct ModFileAccessor(PathBuf);
 
+/// A file of the scratch area, which is read and written straight from the SD card
+pub struct ScratchFileAccessor(File);
+
+pub struct ScratchDirAccessor(Vec<(PathBuf, DirectoryEntryType)>);
+
 pub struct ModDirAccessor(PathBuf);
 
 pub struct ModFsAccessor;
@@ -13,13 +29,13 @@ impl FileAccessor for ModFileAccessor {
     fn read(&mut self, mut buffer: &mut [u8], offset: usize) -> Result<usize, AccessorResult> {
         debug!(target: "no-mod-path", "ModFileAccessor::read - Buffer length: {:#x}", buffer.len());
 
-        let fs = unsafe { &*crate::GLOBAL_FILESYSTEM.data_ptr() };
+        let fs = super::filesystem()?;
         let file = fs.get().load(&self.0).map_err(|_| AccessorResult::Unexpected)?;
         buffer.write(&file.as_slice()[offset..]).map_err(|_| AccessorResult::Unexpected)
     }
 
     fn get_size(&mut self) -> Result<usize, AccessorResult> {
-        let fs = unsafe { &*crate::GLOBAL_FILESYSTEM.data_ptr() };
+        let fs = super::filesystem()?;
         let size = fs.get().query_max_filesize(&self.0).map_or_else(|| Err(AccessorResult::Unexpected), Ok);
         if let Ok(size) = size {
             debug!(target: "no-mod-path", "ModFileAccessor::get_size - Size: {:#x}", size);
@@ -32,7 +48,7 @@ impl FileAccessor for ModFileAccessor {
 
 impl DirectoryAccessor for ModDirAccessor {
     fn read(&mut self, buffer: &mut [DirectoryEntry]) -> Result<usize, AccessorResult> {
-        let fs = unsafe { &*crate::GLOBAL_FILESYSTEM.data_ptr() };
+        let fs = super::filesystem()?;
         let children = fs.get().get_children(&self.0);
         for (idx, path) in children.iter().enumerate() {
             if idx >= buffer.len() {
@@ -59,16 +75,80 @@ impl DirectoryAccessor for ModDirAccessor {
     }
 
     fn get_entry_count(&mut self) -> Result<usize, AccessorResult> {
-        let fs = unsafe { &*crate::GLOBAL_FILESYSTEM.data_ptr() };
+        let fs = super::filesystem()?;
         Ok(fs.get().get_children(&self.0).len())
     }
 }
 
+impl FileAccessor for ScratchFileAccessor {
+    fn read(&mut self, buffer: &mut [u8], offset: usize) -> Result<usize, AccessorResult> {
+        self.0.seek(SeekFrom::Start(offset as u64)).map_err(|_| AccessorResult::Unexpected)?;
+        self.0.read(buffer).map_err(|_| AccessorResult::Unexpected)
+    }
+
+    fn write(&mut self, buffer: &[u8], offset: usize) -> Result<(), AccessorResult> {
+        self.0.seek(SeekFrom::Start(offset as u64)).map_err(|_| AccessorResult::Unexpected)?;
+        self.0.write_all(buffer).map_err(|_| AccessorResult::Unexpected)
+    }
+
+    fn flush(&mut self) -> Result<(), AccessorResult> {
+        self.0.flush().map_err(|_| AccessorResult::Unexpected)
+    }
+
+    fn set_size(&mut self, size: usize) -> Result<(), AccessorResult> {
+        self.0.set_len(size as u64).map_err(|_| AccessorResult::Unexpected)
+    }
+
+    fn get_size(&mut self) -> Result<usize, AccessorResult> {
+        self.0.metadata().map(|metadata| metadata.len() as usize).map_err(|_| AccessorResult::Unexpected)
+    }
+}
+
+impl DirectoryAccessor for ScratchDirAccessor {
+    fn read(&mut self, buffer: &mut [DirectoryEntry]) -> Result<usize, AccessorResult> {
+        for (entry, (path, ty)) in buffer.iter_mut().zip(self.0.iter()) {
+            entry.path = path.clone();
+            entry.ty = match ty {
+                DirectoryEntryType::File(size) => DirectoryEntryType::File(*size),
+                DirectoryEntryType::Directory => DirectoryEntryType::Directory,
+            };
+        }
+
+        Ok(buffer.len().min(self.0.len()))
+    }
+
+    fn get_entry_count(&mut self) -> Result<usize, AccessorResult> {
+        Ok(self.0.len())
+    }
+}
+
+/// Gets the path on the SD card of a path in the scratch area of `mods:/`. Paths with components such as `..` are
+/// rejected, since they could reach any file of the SD card.
+fn scratch_path(path: &Path) -> Option<PathBuf> {
+    let path = path.strip_prefix("/").unwrap_or(path);
+    let local = path.strip_prefix(SCRATCH_DIR).ok()?;
+
+    if !local.components().all(|component| matches!(component, Component::Normal(_))) {
+        warn!("Refusing to access '{}', which is outside of the scratch area.", path.display());
+        return None;
+    }
+
+    Some(SCRATCH_ROOT.join(local))
+}
+
 impl FileSystemAccessor for ModFsAccessor {
     fn get_entry_type(&self, path: &std::path::Path) -> Result<FsEntryType, AccessorResult> {
         debug!(target: "no-mod-path", "ModFsAccessor::get_entry_type - Path: {}", path.display());
 
-        let fs = unsafe { &*crate::GLOBAL_FILESYSTEM.data_ptr() };
+        if let Some(physical) = scratch_path(path) {
+            return match std::fs::metadata(physical) {
+                Ok(metadata) if metadata.is_dir() => Ok(FsEntryType::Directory),
+                Ok(_) => Ok(FsEntryType::File),
+                Err(_) => Err(AccessorResult::PathNotFound),
+            };
+        }
+
+        let fs = super::filesystem()?;
         match fs.get().get_virtual_entry_type(path) {
             Err(_) => match fs.get().get_patch_entry_type(path) {
                 Ok(ty) => match ty {
@@ -91,8 +171,16 @@ impl FileSystemAccessor for ModFsAccessor {
 
         debug!(target: "no-mod-path", "ModFsAccessor::open_file - Path: {} | Read: {} | Write: {} | Append: {}", path.display(), read, write, append);
 
-        let fs = unsafe { &*crate::GLOBAL_FILESYSTEM.data_ptr() };
+        if let Some(physical) = scratch_path(path) {
+            return match OpenOptions::new().read(read).write(write).append(append).open(physical) {
+                Ok(file) => Ok(FAccessor::new(ScratchFileAccessor(file), mode)),
+                Err(_) => Err(AccessorResult::PathNotFound),
+            };
+        }
+
+        let fs = super::filesystem()?;
 
+        // Only the scratch area can be written to, the rest of the filesystem is what the mods provide
         if write || append {
             return Err(AccessorResult::Unsupported);
         }
@@ -107,7 +195,21 @@ impl FileSystemAccessor for ModFsAccessor {
     fn open_directory(&self, path: &std::path::Path, _mode: skyline::nn::fs::OpenDirectoryMode) -> Result<*mut DAccessor, AccessorResult> {
         debug!(target: "no-mod-path", "ModFsAccessor::open_directory - Path: {}", path.display());
 
-        let fs = unsafe { &*crate::GLOBAL_FILESYSTEM.data_ptr() };
+        if let Some(physical) = scratch_path(path) {
+            let entries = std::fs::read_dir(physical)
+                .map_err(|_| AccessorResult::PathNotFound)?
+                .filter_map(|entry| {
+                    let entry = entry.ok()?;
+                    let metadata = entry.metadata().ok()?;
+                    let ty = if metadata.is_dir() { DirectoryEntryType::Directory } else { DirectoryEntryType::File(metadata.len() as i64) };
+                    Some((path.join(entry.file_name()), ty))
+                })
+                .collect();
+
+            return Ok(DAccessor::new(ScratchDirAccessor(entries)));
+        }
+
+        let fs = super::filesystem()?;
 
         if fs.get().contains(path) {
             Ok(DAccessor::new(ModDirAccessor(PathBuf::from(path))))
@@ -115,6 +217,33 @@ impl FileSystemAccessor for ModFsAccessor {
             Err(AccessorResult::PathNotFound)
         }
     }
+
+    fn create_file(&self, path: &std::path::Path, size: usize) -> Result<(), AccessorResult> {
+        debug!(target: "no-mod-path", "ModFsAccessor::create_file - Path: {} | Size: {:#x}", path.display(), size);
+
+        let physical = scratch_path(path).ok_or(AccessorResult::Unsupported)?;
+
+        if let Some(parent) = physical.parent() {
+            std::fs::create_dir_all(parent).map_err(|_| AccessorResult::Unexpected)?;
+        }
+
+        let file = File::create(physical).map_err(|_| AccessorResult::Unexpected)?;
+        file.set_len(size as u64).map_err(|_| AccessorResult::Unexpected)
+    }
+
+    fn create_directory(&self, path: &std::path::Path) -> Result<(), AccessorResult> {
+        debug!(target: "no-mod-path", "ModFsAccessor::create_directory - Path: {}", path.display());
+
+        let physical = scratch_path(path).ok_or(AccessorResult::Unsupported)?;
+        std::fs::create_dir_all(physical).map_err(|_| AccessorResult::Unexpected)
+    }
+
+    fn delete_file(&self, path: &std::path::Path) -> Result<(), AccessorResult> {
+        debug!(target: "no-mod-path", "ModFsAccessor::delete_file - Path: {}", path.display());
+
+        let physical = scratch_path(path).ok_or(AccessorResult::Unsupported)?;
+        std::fs::remove_file(physical).map_err(|_| AccessorResult::PathNotFound)
+    }
 }
 
 pub fn install_mod_fs() {
@@ -1,3 +1,5 @@
+// The plugin only targets the console, host builds are only used for the simulator
+#![cfg(target_os = "switch")]
 #![allow(incomplete_features)] // for if_let_guard
 #![feature(proc_macro_hygiene)]
 #![feature(if_let_guard)]
@@ -36,7 +38,6 @@ mod menus;
 mod offsets;
 mod replacement;
 mod resource;
-#[cfg(feature = "updater")]
 mod update;
 
 use fs::GlobalFilesystem;
@@ -110,9 +111,7 @@ impl PathExtension for Path {
     }
 
     fn is_stream(&self) -> bool {
-        static VALID_PREFIXES: &[&str] = &["/stream;", "/stream:", "stream;", "stream:"];
-
-        VALID_PREFIXES.iter().any(|x| self.starts_with(*x))
+        fs::pipeline::is_stream(self)
     }
 
     fn has_extension<S: AsRef<str>>(&self, ext: S) -> bool {
@@ -120,37 +119,7 @@ impl PathExtension for Path {
     }
 
     fn smash_hash(&self) -> Result<Hash40, InvalidOsStrError> {
-        if self.extension().is_none() {
-            let hash = self
-                .file_name()
-                .and_then(|x| x.to_str())
-                .and_then(
-                    |x| {
-                        if x.starts_with("0x") {
-                            u64::from_str_radix(x.trim_start_matches("0x"), 16).ok()
-                        } else {
-                            None
-                        }
-                    },
-                )
-                .map(Hash40);
-            if let Some(hash) = hash {
-                return Ok(hash);
-            }
-        }
-        let mut path = self
-            .as_os_str()
-            .to_str()
-            .map_or(Err(InvalidOsStrError), Ok)?
-            .to_lowercase()
-            .replace(';', ":")
-            .replace(".mp4", ".webm");
-
-        if let Some(regional_idx) = path.find('+') {
-            path.replace_range(regional_idx..regional_idx + 6, "")
-        }
-
-        Ok(Hash40::from(path.trim_start_matches('/')))
+        fs::pipeline::smash_hash(self).ok_or(InvalidOsStrError)
     }
 }
 
@@ -167,9 +136,7 @@ fn get_path_from_hash(hash: Hash40) -> PathBuf {
     }
 }
 
-pub const REGIONS: &[&str] = &[
-    "jp_ja", "us_en", "us_fr", "us_es", "eu_en", "eu_fr", "eu_es", "eu_de", "eu_nl", "eu_it", "eu_ru", "kr_ko", "zh_cn", "zh_tw",
-];
+pub use fs::pipeline::REGIONS;
 /// Initializes the `nn::time` library, for creating a log file based off of the current time. For some reason Smash does not initialize this
 fn init_time() {
     unsafe {
@@ -233,10 +200,15 @@ fn initial_loading(_ctx: &InlineCtx) {
     }
     drop(filesystem);
     fuse::mods::install_mod_fs();
+
+    // Discovery and the mod filesystem are set up by now, which is as far as a broken update has to get to be rolled back
+    update::install::confirm_update();
+
+    fuse::live::install_live_fs();
     api::event::send_event(Event::ModFilesystemMounted);
 }
 
-#[skyline::hook(offset = offsets::title_screen_version())]
+#[skyline::hook(offset = offsets::title_screen_version().unwrap())]
 fn change_version_string(arg: u64, string: *const c_char) {
     let original_str = unsafe { skyline::from_c_str(string) };
 
@@ -263,7 +235,7 @@ fn change_version_string(arg: u64, string: *const c_char) {
 // #[skyline::from_offset(0x336d890)]
 // pub fn stop_all_bgm();
 
-#[skyline::hook(offset = offsets::eshop_show())]
+#[skyline::hook(offset = offsets::eshop_show().unwrap())]
 fn show_eshop() {
     // stop_all_bgm();
     // let instance = (*(offsets::offset_to_addr(0x532d8d0) as *const u64));
@@ -274,6 +246,9 @@ fn show_eshop() {
 
 #[skyline::main(name = "arcropolis")]
 pub fn main() {
+    // Roll back an update which never finished booting before any of its code gets to run, such as the migrations of the configuration
+    let previous_update = update::install::check_previous_update();
+
     // Initialize the time for the logger
     init_time();
 
@@ -292,6 +267,14 @@ pub fn main() {
         println!("[arcropolis] Failed to initialize logger. Reason: {:?}", err);
     }
 
+    // Relaunch with the restored installation, or with an update that was dropped on the SD card
+    if update::install::report_previous_update(previous_update) || update::install::install_local_update() {
+        unsafe { skyline::nn::oe::RequestToRelaunchApplication() };
+    }
+
+    // The presets are converted once the logger can report the mods which are missing, and before discovery reads them
+    config::migrate_presets();
+
     // Acquire the filesystem and promise it to the initial_loading hook
     let mut filesystem = GLOBAL_FILESYSTEM.write();
 
@@ -348,7 +331,21 @@ pub fn main() {
             .unwrap();
     }
 
-    skyline::install_hooks!(initial_loading, change_version_string, show_eshop,);
+    skyline::install_hooks!(initial_loading);
+
+    // These hooks only change the menus, so the game can go on without them if their code could not be found
+    if offsets::title_screen_version().is_some() {
+        skyline::install_hook!(change_version_string);
+    }
+
+    if offsets::eshop_show().is_some() {
+        skyline::install_hook!(show_eshop);
+    }
+
+    for feature in offsets::missing_features() {
+        warn!("The offset of '{}' could not be found for this version of the game, the feature has been disabled.", feature);
+    }
+
     replacement::install();
 
     std::panic::set_hook(Box::new(|info| {
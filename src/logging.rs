@@ -1,13 +1,16 @@
 use std::{
     fs::File,
     io::{BufWriter, Write},
-    ops::Deref,
-    path::Path,
+    path::{Path, PathBuf},
+    str::FromStr,
     time::SystemTime,
 };
 
-use log::{LevelFilter, Metadata, Record, SetLoggerError};
-use once_cell::sync::Lazy;
+use log::{
+    kv::{self, Key, Source, Value, VisitSource},
+    LevelFilter, Metadata, Record, SetLoggerError,
+};
+use once_cell::sync::{Lazy, OnceCell};
 use parking_lot::Mutex;
 
 use crate::config;
@@ -50,55 +53,222 @@ fn format_time_string(seconds: u64) -> String {
 
 static LOG_PATH: &str = "sd:/ultimate/arcropolis/logs";
 static FILE_LOG_BUFFER: usize = 0x2000; // Room for 0x2000 characters, might have performance issues if the logger level is "Info" or "Trace"
-struct FileLogger(Option<Mutex<BufWriter<File>>>);
 
-impl Deref for FileLogger {
-    type Target = Option<Mutex<BufWriter<File>>>;
+/// A log file which moves on to a new part once it reaches the maximum size
+struct LogFile {
+    writer: BufWriter<File>,
+    written: usize,
+    stem: String,
+    extension: &'static str,
+    part: usize,
+}
+
+impl LogFile {
+    fn create(stem: String, extension: &'static str, part: usize) -> std::io::Result<Self> {
+        let name = if part == 0 {
+            format!("{}.{}", stem, extension)
+        } else {
+            format!("{}_{}.{}", stem, part, extension)
+        };
+        let file = File::create(Path::new(LOG_PATH).join(name))?;
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+        Ok(Self {
+            writer: BufWriter::with_capacity(FILE_LOG_BUFFER, file),
+            written: 0,
+            stem,
+            extension,
+            part,
+        })
     }
 }
 
+struct FileLogger(Option<Mutex<LogFile>>);
+
 impl FileLogger {
+    fn new(extension: &'static str) -> Self {
+        let seconds = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("Clock may have gone backwards!");
+        let _ = std::fs::create_dir_all(LOG_PATH);
+
+        LogFile::create(format_time_string(seconds.as_secs()), extension, 0).map_or_else(
+            |_| {
+                error!(target: "std", "Unable to initialize the file logger!");
+                FileLogger(None)
+            },
+            |file| {
+                // Only the logs of the most recent boots are kept, so that the log folder does not grow forever
+                rotate_logs(extension, *LOG_RETENTION);
+
+                // Spawn a log flusher, since we don't have the ability to flush the logger on application close, home button press,
+                // or crash (crashing technically can be done but ARCropolis is not the place to implement)
+                let _ = std::thread::spawn(|| {
+                    std::thread::sleep(std::time::Duration::from_millis(2000));
+                    log::logger().flush();
+                });
+                FileLogger(Some(Mutex::new(file)))
+            },
+        )
+    }
+
     pub fn write<T: AsRef<[u8]>>(&self, message: T) {
-        if let Some(writer) = &self.0 {
-            let mut writer = writer.lock();
-            let _ = writer.write(message.as_ref());
+        if let Some(file) = &self.0 {
+            let mut file = file.lock();
+            let message = message.as_ref();
+
+            if file.written != 0 && file.written + message.len() > *LOG_MAX_SIZE {
+                let _ = file.writer.flush();
+
+                match LogFile::create(file.stem.clone(), file.extension, file.part + 1) {
+                    Ok(next) => *file = next,
+                    // Keep writing to the current file rather than losing the rest of the logs
+                    Err(err) => error!(target: "std", "Failed to create the next log file! Reason: {:?}", err),
+                }
+            }
+
+            let _ = file.writer.write(message);
+            file.written += message.len();
+        }
+    }
+
+    pub fn flush(&self) {
+        if let Some(file) = &self.0 {
+            if let Some(mut file) = file.try_lock() {
+                if let Err(err) = file.writer.flush() {
+                    error!(target: "std", "Failed to flush file logger! Reason: {:?}", err)
+                }
+            }
         }
     }
 }
 
-// Summon the file logger and create a file for it based on the current time (requires time to be initialized)
-static FILE_WRITER: Lazy<FileLogger> = Lazy::new(|| {
-    let seconds = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .expect("Clock may have gone backwards!");
-    let path = Path::new(LOG_PATH).join(format!("{}.log", format_time_string(seconds.as_secs())));
-    let _ = std::fs::create_dir_all(LOG_PATH);
-    std::fs::File::create(path).map_or_else(
-        |_| {
-            error!(target: "std", "Unable to initialize the file logger!");
-            FileLogger(None)
-        },
-        |file| {
-            // Spawn a log flusher, since we don't have the ability to flush the logger on application close, home button press,
-            // or crash (crashing technically can be done but ARCropolis is not the place to implement)
-            let _ = std::thread::spawn(|| {
-                std::thread::sleep(std::time::Duration::from_millis(2000));
-                log::logger().flush();
-            });
-            FileLogger(Some(Mutex::new(BufWriter::with_capacity(FILE_LOG_BUFFER, file))))
-        },
-    )
-});
+/// Deletes the oldest logs with the provided extension until only `keep` of them are left. Logs are named after the time
+/// they were created at, so sorting them by name sorts them from the oldest to the newest.
+fn rotate_logs(extension: &str, keep: usize) {
+    let mut logs: Vec<PathBuf> = match std::fs::read_dir(LOG_PATH) {
+        Ok(dir) => dir
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().and_then(|ext| ext.to_str()) == Some(extension))
+            .collect(),
+        Err(_) => return,
+    };
+
+    if logs.len() <= keep {
+        return;
+    }
+
+    logs.sort();
+
+    for path in logs.iter().take(logs.len() - keep) {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+static LOG_RETENTION: Lazy<usize> = Lazy::new(|| config::log_retention().max(1));
+static LOG_MAX_SIZE: Lazy<usize> = Lazy::new(config::log_max_size);
+static JSON_LOGGING: Lazy<bool> = Lazy::new(config::json_logging_enabled);
+
+// Summon the file loggers and create a file for them based on the current time (requires time to be initialized)
+static FILE_WRITER: Lazy<FileLogger> = Lazy::new(|| FileLogger::new("log"));
+static JSON_WRITER: Lazy<FileLogger> = Lazy::new(|| FileLogger::new("jsonl"));
+
+/// The logging level of the modules which were given one in the configuration, from the most to the least specific, along
+/// with the prefix of their submodules
+static MODULE_FILTERS: OnceCell<Vec<(String, String, LevelFilter)>> = OnceCell::new();
+static DEFAULT_FILTER: OnceCell<LevelFilter> = OnceCell::new();
 
 struct ArcLogger;
 
 static LOGGER: ArcLogger = ArcLogger;
 
 pub fn init(filter: LevelFilter) -> Result<(), SetLoggerError> {
-    log::set_logger(&LOGGER).map(|()| log::set_max_level(filter))
+    let mut invalid = Vec::new();
+
+    let mut module_filters: Vec<(String, String, LevelFilter)> = config::module_log_levels()
+        .into_iter()
+        .filter_map(|(module, level)| match LevelFilter::from_str(&level) {
+            Ok(level) => {
+                let prefix = format!("{}::", module);
+                Some((module, prefix, level))
+            },
+            Err(_) => {
+                invalid.push((module, level));
+                None
+            },
+        })
+        .collect();
+
+    module_filters.sort_by(|(a, ..), (b, ..)| b.len().cmp(&a.len()));
+
+    // The maximum level has to let through the records of the most verbose module, the others are filtered afterwards
+    let max_level = module_filters.iter().map(|(.., level)| *level).fold(filter, LevelFilter::max);
+
+    let _ = MODULE_FILTERS.set(module_filters);
+    let _ = DEFAULT_FILTER.set(filter);
+
+    log::set_logger(&LOGGER).map(|()| log::set_max_level(max_level))?;
+
+    // The invalid levels can only be reported once there is a logger to report them to
+    for (module, level) in invalid {
+        warn!("Invalid logging level '{}' for module '{}'.", level, module);
+    }
+
+    Ok(())
+}
+
+/// Gets the logging level of a module, which is the one of the most specific module filter matching it
+fn module_filter(module_path: &str) -> LevelFilter {
+    let default = DEFAULT_FILTER.get().copied().unwrap_or(LevelFilter::Trace);
+
+    MODULE_FILTERS
+        .get()
+        .and_then(|filters| {
+            filters
+                .iter()
+                .find(|(module, prefix, _)| module_path == module || module_path.starts_with(prefix.as_str()))
+        })
+        .map_or(default, |(.., level)| *level)
+}
+
+/// Copies the key-values of a record, such as the `hash` and `path` of the file it is about, into its JSON object
+struct JsonFields<'a>(&'a mut serde_json::Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        // Hashes are written like everywhere else in the logs, so that they can be looked for the same way
+        let value = match (key.as_str(), value.to_u64()) {
+            ("hash", Some(hash)) => format!("{:#x}", hash),
+            _ => value.to_string(),
+        };
+
+        self.0.insert(key.as_str().to_string(), serde_json::Value::String(value));
+        Ok(())
+    }
+}
+
+fn json_record(record: &Record, message: &str) -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |time| time.as_millis() as u64);
+
+    let mut json = serde_json::json!({
+        "timestamp": timestamp,
+        "level": record.level().as_str(),
+        "module": record.module_path(),
+        "target": record.target(),
+        "message": message,
+    });
+
+    if let (Some(file), Some(line)) = (record.file(), record.line()) {
+        json["source"] = serde_json::json!(format!("{}:{}", file, line));
+    }
+
+    // The records about files carry their hash or their path, which are the fields to look for across reports
+    if let Some(fields) = json.as_object_mut() {
+        let _ = record.key_values().visit(&mut JsonFields(fields));
+    }
+
+    format!("{}\n", json)
 }
 
 impl log::Log for ArcLogger {
@@ -117,6 +287,10 @@ impl log::Log for ArcLogger {
             None => return,
         };
 
+        if record.level() > module_filter(module_path) {
+            return;
+        }
+
         let skip_mod_path = record.target() == "no-mod-path";
 
         let message = if record.level() == LevelFilter::Debug && !skip_mod_path {
@@ -132,22 +306,28 @@ impl log::Log for ArcLogger {
             format!("{}\n", record.args())
         };
 
+        let write_to_file = || {
+            if config::file_logging_enabled() {
+                FILE_WRITER.write(strip_ansi_escapes::strip(&message).unwrap_or_default());
+
+                if *JSON_LOGGING {
+                    let args = format!("{}", record.args());
+                    let args = String::from_utf8(strip_ansi_escapes::strip(args).unwrap_or_default()).unwrap_or_default();
+                    JSON_WRITER.write(json_record(record, &args));
+                }
+            }
+        };
+
         // We allow two different log targets, one for specifically logging to the skyline logger and the other for specifically
         // logging to a file. If no target is mentioned (or one that doesn't exist) we log to both.
         match record.target() {
             "std" => {
                 print!("{}", message);
             },
-            "file" => {
-                if config::file_logging_enabled() {
-                    FILE_WRITER.write(strip_ansi_escapes::strip(message).unwrap_or_default());
-                }
-            },
+            "file" => write_to_file(),
             _ => {
                 print!("{}", message);
-                if config::file_logging_enabled() {
-                    FILE_WRITER.write(strip_ansi_escapes::strip(message).unwrap_or_default());
-                }
+                write_to_file();
             },
         }
     }
@@ -155,12 +335,10 @@ impl log::Log for ArcLogger {
     // Only matters for writing to a file
     fn flush(&self) {
         if config::file_logging_enabled() {
-            if let Some(writer) = &**FILE_WRITER {
-                if let Some(mut writer) = writer.try_lock() {
-                    if let Err(err) = writer.flush() {
-                        error!(target: "std", "Failed to flush file logger! Reason: {:?}", err)
-                    }
-                }
+            FILE_WRITER.flush();
+
+            if *JSON_LOGGING {
+                JSON_WRITER.flush();
             }
         }
     }
@@ -1,24 +1,29 @@
 // #![feature(proc_macro_hygiene)]
 
 use std::{
-    collections::{HashMap, HashSet},
-    path::Path,
+    collections::HashSet,
+    path::{Path, PathBuf},
 };
 
 use serde::{Deserialize, Serialize};
 use skyline_web::Webpage;
-use smash_arc::Hash40;
 
-use crate::config;
+use crate::{
+    config,
+    fs::{archive, folder_name, mod_id, resolve_dependencies, violations_dialog_text, ConflictReport, DependencyViolation},
+};
 
 #[derive(Debug, Serialize)]
 pub struct Information {
     entries: Vec<Entry>,
     workspace: String,
+    priority: Vec<u32>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Entry {
+    /// The index of the mod in the menu, which has nothing to do with the `id` of its `info.toml`
+    #[serde(skip_deserializing)]
     id: Option<u32>,
     folder_name: Option<String>,
     is_disabled: Option<bool>,
@@ -27,6 +32,8 @@ pub struct Entry {
     version: Option<String>,
     description: Option<String>,
     category: Option<String>,
+    #[serde(skip_deserializing)]
+    conflicts: Option<Vec<String>>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -34,10 +41,27 @@ pub enum ArcadiaMessage {
     ToggleMod { id: usize, state: bool },
     ChangeAll { state: bool },
     ChangeIndexes { state: bool, indexes: Vec<usize> },
+    ChangePriority { id: usize, raise: bool },
     Closure,
 }
 
-pub fn get_mods(presets: &HashSet<Hash40>) -> Vec<Entry> {
+/// Moves a mod root up or down in the priority list. Mods which are not in the list yet are added at the bottom when raised,
+/// and mods at the bottom of the list are removed from it when lowered.
+fn move_priority(priority: &mut Vec<String>, id: String, raise: bool) {
+    match priority.iter().position(|x| *x == id) {
+        Some(0) if raise => {},
+        Some(idx) if raise => priority.swap(idx, idx - 1),
+        Some(idx) if idx + 1 == priority.len() => {
+            priority.remove(idx);
+        },
+        Some(idx) => priority.swap(idx, idx + 1),
+        None if raise => priority.push(id),
+        None => {},
+    }
+}
+
+pub fn get_mods(presets: &HashSet<String>) -> Vec<Entry> {
+    let conflicts = ConflictReport::load();
     let mut id: u32 = 0;
     std::fs::read_dir(&config::umm_path())
         .unwrap()
@@ -45,15 +69,29 @@ pub fn get_mods(presets: &HashSet<Hash40>) -> Vec<Entry> {
         .filter_map(|(_i, path)| {
             let path_to_be_used = path.unwrap().path();
 
+            // Mod archives can be toggled like folders
             if path_to_be_used.is_file() {
-                return None;
+                if !archive::is_archive(&path_to_be_used) {
+                    return None;
+                }
+
+                archive::register_archive_root(&path_to_be_used);
             }
 
-            let disabled = !presets.contains(&Hash40::from(path_to_be_used.to_str().unwrap()));
+            let disabled = !presets.contains(&mod_id(&path_to_be_used));
 
             let folder_name = Path::new(&path_to_be_used).file_name().unwrap().to_os_string().into_string().unwrap();
 
-            let info_path = format!("{}/info.toml", path_to_be_used.display());
+            let info_path = path_to_be_used.join("info.toml");
+
+            let contested: Vec<String> = conflicts
+                .contested_by(&path_to_be_used)
+                .into_iter()
+                .map(|(local, winner)| {
+                    let winner = winner.file_name().and_then(|name| name.to_str()).unwrap_or("???");
+                    format!("{} (loaded from {})", local.display(), winner)
+                })
+                .collect();
 
             let default_entry = Entry {
                 id: Some(id),
@@ -62,10 +100,11 @@ pub fn get_mods(presets: &HashSet<Hash40>) -> Vec<Entry> {
                 version: Some("???".to_string()),
                 // description: Some("".to_string()),
                 category: Some("Misc".to_string()),
+                conflicts: Some(contested.clone()),
                 ..Default::default()
             };
 
-            let mod_info = match toml::from_str::<Entry>(&std::fs::read_to_string(&info_path).unwrap_or_default()) {
+            let mod_info = match toml::from_str::<Entry>(&String::from_utf8_lossy(&archive::read_mod_file(&info_path).unwrap_or_default())) {
                 Ok(res) => Entry {
                     id: Some(id),
                     folder_name: Some(folder_name.clone()),
@@ -81,6 +120,7 @@ pub fn get_mods(presets: &HashSet<Hash40>) -> Vec<Entry> {
                         }
                     }),
                     description: Some(res.description.unwrap_or_default().replace('\n', "<br />")),
+                    conflicts: Some(contested),
                 },
                 Err(e) => {
                     skyline_web::DialogOk::ok(&format!("The following info.toml is not valid: \n\n* '{}'\n\nError: {}", folder_name, e,));
@@ -105,15 +145,33 @@ pub fn show_arcadia(workspace: Option<String>) {
 
     let mut storage = config::GLOBAL_CONFIG.lock().unwrap();
     let workspace_name: String = workspace.unwrap_or_else(|| storage.get_field("workspace").unwrap_or_else(|_| "Default".to_string()));
-    let workspace_list: HashMap<String, String> = storage.get_field_json("workspace_list").unwrap_or_default();
-    let preset_name = &workspace_list[&workspace_name];
+    let preset_name = config::workspace_preset_name(&storage, &workspace_name);
 
-    let presets: HashSet<Hash40> = storage.get_field_json(preset_name).unwrap_or_default();
+    let presets: HashSet<String> = storage.get_field_json(&preset_name).unwrap_or_default();
     let mut new_presets = presets.clone();
 
+    let priority_name = config::priority_field(&preset_name);
+    let priority: Vec<String> = storage.get_field_json(&priority_name).unwrap_or_default();
+    let mut new_priority = priority.clone();
+
+    let entries = get_mods(&presets);
+
+    // The presets hold the identity of the mods, which is looked up once rather than on every message
+    let ids: Vec<String> = entries
+        .iter()
+        .map(|entry| mod_id(&umm_path.join(entry.folder_name.as_ref().unwrap())))
+        .collect();
+
+    // The menu only knows about the indexes of the entries, so translate the priority list to them
+    let priority_ids = priority
+        .iter()
+        .filter_map(|priority_id| ids.iter().position(|id| id == priority_id).and_then(|idx| entries[idx].id))
+        .collect();
+
     let mods: Information = Information {
-        entries: get_mods(&presets),
+        entries,
         workspace: workspace_name.clone(),
+        priority: priority_ids,
     };
 
     // region Setup Preview Images
@@ -121,8 +179,8 @@ pub fn show_arcadia(workspace: Option<String>) {
     for item in &mods.entries {
         let path = &umm_path.join(item.folder_name.as_ref().unwrap()).join("preview.webp");
 
-        if path.exists() {
-            images.push((format!("img/{}", item.id.unwrap()), std::fs::read(path).unwrap()));
+        if let Ok(data) = archive::read_mod_file(path) {
+            images.push((format!("img/{}", item.id.unwrap()), data));
         };
     }
 
@@ -157,17 +215,15 @@ pub fn show_arcadia(workspace: Option<String>) {
     while let Ok(message) = session.recv_json::<ArcadiaMessage>() {
         match message {
             ArcadiaMessage::ToggleMod { id, state } => {
-                let path = format!("{}/{}", umm_path.display(), mods.entries[id].folder_name.as_ref().unwrap());
-                let hash = Hash40::from(path.as_str());
-                debug!("Setting {} to {}", path, state);
+                debug!("Setting {} to {}", ids[id], state);
 
                 if state {
-                    new_presets.insert(hash);
+                    new_presets.insert(ids[id].clone());
                 } else {
-                    new_presets.remove(&hash);
+                    new_presets.remove(&ids[id]);
                 }
 
-                debug!("{} has been {}", path, state);
+                debug!("{} has been {}", ids[id], state);
             },
             ArcadiaMessage::ChangeAll { state } => {
                 debug!("Changing all to {}", state);
@@ -175,27 +231,24 @@ pub fn show_arcadia(workspace: Option<String>) {
                 if !state {
                     new_presets.clear();
                 } else {
-                    for item in mods.entries.iter() {
-                        let path = format!("{}/{}", umm_path.display(), item.folder_name.as_ref().unwrap());
-                        let hash = Hash40::from(path.as_str());
-
-                        new_presets.insert(hash);
-                    }
+                    new_presets.extend(ids.iter().cloned());
                 }
             },
             ArcadiaMessage::ChangeIndexes { state, indexes } => {
                 for idx in indexes {
-                    let path = format!("{}/{}", umm_path.display(), mods.entries[idx].folder_name.as_ref().unwrap());
-                    let hash = Hash40::from(path.as_str());
-                    debug!("Setting {} to {}", path, state);
+                    debug!("Setting {} to {}", ids[idx], state);
 
                     if state {
-                        new_presets.insert(hash);
+                        new_presets.insert(ids[idx].clone());
                     } else {
-                        new_presets.remove(&hash);
+                        new_presets.remove(&ids[idx]);
                     }
                 }
             },
+            ArcadiaMessage::ChangePriority { id, raise } => {
+                debug!("Changing the priority of {} (raise: {})", ids[id], raise);
+                move_priority(&mut new_priority, ids[id].clone(), raise);
+            },
             ArcadiaMessage::Closure => {
                 session.exit();
                 session.wait_for_exit();
@@ -204,14 +257,46 @@ pub fn show_arcadia(workspace: Option<String>) {
         }
     }
 
+    // Report the unfulfilled dependencies before leaving, so that broken combinations of mods don't reach the game
+    let installed: Vec<PathBuf> = mods
+        .entries
+        .iter()
+        .filter_map(|entry| entry.folder_name.as_ref().map(|name| umm_path.join(name)))
+        .collect();
+    let mut enabled: Vec<PathBuf> = installed.iter().filter(|root| new_presets.contains(&mod_id(root))).cloned().collect();
+    let violations = resolve_dependencies(&mut enabled, &installed, false);
+
+    if !violations.is_empty() {
+        let requirements: Vec<&PathBuf> = violations
+            .iter()
+            .filter_map(|x| match x {
+                DependencyViolation::DisabledRequirement { name, .. } => {
+                    installed.iter().find(|root| folder_name(root) == name.as_str())
+                },
+                _ => None,
+            })
+            .collect();
+
+        if requirements.is_empty() {
+            skyline_web::DialogOk::ok(&violations_dialog_text(&violations, "These mods might not work properly."));
+        } else if skyline_web::Dialog::yes_no(&violations_dialog_text(&violations, "Would you like to enable the required mods?")) {
+            new_presets.extend(requirements.into_iter().map(|root| mod_id(root)));
+        }
+    }
+
     let active_workspace: String = storage.get_field("workspace").unwrap_or_else(|_| "Default".to_string());
 
     storage.set_field_json(&preset_name, &new_presets).unwrap();
+    storage.set_field_json(&priority_name, &new_priority).unwrap();
     storage.flush();
 
     drop(storage);
 
     if new_presets != presets {
+        crate::api::event::send_presets_changed(&workspace_name, new_presets.len());
+    }
+
+    if new_presets != presets || new_priority != priority {
         // Acquire the filesystem so we can check if it's already finished or not (for boot-time mod manager)
         if let Some(_filesystem) = crate::GLOBAL_FILESYSTEM.try_read() {
             if active_workspace.eq(&workspace_name) && skyline_web::Dialog::yes_no("Your preset has successfully been updated!<br>Your changes will take effect on the next boot.<br>Would you like to reboot the game to reload your mods?") {
@@ -220,3 +305,43 @@ pub fn show_arcadia(workspace: Option<String>) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn priority(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn raising_moves_up_or_adds_at_the_bottom() {
+        let mut list = priority(&["a", "b", "c"]);
+
+        move_priority(&mut list, String::from("c"), true);
+        assert_eq!(list, priority(&["a", "c", "b"]));
+
+        // The top of the list stays there
+        move_priority(&mut list, String::from("a"), true);
+        assert_eq!(list, priority(&["a", "c", "b"]));
+
+        move_priority(&mut list, String::from("d"), true);
+        assert_eq!(list, priority(&["a", "c", "b", "d"]));
+    }
+
+    #[test]
+    fn lowering_moves_down_or_removes_from_the_bottom() {
+        let mut list = priority(&["a", "b", "c"]);
+
+        move_priority(&mut list, String::from("a"), false);
+        assert_eq!(list, priority(&["b", "a", "c"]));
+
+        move_priority(&mut list, String::from("c"), false);
+        assert_eq!(list, priority(&["b", "a"]));
+
+        // Mods without a priority are left alone
+        move_priority(&mut list, String::from("d"), false);
+        assert_eq!(list, priority(&["b", "a"]));
+    }
+}
+
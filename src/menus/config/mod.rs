@@ -1,6 +1,6 @@
 // #![feature(proc_macro_hygiene)]
 
-use log::info;
+use log::{error, info};
 use serde::Deserialize;
 use skyline_config::{ConfigStorage, StorageHolder};
 use skyline_web::{Visibility, Webpage};
@@ -11,6 +11,37 @@ pub struct ConfigChanged {
     value: String,
 }
 
+/// Formats a message which replaces the text of an element of the page instead of toggling it
+fn text_message(id: &str, text: &str) -> String {
+    format!("{}|{}", id, text)
+}
+
+/// The updater only finds the latest release and prerelease on GitHub, so older pinned versions have to be installed by hand
+fn pinned_version_text<CS: ConfigStorage>(storage: &StorageHolder<CS>) -> String {
+    storage
+        .get_field::<String>("pinned_version")
+        .ok()
+        .filter(|version| !version.trim().is_empty())
+        .map(|version| {
+            format!(
+                "{} (downloaded only while it is the latest release or prerelease, otherwise install it from {})",
+                version.trim(),
+                crate::update::install::LOCAL_RELEASE_PATH
+            )
+        })
+        .unwrap_or_else(|| String::from("None"))
+}
+
+fn ignored_versions_text<CS: ConfigStorage>(storage: &StorageHolder<CS>) -> String {
+    let versions: Vec<String> = storage.get_field_json("ignored_versions").unwrap_or_default();
+
+    if versions.is_empty() {
+        String::from("None")
+    } else {
+        versions.join(", ")
+    }
+}
+
 // Is this trash? Yes
 // Did I have a choice? No
 pub fn show_config_editor<CS: ConfigStorage>(storage: &mut StorageHolder<CS>) {
@@ -41,18 +72,44 @@ pub fn show_config_editor<CS: ConfigStorage>(storage: &mut StorageHolder<CS>) {
         session.send("legacy_discovery");
     }
 
+    if storage.get_flag("strict_param_patches") {
+        session.send("strict_param_patches");
+    }
+
     if storage.get_flag("debug") {
         session.send("debug");
     }
 
+    let mut follow_account = crate::config::follows_household(storage);
+
+    if follow_account {
+        session.send("follow_account");
+    }
+
     if storage.get_flag("log_to_file") {
         session.send("log_to_file");
     }
 
+    if storage.get_flag("log_to_json") {
+        session.send("log_to_json");
+    }
+
     if storage.get_flag("auto_update") {
         session.send("auto_update");
     }
 
+    if storage.get_flag("never_downgrade") {
+        session.send("never_downgrade");
+    }
+
+    if storage.get_flag("allow_unverified_updates") {
+        session.send("allow_unverified_updates");
+    }
+
+    // Versions are edited in the configuration file, the editor only shows them and allows to clear them
+    session.send(&text_message("pinned_version", &pinned_version_text(storage)));
+    session.send(&text_message("ignored_versions", &ignored_versions_text(storage)));
+
     let region: String = storage.get_field("region").unwrap();
     session.send(&region);
 
@@ -91,18 +148,74 @@ pub fn show_config_editor<CS: ConfigStorage>(storage: &mut StorageHolder<CS>) {
                 info!("Set legacy_discovery flag to {}", curr_value);
                 session.send("legacy_discovery");
             },
+            "strict_param_patches" => {
+                let curr_value = !storage.get_flag("strict_param_patches");
+                storage.set_flag("strict_param_patches", curr_value).unwrap();
+                info!("Set strict_param_patches flag to {}", curr_value);
+                session.send("strict_param_patches");
+                reboot_required = true;
+            },
+            "follow_account" => {
+                follow_account = !follow_account;
+
+                if follow_account {
+                    let workspace: String = storage.get_field("workspace").unwrap_or_else(|_| "Default".to_string());
+
+                    if let Err(e) = crate::config::follow_household(&workspace) {
+                        error!("Failed to share the configuration with the household. Reason: {:?}", e);
+                        follow_account = false;
+                        continue;
+                    }
+                } else {
+                    crate::config::leave_household();
+                    // The configuration being edited is the one of the household when the account followed it on boot
+                    crate::config::unassign_account_workspace(storage);
+                }
+
+                info!("Set follow_account to {}", follow_account);
+                session.send("follow_account");
+                reboot_required = true;
+            },
             "log_to_file" => {
                 let curr_value = !storage.get_flag("log_to_file");
                 storage.set_flag("log_to_file", curr_value).unwrap();
                 info!("Set log_to_file flag to {}", curr_value);
                 session.send("log_to_file");
             },
+            "log_to_json" => {
+                let curr_value = !storage.get_flag("log_to_json");
+                storage.set_flag("log_to_json", curr_value).unwrap();
+                info!("Set log_to_json flag to {}", curr_value);
+                session.send("log_to_json");
+            },
             "auto_update" => {
                 let curr_value = !storage.get_flag("auto_update");
                 storage.set_flag("auto_update", curr_value).unwrap();
                 info!("Set auto_update flag to {}", curr_value);
                 session.send("auto_update");
             },
+            "never_downgrade" => {
+                let curr_value = !storage.get_flag("never_downgrade");
+                storage.set_flag("never_downgrade", curr_value).unwrap();
+                info!("Set never_downgrade flag to {}", curr_value);
+                session.send("never_downgrade");
+            },
+            "allow_unverified_updates" => {
+                let curr_value = !storage.get_flag("allow_unverified_updates");
+                storage.set_flag("allow_unverified_updates", curr_value).unwrap();
+                info!("Set allow_unverified_updates flag to {}", curr_value);
+                session.send("allow_unverified_updates");
+            },
+            "unpin" => {
+                storage.set_field("pinned_version", "").unwrap();
+                info!("Cleared the pinned version");
+                session.send(&text_message("pinned_version", &pinned_version_text(storage)));
+            },
+            "unignore" => {
+                storage.set_field_json("ignored_versions", &Vec::<String>::new()).unwrap();
+                info!("Cleared the ignored versions");
+                session.send(&text_message("ignored_versions", &ignored_versions_text(storage)));
+            },
             _ => break,
         }
     }
@@ -36,7 +36,36 @@ pub fn show_main_menu() {
             "http://localhost/config" => {
                 show_config_editor(&mut crate::config::GLOBAL_CONFIG.lock().unwrap());
             },
+            "http://localhost/reload" => {
+                reload_mods();
+            },
             _ => {},
         },
     }
 }
+
+/// Reloads the modified files of the enabled mods and tells the user how it went
+fn reload_mods() {
+    // The filesystem does not exist yet when the menu is opened during boot
+    if !crate::fs::GlobalFilesystem::is_init() {
+        skyline_web::DialogOk::ok("Mods can only be reloaded once the game has finished booting.");
+        return;
+    }
+
+    let report = match crate::fs::reload_mods() {
+        Some(report) => report,
+        None => return,
+    };
+
+    if report.needs_reboot.is_empty() {
+        skyline_web::DialogOk::ok(&format!("{} modified files were reloaded.", report.reloaded.len()));
+    } else {
+        let files = report.needs_reboot.iter().map(|path| path.display().to_string()).collect::<Vec<String>>().join("<br>* ");
+
+        skyline_web::DialogOk::ok(&format!(
+            "{} modified files were reloaded.<br>The following files need a reboot to be reloaded:<br><br>* {}",
+            report.reloaded.len(),
+            files
+        ));
+    }
+}
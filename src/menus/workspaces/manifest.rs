@@ -0,0 +1,329 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use skyline_config::{ConfigStorage, StorageHolder};
+use thiserror::Error;
+use walkdir::WalkDir;
+
+use crate::{
+    config::{self, WorkspaceSettings},
+    fs::{archive, installed_mod_roots, mod_id},
+};
+
+/// Where workspaces are exported to, and where the manifests to import are looked for
+pub static MANIFESTS_PATH: &str = "sd:/ultimate/arcropolis/workspaces";
+
+#[derive(Error, Debug)]
+pub enum ManifestError {
+    #[error("the workspace '{0}' does not exist")]
+    UnknownWorkspace(String),
+    #[error("the manifest could not be parsed: {0}")]
+    Parse(#[from] serde_json::Error),
+    #[error("IO error: {0}")]
+    IO(#[from] std::io::Error),
+}
+
+/// A workspace which can be shared between consoles, since mods are identified by their identity and folder name rather
+/// than by their path
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WorkspaceManifest {
+    pub workspace: String,
+    pub arcropolis_version: String,
+    /// The enabled mods, from highest to lowest load priority
+    pub mods: Vec<ManifestMod>,
+    #[serde(default)]
+    pub settings: WorkspaceSettings,
+    /// The `config.json` of the workspace, if it has one
+    #[serde(default)]
+    pub config: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ManifestMod {
+    /// The identity of the mod in the presets, see `mod_id`
+    #[serde(default)]
+    pub id: Option<String>,
+    pub folder_name: String,
+    /// The version from the `info.toml` of the mod
+    pub version: Option<String>,
+    /// Local path of every file of the mod => SHA-256 of its content
+    pub files: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct ModInfo {
+    version: Option<String>,
+}
+
+/// What could not be matched when importing a manifest
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub workspace: String,
+    pub enabled: usize,
+    pub missing: Vec<String>,
+    /// Folder name, version in the manifest, installed version
+    pub mismatched_versions: Vec<(String, String, String)>,
+    /// Folder name, number of files which are missing or have another content
+    pub changed_files: Vec<(String, usize)>,
+}
+
+/// Folder and workspace names come from the SD card and other consoles, so they are escaped before being shown in a dialog
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+impl ImportReport {
+    /// Formats the report for a dialog
+    pub fn to_html(&self) -> String {
+        let mut text = format!(
+            "The workspace {} was imported with {} mods enabled.",
+            escape_html(&self.workspace),
+            self.enabled
+        );
+
+        if !self.missing.is_empty() {
+            let missing: Vec<String> = self.missing.iter().map(|name| escape_html(name)).collect();
+            text.push_str(&format!("<br><br>Missing mods:<br>* {}", missing.join("<br>* ")));
+        }
+
+        if !self.mismatched_versions.is_empty() {
+            let versions: Vec<String> = self
+                .mismatched_versions
+                .iter()
+                .map(|(name, expected, found)| {
+                    format!(
+                        "{} (expected {}, found {})",
+                        escape_html(name),
+                        escape_html(expected),
+                        escape_html(found)
+                    )
+                })
+                .collect();
+            text.push_str(&format!("<br><br>Mods with another version:<br>* {}", versions.join("<br>* ")));
+        }
+
+        if !self.changed_files.is_empty() {
+            let files: Vec<String> = self
+                .changed_files
+                .iter()
+                .map(|(name, count)| format!("{} ({} files)", escape_html(name), count))
+                .collect();
+            text.push_str(&format!("<br><br>Mods with different files:<br>* {}", files.join("<br>* ")));
+        }
+
+        text
+    }
+}
+
+fn mod_version(root: &Path) -> Option<String> {
+    let data = archive::read_mod_file(root.join("info.toml")).ok()?;
+    toml::from_str::<ModInfo>(&String::from_utf8_lossy(&data)).ok()?.version
+}
+
+/// Gets the local path of every file of a mod, whether the mod is a folder or an archive
+fn mod_files(root: &Path) -> Vec<PathBuf> {
+    if archive::is_archive(root) {
+        return archive::get_archive(root).map_or_else(|_| Vec::new(), |archive| archive.files().cloned().collect());
+    }
+
+    WalkDir::new(root)
+        .into_iter()
+        .flatten()
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| entry.path().strip_prefix(root).ok().map(Path::to_path_buf))
+        .collect()
+}
+
+/// Hashes a file of a mod with SHA-256, formatted like `sha256sum` does. The file is streamed through the hasher, since
+/// mods can have files which are too big to be read at once.
+fn file_checksum(path: &Path) -> Option<String> {
+    let mut hasher = Sha256::new();
+    archive::copy_mod_file(path, &mut hasher).ok()?;
+    Some(hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Hashes every file of a mod. This reads the whole mod, so it is only done when a manifest is made or imported.
+fn mod_checksums(root: &Path) -> BTreeMap<String, String> {
+    mod_files(root)
+        .into_iter()
+        .filter_map(|local| {
+            let checksum = file_checksum(&root.join(&local))?;
+            // Manifests use the same separators on every console
+            Some((local.to_string_lossy().replace('\\', "/"), checksum))
+        })
+        .collect()
+}
+
+/// Gets the mods installed in the mods folder and the extra paths, by folder name. When two of them have the same name,
+/// the one of the mods folder is used, like it is listed first.
+fn installed_mods() -> HashMap<String, PathBuf> {
+    let mut installed = HashMap::new();
+
+    for root in installed_mod_roots(&config::extra_paths()) {
+        if let Some(name) = root.file_name().and_then(|name| name.to_str()) {
+            installed.entry(name.to_string()).or_insert(root);
+        }
+    }
+
+    installed
+}
+
+/// Gets the name of the manifest file of a workspace, without the characters which would make it a path
+fn manifest_file_name(name: &str) -> String {
+    let name: String = name.chars().filter(|c| !matches!(c, '/' | '\\' | ':')).collect();
+    format!("{}.json", name)
+}
+
+/// Writes the manifest of a workspace to the manifests folder, and returns its path
+pub fn export_workspace<CS: ConfigStorage>(storage: &StorageHolder<CS>, name: &str) -> Result<PathBuf, ManifestError> {
+    let workspace_list: HashMap<String, String> = storage.get_field_json("workspace_list").unwrap_or_default();
+    let preset_name = workspace_list
+        .get(name)
+        .ok_or_else(|| ManifestError::UnknownWorkspace(name.to_string()))?;
+
+    let presets: HashSet<String> = storage.get_field_json(preset_name).unwrap_or_default();
+    let priority: Vec<String> = storage.get_field_json(config::priority_field(preset_name)).unwrap_or_default();
+
+    let mut enabled: Vec<(String, PathBuf)> = installed_mods()
+        .into_values()
+        .map(|root| (mod_id(&root), root))
+        .filter(|(id, _)| presets.contains(id))
+        .collect();
+
+    // Mods which were never given a priority come after the others, like they do during discovery
+    enabled.sort_by_key(|(id, root)| (priority.iter().position(|x| x == id).unwrap_or(usize::MAX), root.clone()));
+
+    let mods = enabled
+        .iter()
+        .map(|(id, root)| ManifestMod {
+            id: Some(id.clone()),
+            folder_name: root.file_name().unwrap().to_string_lossy().into_owned(),
+            version: mod_version(root),
+            files: mod_checksums(root),
+        })
+        .collect();
+
+    let manifest = WorkspaceManifest {
+        workspace: name.to_string(),
+        arcropolis_version: env!("CARGO_PKG_VERSION").to_string(),
+        mods,
+        settings: storage.get_field_json(config::settings_field(preset_name)).unwrap_or_default(),
+        config: config::preset_mod_config(storage, preset_name),
+    };
+
+    std::fs::create_dir_all(MANIFESTS_PATH)?;
+
+    let path = Path::new(MANIFESTS_PATH).join(manifest_file_name(name));
+    std::fs::write(&path, serde_json::to_string_pretty(&manifest)?)?;
+
+    info!("Exported the workspace '{}' to '{}'.", name, path.display());
+
+    Ok(path)
+}
+
+/// Gets the names of the manifests which can be imported
+pub fn available_manifests() -> Vec<String> {
+    let mut manifests: Vec<String> = std::fs::read_dir(MANIFESTS_PATH)
+        .map(|dir| {
+            dir.flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().and_then(|ext| ext.to_str()) == Some("json"))
+                .filter_map(|path| Some(path.file_stem()?.to_str()?.to_string()))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    manifests.sort();
+    manifests
+}
+
+/// Creates a new workspace from a manifest of the manifests folder. Mods are looked for by identity, then by folder name,
+/// and the ones which are missing or differ from the ones the manifest was made with are reported.
+pub fn import_workspace<CS: ConfigStorage>(storage: &mut StorageHolder<CS>, manifest_name: &str) -> Result<ImportReport, ManifestError> {
+    let data = std::fs::read(Path::new(MANIFESTS_PATH).join(format!("{}.json", manifest_name)))?;
+    let manifest: WorkspaceManifest = serde_json::from_slice(&data)?;
+
+    let mut workspace_list: HashMap<String, String> = storage.get_field_json("workspace_list").unwrap_or_default();
+
+    // Never overwrite an existing workspace, the imported one gets a number instead
+    let mut name = manifest.workspace.clone();
+    let mut idx = 2;
+
+    while workspace_list.contains_key(&name) {
+        name = format!("{} ({})", manifest.workspace, idx);
+        idx += 1;
+    }
+
+    let installed = installed_mods();
+    let installed_ids: HashMap<String, &PathBuf> = installed.values().map(|root| (mod_id(root), root)).collect();
+    let mut report = ImportReport {
+        workspace: name.clone(),
+        ..Default::default()
+    };
+
+    let mut presets: HashSet<String> = HashSet::new();
+    let mut priority: Vec<String> = Vec::new();
+
+    for entry in manifest.mods.iter() {
+        let by_id = entry.id.as_ref().and_then(|id| installed_ids.get(id).copied());
+
+        let root = match by_id.or_else(|| installed.get(&entry.folder_name)) {
+            Some(root) => root,
+            None => {
+                report.missing.push(entry.folder_name.clone());
+                continue;
+            },
+        };
+
+        let version = mod_version(root);
+
+        if entry.version != version {
+            report.mismatched_versions.push((
+                entry.folder_name.clone(),
+                entry.version.clone().unwrap_or_else(|| String::from("???")),
+                version.unwrap_or_else(|| String::from("???")),
+            ));
+        }
+
+        let checksums = mod_checksums(root);
+        let changed = entry.files.iter().filter(|(local, hash)| checksums.get(*local) != Some(*hash)).count();
+
+        if changed != 0 {
+            report.changed_files.push((entry.folder_name.clone(), changed));
+        }
+
+        let id = mod_id(root);
+        presets.insert(id.clone());
+        priority.push(id);
+    }
+
+    report.enabled = presets.len();
+
+    let preset_name = format!("{}_preset{}", name, workspace_list.len() + 1);
+
+    storage.set_field_json(&preset_name, &presets).unwrap();
+    storage.set_field_json(config::priority_field(&preset_name), &priority).unwrap();
+    storage.set_field_json(config::settings_field(&preset_name), &manifest.settings).unwrap();
+
+    storage
+        .set_field_json(
+            config::mod_config_field(&preset_name),
+            manifest.config.as_ref().unwrap_or(&serde_json::Value::Null),
+        )
+        .unwrap();
+
+    workspace_list.insert(name, preset_name);
+    storage.set_field_json("workspace_list", &workspace_list).unwrap();
+
+    info!("Imported the manifest '{}' as the workspace '{}'.", manifest_name, report.workspace);
+
+    Ok(report)
+}
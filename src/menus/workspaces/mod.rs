@@ -1,15 +1,25 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
 
+use log::LevelFilter;
 use serde::{Deserialize, Serialize};
 use skyline_web::Webpage;
-use smash_arc::Hash40;
 
 use crate::config;
 
+mod manifest;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Information {
     workspaces: Vec<String>,
     active_workspace: String,
+    manifests: Vec<String>,
+    /// The settings of every workspace, by workspace name
+    settings: HashMap<String, config::WorkspaceSettings>,
+    /// The `config.json` of every workspace which has one, by workspace name
+    configs: HashMap<String, serde_json::Value>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -20,9 +30,33 @@ pub enum WorkspacesMessage {
     Rename { source_name: String, target_name: String },
     Remove { name: String },
     Duplicate { source_name: String, target_name: String },
+    EditSettings { name: String, settings: config::WorkspaceSettings, config: Option<serde_json::Value> },
+    Export { name: String },
+    Import { name: String },
     ClosureRequest,
 }
 
+/// Drops the settings which the game would not understand, since they are typed in by hand
+fn validate_settings(name: &str, mut settings: config::WorkspaceSettings) -> config::WorkspaceSettings {
+    if let Some(region) = settings.region.as_ref().filter(|region| !crate::REGIONS.contains(&region.as_str())) {
+        warn!("Ignoring the unknown region '{}' of the workspace '{}'.", region, name);
+        settings.region = None;
+    }
+
+    if let Some(level) = settings.logging_level.as_ref().filter(|level| LevelFilter::from_str(level).is_err()) {
+        warn!("Ignoring the unknown logging level '{}' of the workspace '{}'.", level, name);
+        settings.logging_level = None;
+    }
+
+    settings
+}
+
+/// Exporting and importing report back with a dialog, so they are done once the session is closed
+enum ManifestRequest {
+    Export(String),
+    Import(String),
+}
+
 pub fn show_workspaces() {
     let mut storage = config::GLOBAL_CONFIG.lock().unwrap();
     let mut active_workspace: String = storage.get_field("workspace").unwrap_or_else(|_| "Default".to_string());
@@ -32,9 +66,24 @@ pub fn show_workspaces() {
     let info: Information = Information {
         workspaces: workspace_list.iter().map(|(k, _v)| k.clone()).collect(),
         active_workspace: active_workspace.clone(),
+        manifests: manifest::available_manifests(),
+        settings: workspace_list
+            .iter()
+            .map(|(name, preset_name)| {
+                (
+                    name.clone(),
+                    storage.get_field_json(config::settings_field(preset_name)).unwrap_or_default(),
+                )
+            })
+            .collect(),
+        configs: workspace_list
+            .iter()
+            .filter_map(|(name, preset_name)| Some((name.clone(), config::preset_mod_config(&storage, preset_name)?)))
+            .collect(),
     };
 
     let mut workspace_to_edit: Option<String> = None;
+    let mut manifest_request: Option<ManifestRequest> = None;
 
     let session = Webpage::new()
         .htdocs_dir("contents")
@@ -52,11 +101,19 @@ pub fn show_workspaces() {
     while let Ok(message) = session.recv_json::<WorkspacesMessage>() {
         match message {
             WorkspacesMessage::Create { name } => {
-                workspace_list.insert(name.clone(), format!("{}_preset{}", name, workspace_list.len() + 1));
+                let preset_name = format!("{}_preset{}", name, workspace_list.len() + 1);
+                config::clear_preset(&mut storage, &preset_name);
+                workspace_list.insert(name, preset_name);
                 storage.set_field_json("workspace_list", &workspace_list).unwrap_or_default();
             },
             WorkspacesMessage::SetActive { name } => {
                 active_workspace = name.clone();
+
+                // While following the household, the workspace picked by an account is the one it boots with
+                if config::follows_household(&storage) {
+                    config::assign_account_workspace(&mut storage, &name);
+                }
+
                 storage.set_field("workspace", name).unwrap();
             },
             WorkspacesMessage::Edit { name } => {
@@ -69,23 +126,71 @@ pub fn show_workspaces() {
             WorkspacesMessage::Rename { source_name, target_name } => {
                 let preset_name = workspace_list[&source_name].clone();
                 workspace_list.remove(&source_name);
-                workspace_list.insert(target_name, preset_name);
+                workspace_list.insert(target_name.clone(), preset_name);
                 storage.set_field_json("workspace_list", &workspace_list).unwrap_or_default();
+                config::rename_account_workspace(&mut storage, &source_name, &target_name);
             },
             WorkspacesMessage::Remove { name } => {
-                workspace_list.remove(&name);
+                if let Some(preset_name) = workspace_list.remove(&name) {
+                    config::clear_preset(&mut storage, &preset_name);
+                }
+
+                config::remove_account_workspace(&mut storage, &name);
                 storage.set_field_json("workspace_list", &workspace_list).unwrap_or_default();
             },
             WorkspacesMessage::Duplicate { source_name, target_name } => {
                 let source_preset_name = &workspace_list[&source_name];
                 let target_preset_name = format!("{}_preset{}", target_name, workspace_list.len() + 1);
 
-                let presets: HashSet<Hash40> = storage.get_field_json(source_preset_name).unwrap_or_default();
+                let presets: HashSet<String> = storage.get_field_json(source_preset_name).unwrap_or_default();
+                let settings: config::WorkspaceSettings = storage.get_field_json(config::settings_field(source_preset_name)).unwrap_or_default();
+                let mod_config = config::preset_mod_config(&storage, source_preset_name).unwrap_or(serde_json::Value::Null);
 
                 workspace_list.insert(target_name, target_preset_name.clone());
+                storage.set_field_json(config::settings_field(&target_preset_name), &settings).unwrap();
+                storage
+                    .set_field_json(config::mod_config_field(&target_preset_name), &mod_config)
+                    .unwrap();
+
                 storage.set_field_json(target_preset_name, &presets).unwrap();
                 storage.set_field_json("workspace_list", &workspace_list).unwrap_or_default();
             },
+            WorkspacesMessage::EditSettings {
+                name,
+                settings,
+                config: mod_config,
+            } => {
+                let preset_name = match workspace_list.get(&name) {
+                    Some(preset_name) => preset_name.clone(),
+                    None => continue,
+                };
+
+                let settings = validate_settings(&name, settings);
+
+                storage.set_field_json(config::settings_field(&preset_name), &settings).unwrap();
+                storage
+                    .set_field_json(
+                        config::mod_config_field(&preset_name),
+                        mod_config.as_ref().unwrap_or(&serde_json::Value::Null),
+                    )
+                    .unwrap();
+
+                info!("Edited the settings of the workspace '{}'. They take effect on the next boot.", name);
+            },
+            WorkspacesMessage::Export { name } => {
+                session.wait_for_exit();
+                session.exit();
+                storage.set_field_json("workspace_list", &workspace_list).unwrap_or_default();
+                manifest_request = Some(ManifestRequest::Export(name));
+                break;
+            },
+            WorkspacesMessage::Import { name } => {
+                session.wait_for_exit();
+                session.exit();
+                storage.set_field_json("workspace_list", &workspace_list).unwrap_or_default();
+                manifest_request = Some(ManifestRequest::Import(name));
+                break;
+            },
             WorkspacesMessage::ClosureRequest => {
                 session.wait_for_exit();
                 session.exit();
@@ -100,6 +205,30 @@ pub fn show_workspaces() {
         storage.set_field("workspace", active_workspace.clone()).unwrap();
     }
 
+    let reopen = manifest_request.is_some();
+
+    match manifest_request {
+        Some(ManifestRequest::Export(name)) => match manifest::export_workspace(&storage, &name) {
+            Ok(path) => {
+                skyline_web::DialogOk::ok(&format!("The workspace {} was exported to {}.", name, path.display()));
+            },
+            Err(e) => {
+                error!("Failed to export the workspace '{}'. Reason: {}", name, e);
+                skyline_web::DialogOk::ok(&format!("The workspace {} could not be exported.<br>Reason: {}", name, e));
+            },
+        },
+        Some(ManifestRequest::Import(name)) => match manifest::import_workspace(&mut storage, &name) {
+            Ok(report) => {
+                skyline_web::DialogOk::ok(&report.to_html());
+            },
+            Err(e) => {
+                error!("Failed to import the manifest '{}'. Reason: {}", name, e);
+                skyline_web::DialogOk::ok(&format!("The manifest {} could not be imported.<br>Reason: {}", name, e));
+            },
+        },
+        None => {},
+    }
+
     drop(storage);
 
     match workspace_to_edit {
@@ -111,10 +240,17 @@ pub fn show_workspaces() {
     }
 
     if active_workspace.ne(&prev_set_workspace) {
+        crate::api::event::send_workspace_switched(&prev_set_workspace, &active_workspace);
+
         if let Some(_filesystem) = crate::GLOBAL_FILESYSTEM.try_read() {
             if skyline_web::Dialog::yes_no(format!("Your active workspace has successfully been changed to {}!<br>Your changes will take effect on the next boot.<br>Would you like to reboot the game to reload your mods?", active_workspace)) {
                 unsafe { skyline::nn::oe::RequestToRelaunchApplication() };
             }
         }
     }
+
+    // Go back to the workspaces once the result was shown, so that the imported workspace can be used right away
+    if reopen {
+        show_workspaces();
+    }
 }
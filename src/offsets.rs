@@ -2,11 +2,20 @@ use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use skyline::hooks::{getRegionAddress, Region};
 
+use self::signatures::*;
+
+mod signatures;
+
 static OFFSETS: Lazy<Offsets> = Lazy::new(|| {
     let path = crate::CACHE_PATH.join("offsets.toml");
     let offsets = match std::fs::read_to_string(&path) {
-        Ok(string) => match toml::de::from_str(string.as_str()) {
-            Ok(offsets) => offsets,
+        Ok(string) => match toml::de::from_str::<Offsets>(string.as_str()) {
+            // The cache is only trusted if the code at every offset still looks like what was searched for
+            Ok(offsets) if offsets.is_valid() => offsets,
+            Ok(_) => {
+                warn!("The offsets in 'offsets.toml' do not match the code anymore, searching for them again.");
+                Offsets::new()
+            },
             Err(err) => {
                 error!("Unable to parse 'offsets.toml'. Reason: {:?}", err);
                 Offsets::new()
@@ -26,95 +35,11 @@ static OFFSETS: Lazy<Offsets> = Lazy::new(|| {
         },
         Err(_) => error!("Failed to serialize offsets."),
     }
+
+    offsets.ensure_required();
     offsets
 });
 
-static FILESYSTEM_INFO_ADRP_SEARCH_CODE: &[u8] = &[0xf3, 0x03, 0x00, 0xaa, 0x1f, 0x01, 0x09, 0x6b, 0xe0, 0x04, 0x00, 0x54];
-
-static RES_SERVICE_ADRP_SEARCH_CODE: &[u8] = &[
-    0x04, 0x01, 0x49, 0xfa, 0x21, 0x05, 0x00, 0x54, 0x5f, 0x00, 0x00, 0xf9, 0x7f, 0x00, 0x00, 0xf9,
-];
-
-static LOOKUP_STREAM_HASH_SEARCH_CODE: &[u8] = &[
-    0x29, 0x58, 0x40, 0xf9, 0x28, 0x60, 0x40, 0xf9, 0x2a, 0x05, 0x40, 0xb9, 0x09, 0x0d, 0x0a, 0x8b, 0xaa, 0x01, 0x00, 0x34, 0x5f, 0x01, 0x00, 0xf1,
-];
-
-static TITLE_SCREEN_VERSION_SEARCH_CODE: &[u8] = &[
-    0xfc, 0x0f, 0x1d, 0xf8, 0xf4, 0x4f, 0x01, 0xa9, 0xfd, 0x7b, 0x02, 0xa9, 0xfd, 0x83, 0x00, 0x91, 0xff, 0x07, 0x40, 0xd1, 0xf4, 0x03, 0x01, 0xaa,
-    0xf3, 0x03, 0x00, 0xaa,
-];
-
-static ESHOPMANAGER_SHOW_SEARCH_CODE: &[u8] = &[
-    0x08, 0xe1, 0x43, 0xf9, 0x14, 0x05, 0x40, 0xf9, 0x88, 0x22, 0x44, 0x39, 0x08, 0x04, 0x00, 0x35,
-];
-
-static INFLATE_SEARCH_CODE: &[u8] = &[
-    0x4b, 0x00, 0x1b, 0x0b, 0x00, 0x01, 0x1f, 0xd6, 0x68, 0x6a, 0x40, 0xf9, 0x09, 0x3d, 0x40, 0xf9, 0x2c, 0x01, 0x40, 0xf9,
-];
-
-static MEMCPY_1_SEARCH_CODE: &[u8] = &[
-    0xf5, 0x1f, 0x40, 0xb9, 0xa7, 0x00, 0x00, 0x14, 0xe2, 0xa3, 0x00, 0x91, 0xe4, 0xc3, 0x00, 0x91,
-];
-
-static MEMCPY_2_SEARCH_CODE: &[u8] = &[
-    0xf8, 0x1b, 0x40, 0xf9, 0x1f, 0x03, 0x15, 0xeb, 0xa2, 0x2a, 0x00, 0x54, 0x96, 0x03, 0x18, 0x8b, 0x68, 0x1a, 0x40, 0xf9,
-];
-
-static MEMCPY_3_SEARCH_CODE: &[u8] = &[
-    0xe8, 0x03, 0x18, 0xaa, 0xf8, 0x1b, 0x40, 0xf9, 0xd6, 0x02, 0x18, 0x8b, 0xbf, 0x02, 0x18, 0xeb, 0x88, 0xfb, 0xff, 0x54,
-];
-
-static INFLATE_DIR_FILE_SEARCH_CODE: &[u8] = &[
-    0xfc, 0x6f, 0xba, 0xa9, 0xfa, 0x67, 0x01, 0xa9, 0xf8, 0x5f, 0x02, 0xa9, 0xf6, 0x57, 0x03, 0xa9, 0xf4, 0x4f, 0x04, 0xa9, 0xfd, 0x7b, 0x05, 0xa9,
-    0xfd, 0x43, 0x01, 0x91, 0xff, 0x03, 0x07, 0xd1, 0x4c, 0xb4, 0x40, 0xa9,
-];
-
-static MANUAL_OPEN_SEARCH_CODE: &[u8] = &[
-    0xfc, 0x4f, 0xbe, 0xa9, 0xfd, 0x7b, 0x01, 0xa9, 0xfd, 0x43, 0x00, 0x91, 0xff, 0x0f, 0x40, 0xd1,
-];
-
-static INITIAL_LOADING_SEARCH_CODE: &[u8] = &[
-    0x08, 0x3f, 0x40, 0xf9, 0x08, 0x01, 0x40, 0xf9, 0x08, 0x21, 0x40, 0xf9, 0x08, 0x3d, 0x40, 0xb9, 0x08, 0x5d, 0x00, 0x12,
-];
-
-static PROCESS_RESOURCE_NODE_SEARCH_CODE: &[u8] = &[
-    0x5f, 0x05, 0x00, 0x31, 0xea, 0x03, 0x8a, 0x1a, 0x29, 0x01, 0x0a, 0x0b, 0x29, 0x05, 0x00, 0x11, 0x6a, 0x3f, 0x40, 0xf9, 0x29, 0x21, 0xad, 0x9b,
-];
-
-static RES_LOAD_LOOP_START_SEARCH_CODE: &[u8] = &[
-    0x2a, 0x05, 0x09, 0x8b, 0x6e, 0x62, 0x01, 0x91, 0xdf, 0x01, 0x1b, 0xeb, 0x4d, 0xf1, 0x7d, 0xd3, 0xca, 0x01, 0x0d, 0x8b, 0x6d, 0x03, 0x0d, 0x8b,
-];
-
-static RES_LOAD_LOOP_REFRESH_SEARCH_CODE: &[u8] = &[
-    0x68, 0x32, 0x40, 0xf9, 0xee, 0x1b, 0x40, 0xf9, 0xdf, 0x01, 0x08, 0xeb, 0xec, 0x3f, 0x40, 0xf9, 0xed, 0x37, 0x40, 0xf9,
-];
-
-fn find_subsequence(haystack: &[u8], needle: &[u8]) -> Option<usize> {
-    haystack.windows(needle.len()).position(|window| window == needle)
-}
-
-#[allow(clippy::inconsistent_digit_grouping)]
-fn offset_from_adrp(adrp_offset: usize) -> usize {
-    unsafe {
-        let adrp = *(offset_to_addr(adrp_offset) as *const u32);
-        let immhi = (adrp & 0b0000_0000_1111_1111_1111_1111_1110_0000) >> 3;
-        let immlo = (adrp & 0b0110_0000_0000_0000_0000_0000_0000_0000) >> 29;
-        let imm = ((immhi | immlo) << 12) as i32 as usize;
-        let base = adrp_offset & 0xFFFF_FFFF_FFFF_F000;
-        base + imm
-    }
-}
-
-#[allow(clippy::inconsistent_digit_grouping)]
-fn offset_from_ldr(ldr_offset: usize) -> usize {
-    unsafe {
-        let ldr = *(offset_to_addr(ldr_offset) as *const u32);
-        let size = (ldr & 0b1100_0000_0000_0000_0000_0000_0000_0000) >> 30;
-        let imm = (ldr & 0b0000_0000_0011_1111_1111_1100_0000_0000) >> 10;
-        (imm as usize) << size
-    }
-}
-
 pub fn offset_to_addr(offset: usize) -> *const () {
     unsafe { (getRegionAddress(Region::Text) as *const u8).add(offset) as _ }
 }
@@ -127,125 +52,184 @@ fn get_text() -> &'static [u8] {
     }
 }
 
+/// Looks for the offset of a signature, reporting the patterns which matched code that is not what was expected
+fn search(signature: &Signature, text: &[u8]) -> Option<usize> {
+    for candidate in signature.candidates(text) {
+        if candidate.is_valid {
+            return Some(candidate.offset);
+        }
+
+        warn!(
+            "Pattern {} for '{}' matched at {:#x}, but the code there is not what was expected.",
+            candidate.pattern, signature.name, candidate.offset
+        );
+    }
+
+    error!("Unable to find the offset of '{}'.", signature.name);
+    None
+}
+
+/// The offsets of everything ARCropolis hooks or reads in the game. Missing offsets are `None`, which is only tolerated
+/// for the features that the game can run without.
 #[derive(Serialize, Deserialize)]
 struct Offsets {
-    pub lookup_stream_hash: usize,
-    pub inflate: usize,
-    pub memcpy_1: usize,
-    pub memcpy_2: usize,
-    pub memcpy_3: usize,
-    pub inflate_dir_file: usize,
-    pub manual_open: usize,
-    pub initial_loading: usize,
-    pub process_resource_node: usize,
-    pub res_load_loop_start: usize,
-    pub res_load_loop_refresh: usize,
-    pub title_screen_version: usize,
-    pub eshop_button: usize,
-
-    pub filesystem_info: usize,
-    pub res_service: usize,
+    pub lookup_stream_hash: Option<usize>,
+    pub inflate: Option<usize>,
+    pub memcpy_1: Option<usize>,
+    pub memcpy_2: Option<usize>,
+    pub memcpy_3: Option<usize>,
+    pub inflate_dir_file: Option<usize>,
+    pub manual_open: Option<usize>,
+    pub initial_loading: Option<usize>,
+    pub process_resource_node: Option<usize>,
+    pub res_load_loop_start: Option<usize>,
+    pub res_load_loop_refresh: Option<usize>,
+    pub title_screen_version: Option<usize>,
+    pub eshop_button: Option<usize>,
+
+    pub filesystem_adrp: Option<usize>,
+    pub res_service_adrp: Option<usize>,
+    pub filesystem_info: Option<usize>,
+    pub res_service: Option<usize>,
 }
 
 impl Offsets {
     pub fn new() -> Self {
         let text = get_text();
-        let lookup_stream_hash = find_subsequence(text, LOOKUP_STREAM_HASH_SEARCH_CODE).expect("Unable to find subsequence");
-        let inflate = find_subsequence(text, INFLATE_SEARCH_CODE).expect("Unable to find subsequence");
-        let memcpy_1 = find_subsequence(text, MEMCPY_1_SEARCH_CODE).expect("Unable to find subsequence") - 4;
-        let memcpy_2 = find_subsequence(text, MEMCPY_2_SEARCH_CODE).expect("Unable to find subsequence") - 4;
-        let memcpy_3 = find_subsequence(text, MEMCPY_3_SEARCH_CODE).expect("Unable to find subsequence") - 4;
-        let inflate_dir_file = find_subsequence(text, INFLATE_DIR_FILE_SEARCH_CODE).expect("Unable to find subsequence");
-        let manual_open = find_subsequence(text, MANUAL_OPEN_SEARCH_CODE).expect("Unable to find subsequence");
-        let initial_loading = find_subsequence(text, INITIAL_LOADING_SEARCH_CODE).expect("Unable to find subsequence");
-        let process_resource_node = find_subsequence(text, PROCESS_RESOURCE_NODE_SEARCH_CODE).expect("Unable to find subsequence") + 0xC;
-        let res_load_loop_start = find_subsequence(text, RES_LOAD_LOOP_START_SEARCH_CODE).expect("Unable to find subsequence");
-        let res_load_loop_refresh = find_subsequence(text, RES_LOAD_LOOP_REFRESH_SEARCH_CODE).expect("Unable to find subsequence");
-        let title_screen_version = find_subsequence(text, TITLE_SCREEN_VERSION_SEARCH_CODE).expect("Unable to find subsequence!");
-        let eshop_button = find_subsequence(text, ESHOPMANAGER_SHOW_SEARCH_CODE).expect("Unable to find subsequence!") - 16;
-
-        let filesystem_info = {
-            let adrp = find_subsequence(text, FILESYSTEM_INFO_ADRP_SEARCH_CODE).expect("Unable to find subsequence") + 12;
-            let adrp_offset = offset_from_adrp(adrp);
-            let ldr_offset = offset_from_ldr(adrp + 4);
-            adrp_offset + ldr_offset
-        };
-        let res_service = {
-            let adrp = find_subsequence(text, RES_SERVICE_ADRP_SEARCH_CODE).expect("Unable to find subsequence") + 16;
-            let adrp_offset = offset_from_adrp(adrp);
-            let ldr_offset = offset_from_ldr(adrp + 4);
-            adrp_offset + ldr_offset
-        };
+
+        let filesystem_adrp = search(&FILESYSTEM_INFO_ADRP, text);
+        let res_service_adrp = search(&RES_SERVICE_ADRP, text);
 
         Self {
-            lookup_stream_hash,
-            inflate,
-            memcpy_1,
-            memcpy_2,
-            memcpy_3,
-            inflate_dir_file,
-            manual_open,
-            initial_loading,
-            process_resource_node,
-            res_load_loop_start,
-            res_load_loop_refresh,
-            title_screen_version,
-            eshop_button,
-
-            filesystem_info,
-            res_service,
+            lookup_stream_hash: search(&LOOKUP_STREAM_HASH, text),
+            inflate: search(&INFLATE, text),
+            memcpy_1: search(&MEMCPY_1, text),
+            memcpy_2: search(&MEMCPY_2, text),
+            memcpy_3: search(&MEMCPY_3, text),
+            inflate_dir_file: search(&INFLATE_DIR_FILE, text),
+            manual_open: search(&MANUAL_OPEN, text),
+            initial_loading: search(&INITIAL_LOADING, text),
+            process_resource_node: search(&PROCESS_RESOURCE_NODE, text),
+            res_load_loop_start: search(&RES_LOAD_LOOP_START, text),
+            res_load_loop_refresh: search(&RES_LOAD_LOOP_REFRESH, text),
+            title_screen_version: search(&TITLE_SCREEN_VERSION, text),
+            eshop_button: search(&ESHOP_BUTTON, text),
+
+            filesystem_adrp,
+            res_service_adrp,
+            filesystem_info: filesystem_adrp.and_then(|adrp| global_from_adrp(text, adrp)),
+            res_service: res_service_adrp.and_then(|adrp| global_from_adrp(text, adrp)),
+        }
+    }
+
+    fn signatures(&self) -> [(&'static Signature, Option<usize>); 15] {
+        [
+            (&LOOKUP_STREAM_HASH, self.lookup_stream_hash),
+            (&INFLATE, self.inflate),
+            (&MEMCPY_1, self.memcpy_1),
+            (&MEMCPY_2, self.memcpy_2),
+            (&MEMCPY_3, self.memcpy_3),
+            (&INFLATE_DIR_FILE, self.inflate_dir_file),
+            (&MANUAL_OPEN, self.manual_open),
+            (&INITIAL_LOADING, self.initial_loading),
+            (&PROCESS_RESOURCE_NODE, self.process_resource_node),
+            (&RES_LOAD_LOOP_START, self.res_load_loop_start),
+            (&RES_LOAD_LOOP_REFRESH, self.res_load_loop_refresh),
+            (&TITLE_SCREEN_VERSION, self.title_screen_version),
+            (&ESHOP_BUTTON, self.eshop_button),
+            (&FILESYSTEM_INFO_ADRP, self.filesystem_adrp),
+            (&RES_SERVICE_ADRP, self.res_service_adrp),
+        ]
+    }
+
+    /// Whether every offset that was found still points to the code it was found for. Missing offsets of optional
+    /// signatures are trusted, since the game runs without them, but missing required ones are searched for again.
+    fn is_valid(&self) -> bool {
+        let text = get_text();
+
+        self.signatures()
+            .iter()
+            .all(|(signature, offset)| offset.map_or(signature.optional, |offset| signature.is_valid_at(text, offset)))
+            && self.filesystem_info.is_some()
+            && self.res_service.is_some()
+    }
+
+    /// Every offset is required, except for the ones of the hooks which only change the menus of the game
+    fn ensure_required(&self) {
+        let missing: Vec<&str> = self
+            .signatures()
+            .iter()
+            .filter(|(signature, offset)| offset.is_none() && !signature.optional)
+            .map(|(signature, _)| signature.name)
+            .collect();
+
+        if !missing.is_empty() {
+            panic!(
+                "Unable to find the offsets of {}. This version of the game is not supported.",
+                missing.join(", ")
+            );
         }
     }
 }
 
+/// Gets the optional features which were disabled because their offset could not be found
+pub fn missing_features() -> Vec<&'static str> {
+    OFFSETS
+        .signatures()
+        .iter()
+        .filter(|(signature, offset)| offset.is_none() && signature.optional)
+        .map(|(signature, _)| signature.name)
+        .collect()
+}
+
 pub fn initial_loading() -> usize {
-    OFFSETS.initial_loading
+    OFFSETS.initial_loading.unwrap()
 }
 
 pub fn filesystem_info() -> usize {
-    OFFSETS.filesystem_info
+    OFFSETS.filesystem_info.unwrap()
 }
 
 pub fn res_service() -> usize {
-    OFFSETS.res_service
+    OFFSETS.res_service.unwrap()
 }
 
 pub fn inflate() -> usize {
-    OFFSETS.inflate
+    OFFSETS.inflate.unwrap()
 }
 
 pub fn inflate_dir_file() -> usize {
-    OFFSETS.inflate_dir_file
+    OFFSETS.inflate_dir_file.unwrap()
 }
 
 pub fn memcpy_1() -> usize {
-    OFFSETS.memcpy_1
+    OFFSETS.memcpy_1.unwrap()
 }
 
 pub fn memcpy_2() -> usize {
-    OFFSETS.memcpy_2
+    OFFSETS.memcpy_2.unwrap()
 }
 
 pub fn memcpy_3() -> usize {
-    OFFSETS.memcpy_3
+    OFFSETS.memcpy_3.unwrap()
 }
 
 pub fn res_load_loop_start() -> usize {
-    OFFSETS.res_load_loop_start
+    OFFSETS.res_load_loop_start.unwrap()
 }
 
 pub fn res_load_loop_refresh() -> usize {
-    OFFSETS.res_load_loop_refresh
+    OFFSETS.res_load_loop_refresh.unwrap()
 }
 
-pub fn title_screen_version() -> usize {
+pub fn title_screen_version() -> Option<usize> {
     OFFSETS.title_screen_version
 }
 
-pub fn eshop_show() -> usize {
+pub fn eshop_show() -> Option<usize> {
     OFFSETS.eshop_button
 }
 
 pub fn lookup_stream_hash() -> usize {
-    OFFSETS.lookup_stream_hash
+    OFFSETS.lookup_stream_hash.unwrap()
 }
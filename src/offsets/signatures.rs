@@ -0,0 +1,497 @@
+//! The patterns ARCropolis looks for in the code of the game, and the logic to resolve them into offsets. This does not
+//! depend on the console, so that the signature tester can run it against a dump of the game on a host machine.
+
+pub static FILESYSTEM_INFO_ADRP_SEARCH_CODE: &[u8] = &[0xf3, 0x03, 0x00, 0xaa, 0x1f, 0x01, 0x09, 0x6b, 0xe0, 0x04, 0x00, 0x54];
+
+pub static RES_SERVICE_ADRP_SEARCH_CODE: &[u8] = &[
+    0x04, 0x01, 0x49, 0xfa, 0x21, 0x05, 0x00, 0x54, 0x5f, 0x00, 0x00, 0xf9, 0x7f, 0x00, 0x00, 0xf9,
+];
+
+pub static LOOKUP_STREAM_HASH_SEARCH_CODE: &[u8] = &[
+    0x29, 0x58, 0x40, 0xf9, 0x28, 0x60, 0x40, 0xf9, 0x2a, 0x05, 0x40, 0xb9, 0x09, 0x0d, 0x0a, 0x8b, 0xaa, 0x01, 0x00, 0x34, 0x5f, 0x01, 0x00, 0xf1,
+];
+
+pub static TITLE_SCREEN_VERSION_SEARCH_CODE: &[u8] = &[
+    0xfc, 0x0f, 0x1d, 0xf8, 0xf4, 0x4f, 0x01, 0xa9, 0xfd, 0x7b, 0x02, 0xa9, 0xfd, 0x83, 0x00, 0x91, 0xff, 0x07, 0x40, 0xd1, 0xf4, 0x03, 0x01, 0xaa,
+    0xf3, 0x03, 0x00, 0xaa,
+];
+
+pub static ESHOPMANAGER_SHOW_SEARCH_CODE: &[u8] = &[
+    0x08, 0xe1, 0x43, 0xf9, 0x14, 0x05, 0x40, 0xf9, 0x88, 0x22, 0x44, 0x39, 0x08, 0x04, 0x00, 0x35,
+];
+
+/// The offset loaded by the first instruction (`ldr x8, [x8, #0x7c0]`) depends on the layout of the eShop manager,
+/// so it is left out when the exact pattern cannot be found
+pub static ESHOPMANAGER_SHOW_SEARCH_MASK: &[u8] = &[
+    0xff, 0x03, 0xc0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+];
+
+pub static INFLATE_SEARCH_CODE: &[u8] = &[
+    0x4b, 0x00, 0x1b, 0x0b, 0x00, 0x01, 0x1f, 0xd6, 0x68, 0x6a, 0x40, 0xf9, 0x09, 0x3d, 0x40, 0xf9, 0x2c, 0x01, 0x40, 0xf9,
+];
+
+pub static MEMCPY_1_SEARCH_CODE: &[u8] = &[
+    0xf5, 0x1f, 0x40, 0xb9, 0xa7, 0x00, 0x00, 0x14, 0xe2, 0xa3, 0x00, 0x91, 0xe4, 0xc3, 0x00, 0x91,
+];
+
+pub static MEMCPY_2_SEARCH_CODE: &[u8] = &[
+    0xf8, 0x1b, 0x40, 0xf9, 0x1f, 0x03, 0x15, 0xeb, 0xa2, 0x2a, 0x00, 0x54, 0x96, 0x03, 0x18, 0x8b, 0x68, 0x1a, 0x40, 0xf9,
+];
+
+pub static MEMCPY_3_SEARCH_CODE: &[u8] = &[
+    0xe8, 0x03, 0x18, 0xaa, 0xf8, 0x1b, 0x40, 0xf9, 0xd6, 0x02, 0x18, 0x8b, 0xbf, 0x02, 0x18, 0xeb, 0x88, 0xfb, 0xff, 0x54,
+];
+
+pub static INFLATE_DIR_FILE_SEARCH_CODE: &[u8] = &[
+    0xfc, 0x6f, 0xba, 0xa9, 0xfa, 0x67, 0x01, 0xa9, 0xf8, 0x5f, 0x02, 0xa9, 0xf6, 0x57, 0x03, 0xa9, 0xf4, 0x4f, 0x04, 0xa9, 0xfd, 0x7b, 0x05, 0xa9,
+    0xfd, 0x43, 0x01, 0x91, 0xff, 0x03, 0x07, 0xd1, 0x4c, 0xb4, 0x40, 0xa9,
+];
+
+pub static MANUAL_OPEN_SEARCH_CODE: &[u8] = &[
+    0xfc, 0x4f, 0xbe, 0xa9, 0xfd, 0x7b, 0x01, 0xa9, 0xfd, 0x43, 0x00, 0x91, 0xff, 0x0f, 0x40, 0xd1,
+];
+
+pub static INITIAL_LOADING_SEARCH_CODE: &[u8] = &[
+    0x08, 0x3f, 0x40, 0xf9, 0x08, 0x01, 0x40, 0xf9, 0x08, 0x21, 0x40, 0xf9, 0x08, 0x3d, 0x40, 0xb9, 0x08, 0x5d, 0x00, 0x12,
+];
+
+pub static PROCESS_RESOURCE_NODE_SEARCH_CODE: &[u8] = &[
+    0x5f, 0x05, 0x00, 0x31, 0xea, 0x03, 0x8a, 0x1a, 0x29, 0x01, 0x0a, 0x0b, 0x29, 0x05, 0x00, 0x11, 0x6a, 0x3f, 0x40, 0xf9, 0x29, 0x21, 0xad, 0x9b,
+];
+
+pub static RES_LOAD_LOOP_START_SEARCH_CODE: &[u8] = &[
+    0x2a, 0x05, 0x09, 0x8b, 0x6e, 0x62, 0x01, 0x91, 0xdf, 0x01, 0x1b, 0xeb, 0x4d, 0xf1, 0x7d, 0xd3, 0xca, 0x01, 0x0d, 0x8b, 0x6d, 0x03, 0x0d, 0x8b,
+];
+
+pub static RES_LOAD_LOOP_REFRESH_SEARCH_CODE: &[u8] = &[
+    0x68, 0x32, 0x40, 0xf9, 0xee, 0x1b, 0x40, 0xf9, 0xdf, 0x01, 0x08, 0xeb, 0xec, 0x3f, 0x40, 0xf9, 0xed, 0x37, 0x40, 0xf9,
+];
+
+/// A byte pattern to look for in the code. When there is a mask, only the bits set in it are compared, so that operands
+/// which move between game versions (such as registers or struct offsets) can be left out of the pattern.
+pub struct Pattern {
+    pub code: &'static [u8],
+    pub mask: Option<&'static [u8]>,
+}
+
+impl Pattern {
+    pub const fn exact(code: &'static [u8]) -> Self {
+        Self { code, mask: None }
+    }
+
+    pub const fn masked(code: &'static [u8], mask: &'static [u8]) -> Self {
+        Self { code, mask: Some(mask) }
+    }
+
+    pub fn matches(&self, window: &[u8]) -> bool {
+        match self.mask {
+            Some(mask) => window
+                .iter()
+                .zip(self.code.iter())
+                .zip(mask.iter())
+                .all(|((a, b), mask)| a & mask == b & mask),
+            None => window == self.code,
+        }
+    }
+
+    /// Gets the position of the first match in the code
+    pub fn find(&self, text: &[u8]) -> Option<usize> {
+        text.windows(self.code.len()).position(|window| self.matches(window))
+    }
+
+    /// Gets the position of every match in the code, since a pattern that matches more than once is ambiguous
+    #[cfg_attr(target_os = "switch", allow(dead_code))]
+    pub fn find_all(&self, text: &[u8]) -> Vec<usize> {
+        text.windows(self.code.len())
+            .enumerate()
+            .filter(|(_, window)| self.matches(window))
+            .map(|(position, _)| position)
+            .collect()
+    }
+}
+
+/// An offset that a pattern of a signature resolved to
+pub struct Candidate {
+    /// The index of the pattern in the signature
+    #[cfg_attr(not(target_os = "switch"), allow(dead_code))]
+    pub pattern: usize,
+    pub offset: usize,
+    /// Whether the instruction at the offset passed the check of the signature
+    pub is_valid: bool,
+}
+
+/// How to find an offset: the patterns to try in order, the distance between a match and the offset, and a check of the
+/// instruction found at the offset
+pub struct Signature {
+    pub name: &'static str,
+    pub patterns: &'static [Pattern],
+    pub adjust: isize,
+    pub check: fn(&[u8], usize) -> bool,
+    /// Whether the game can run without the feature using this offset
+    pub optional: bool,
+}
+
+impl Signature {
+    /// Gets the offset the first match of every pattern resolves to, in the order the patterns are tried
+    pub fn candidates(&self, text: &[u8]) -> Vec<Candidate> {
+        self.patterns
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, pattern)| {
+                let offset = self.apply(pattern.find(text)?)?;
+                Some(Candidate {
+                    pattern: idx,
+                    offset,
+                    is_valid: (self.check)(text, offset),
+                })
+            })
+            .collect()
+    }
+
+    /// Looks for the offset with every pattern, skipping the matches which fail the check
+    #[cfg_attr(target_os = "switch", allow(dead_code))]
+    pub fn resolve(&self, text: &[u8]) -> Option<usize> {
+        self.candidates(text)
+            .into_iter()
+            .find(|candidate| candidate.is_valid)
+            .map(|candidate| candidate.offset)
+    }
+
+    /// Whether the code at an offset found on a previous boot is still the one that was searched for
+    #[cfg_attr(not(target_os = "switch"), allow(dead_code))]
+    pub fn is_valid_at(&self, text: &[u8], offset: usize) -> bool {
+        let position = match self.unapply(offset) {
+            Some(position) => position,
+            None => return false,
+        };
+
+        (self.check)(text, offset)
+            && self.patterns.iter().any(|pattern| {
+                text.get(position..position + pattern.code.len())
+                    .map_or(false, |window| pattern.matches(window))
+            })
+    }
+
+    pub fn apply(&self, position: usize) -> Option<usize> {
+        if self.adjust >= 0 {
+            position.checked_add(self.adjust as usize)
+        } else {
+            position.checked_sub((-self.adjust) as usize)
+        }
+    }
+
+    #[cfg_attr(not(target_os = "switch"), allow(dead_code))]
+    fn unapply(&self, offset: usize) -> Option<usize> {
+        if self.adjust >= 0 {
+            offset.checked_sub(self.adjust as usize)
+        } else {
+            offset.checked_add((-self.adjust) as usize)
+        }
+    }
+}
+
+fn instruction_at(text: &[u8], offset: usize) -> Option<u32> {
+    let bytes = text.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Any offset inside of the code, aligned to an instruction
+fn is_instruction(text: &[u8], offset: usize) -> bool {
+    offset % 4 == 0 && instruction_at(text, offset).is_some()
+}
+
+/// The memcpy calls are replaced with a NOP, so they have to be calls
+fn is_bl(text: &[u8], offset: usize) -> bool {
+    is_instruction(text, offset) && instruction_at(text, offset).map_or(false, |insn| insn & 0xFC00_0000 == 0x9400_0000)
+}
+
+/// The globals are read through an ADRP followed by an LDR with an immediate offset
+fn is_adrp_ldr(text: &[u8], offset: usize) -> bool {
+    let adrp = instruction_at(text, offset).map_or(false, |insn| insn & 0x9F00_0000 == 0x9000_0000);
+    let ldr = instruction_at(text, offset + 4).map_or(false, |insn| insn & 0x3B40_0000 == 0x3940_0000);
+    offset % 4 == 0 && adrp && ldr
+}
+
+#[allow(clippy::inconsistent_digit_grouping)]
+fn offset_from_adrp(text: &[u8], adrp_offset: usize) -> Option<usize> {
+    let adrp = instruction_at(text, adrp_offset)?;
+    let immhi = (adrp & 0b0000_0000_1111_1111_1111_1111_1110_0000) >> 3;
+    let immlo = (adrp & 0b0110_0000_0000_0000_0000_0000_0000_0000) >> 29;
+    let imm = ((immhi | immlo) << 12) as i32 as usize;
+    let base = adrp_offset & 0xFFFF_FFFF_FFFF_F000;
+    Some(base.wrapping_add(imm))
+}
+
+#[allow(clippy::inconsistent_digit_grouping)]
+fn offset_from_ldr(text: &[u8], ldr_offset: usize) -> Option<usize> {
+    let ldr = instruction_at(text, ldr_offset)?;
+    let size = (ldr & 0b1100_0000_0000_0000_0000_0000_0000_0000) >> 30;
+    let imm = (ldr & 0b0000_0000_0011_1111_1111_1100_0000_0000) >> 10;
+    Some((imm as usize) << size)
+}
+
+/// Gets the offset of the global read by an ADRP followed by an LDR, relative to the start of the code
+pub fn global_from_adrp(text: &[u8], adrp_offset: usize) -> Option<usize> {
+    Some(offset_from_adrp(text, adrp_offset)? + offset_from_ldr(text, adrp_offset + 4)?)
+}
+
+pub static LOOKUP_STREAM_HASH: Signature = Signature {
+    name: "lookup_stream_hash",
+    patterns: &[Pattern::exact(LOOKUP_STREAM_HASH_SEARCH_CODE)],
+    adjust: 0,
+    check: is_instruction,
+    optional: false,
+};
+
+pub static INFLATE: Signature = Signature {
+    name: "inflate",
+    patterns: &[Pattern::exact(INFLATE_SEARCH_CODE)],
+    adjust: 0,
+    check: is_instruction,
+    optional: false,
+};
+
+pub static MEMCPY_1: Signature = Signature {
+    name: "memcpy_1",
+    patterns: &[Pattern::exact(MEMCPY_1_SEARCH_CODE)],
+    adjust: -4,
+    check: is_bl,
+    optional: false,
+};
+
+pub static MEMCPY_2: Signature = Signature {
+    name: "memcpy_2",
+    patterns: &[Pattern::exact(MEMCPY_2_SEARCH_CODE)],
+    adjust: -4,
+    check: is_bl,
+    optional: false,
+};
+
+pub static MEMCPY_3: Signature = Signature {
+    name: "memcpy_3",
+    patterns: &[Pattern::exact(MEMCPY_3_SEARCH_CODE)],
+    adjust: -4,
+    check: is_bl,
+    optional: false,
+};
+
+pub static INFLATE_DIR_FILE: Signature = Signature {
+    name: "inflate_dir_file",
+    patterns: &[Pattern::exact(INFLATE_DIR_FILE_SEARCH_CODE)],
+    adjust: 0,
+    check: is_instruction,
+    optional: false,
+};
+
+pub static MANUAL_OPEN: Signature = Signature {
+    name: "manual_open",
+    patterns: &[Pattern::exact(MANUAL_OPEN_SEARCH_CODE)],
+    adjust: 0,
+    check: is_instruction,
+    optional: false,
+};
+
+pub static INITIAL_LOADING: Signature = Signature {
+    name: "initial_loading",
+    patterns: &[Pattern::exact(INITIAL_LOADING_SEARCH_CODE)],
+    adjust: 0,
+    check: is_instruction,
+    optional: false,
+};
+
+pub static PROCESS_RESOURCE_NODE: Signature = Signature {
+    name: "process_resource_node",
+    patterns: &[Pattern::exact(PROCESS_RESOURCE_NODE_SEARCH_CODE)],
+    adjust: 0xC,
+    check: is_instruction,
+    optional: false,
+};
+
+pub static RES_LOAD_LOOP_START: Signature = Signature {
+    name: "res_load_loop_start",
+    patterns: &[Pattern::exact(RES_LOAD_LOOP_START_SEARCH_CODE)],
+    adjust: 0,
+    check: is_instruction,
+    optional: false,
+};
+
+pub static RES_LOAD_LOOP_REFRESH: Signature = Signature {
+    name: "res_load_loop_refresh",
+    patterns: &[Pattern::exact(RES_LOAD_LOOP_REFRESH_SEARCH_CODE)],
+    adjust: 0,
+    check: is_instruction,
+    optional: false,
+};
+
+pub static TITLE_SCREEN_VERSION: Signature = Signature {
+    name: "title_screen_version",
+    patterns: &[Pattern::exact(TITLE_SCREEN_VERSION_SEARCH_CODE)],
+    adjust: 0,
+    check: is_instruction,
+    optional: true,
+};
+
+pub static ESHOP_BUTTON: Signature = Signature {
+    name: "eshop_button",
+    patterns: &[
+        Pattern::exact(ESHOPMANAGER_SHOW_SEARCH_CODE),
+        Pattern::masked(ESHOPMANAGER_SHOW_SEARCH_CODE, ESHOPMANAGER_SHOW_SEARCH_MASK),
+    ],
+    adjust: -16,
+    check: is_instruction,
+    optional: true,
+};
+
+pub static FILESYSTEM_INFO_ADRP: Signature = Signature {
+    name: "filesystem_info",
+    patterns: &[Pattern::exact(FILESYSTEM_INFO_ADRP_SEARCH_CODE)],
+    adjust: 12,
+    check: is_adrp_ldr,
+    optional: false,
+};
+
+pub static RES_SERVICE_ADRP: Signature = Signature {
+    name: "res_service",
+    patterns: &[Pattern::exact(RES_SERVICE_ADRP_SEARCH_CODE)],
+    adjust: 16,
+    check: is_adrp_ldr,
+    optional: false,
+};
+
+/// Every signature, in the order they are reported
+#[cfg_attr(target_os = "switch", allow(dead_code))]
+pub static SIGNATURES: &[&Signature] = &[
+    &LOOKUP_STREAM_HASH,
+    &INFLATE,
+    &MEMCPY_1,
+    &MEMCPY_2,
+    &MEMCPY_3,
+    &INFLATE_DIR_FILE,
+    &MANUAL_OPEN,
+    &INITIAL_LOADING,
+    &PROCESS_RESOURCE_NODE,
+    &RES_LOAD_LOOP_START,
+    &RES_LOAD_LOOP_REFRESH,
+    &TITLE_SCREEN_VERSION,
+    &ESHOP_BUTTON,
+    &FILESYSTEM_INFO_ADRP,
+    &RES_SERVICE_ADRP,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lays out instructions the way they are stored in the code
+    fn code(instructions: &[u32]) -> Vec<u8> {
+        instructions.iter().flat_map(|insn| insn.to_le_bytes()).collect()
+    }
+
+    // ldr x8, [x8, #0x7c0]
+    const LDR_X8_X8_7C0: u32 = 0xF943_E108;
+    // ldr x8, [x8, #0x7c8]
+    const LDR_X8_X8_7C8: u32 = 0xF943_E508;
+    // ldr w9, [x8, #0x10]
+    const LDR_W9_X8_10: u32 = 0xB940_1109;
+    // adrp x8, #0x1000
+    const ADRP_X8_NEXT_PAGE: u32 = 0xB000_0008;
+    // adrp x8, #-0x1000
+    const ADRP_X8_PREVIOUS_PAGE: u32 = 0xF0FF_FFE8;
+    // bl #0x100
+    const BL: u32 = 0x9400_0040;
+    const NOP: u32 = 0xD503_201F;
+
+    #[test]
+    fn exact_patterns_compare_every_byte() {
+        let pattern = Pattern::exact(ESHOPMANAGER_SHOW_SEARCH_CODE);
+        let mut window = ESHOPMANAGER_SHOW_SEARCH_CODE.to_vec();
+
+        assert!(pattern.matches(&window));
+
+        window[..4].copy_from_slice(&LDR_X8_X8_7C8.to_le_bytes());
+        assert!(!pattern.matches(&window));
+    }
+
+    #[test]
+    fn masked_patterns_ignore_masked_out_bits() {
+        let pattern = Pattern::masked(ESHOPMANAGER_SHOW_SEARCH_CODE, ESHOPMANAGER_SHOW_SEARCH_MASK);
+        assert_eq!(&ESHOPMANAGER_SHOW_SEARCH_CODE[..4], &LDR_X8_X8_7C0.to_le_bytes());
+
+        // Another offset in the first instruction is left out by the mask
+        let mut window = ESHOPMANAGER_SHOW_SEARCH_CODE.to_vec();
+        window[..4].copy_from_slice(&LDR_X8_X8_7C8.to_le_bytes());
+        assert!(pattern.matches(&window));
+
+        // Another instruction is not
+        window[..4].copy_from_slice(&LDR_W9_X8_10.to_le_bytes());
+        assert!(!pattern.matches(&window));
+
+        // Neither is a change in the bytes the mask covers entirely
+        let mut window = ESHOPMANAGER_SHOW_SEARCH_CODE.to_vec();
+        window[15] ^= 1;
+        assert!(!pattern.matches(&window));
+    }
+
+    #[test]
+    fn finds_every_match() {
+        let text = code(&[NOP, BL, NOP, BL]);
+        let pattern = Pattern::exact(&[0x40, 0x00, 0x00, 0x94]);
+
+        assert_eq!(pattern.find(&text), Some(4));
+        assert_eq!(pattern.find_all(&text), vec![4, 12]);
+        assert_eq!(Pattern::exact(&[0x00, 0x00, 0x00, 0x00]).find(&text), None);
+    }
+
+    #[test]
+    fn reads_the_page_of_an_adrp() {
+        let mut text = code(&[NOP; 0x1800]);
+
+        text[0x2000..0x2004].copy_from_slice(&ADRP_X8_NEXT_PAGE.to_le_bytes());
+        assert_eq!(offset_from_adrp(&text, 0x2000), Some(0x3000));
+
+        // The page is relative to the page of the instruction, not to the instruction itself
+        text[0x2ffc..0x3000].copy_from_slice(&ADRP_X8_NEXT_PAGE.to_le_bytes());
+        assert_eq!(offset_from_adrp(&text, 0x2ffc), Some(0x3000));
+
+        text[0x5004..0x5008].copy_from_slice(&ADRP_X8_PREVIOUS_PAGE.to_le_bytes());
+        assert_eq!(offset_from_adrp(&text, 0x5004), Some(0x4000));
+
+        assert_eq!(offset_from_adrp(&text, text.len()), None);
+    }
+
+    #[test]
+    fn scales_the_offset_of_an_ldr_by_its_size() {
+        let text = code(&[LDR_X8_X8_7C0, LDR_W9_X8_10]);
+
+        assert_eq!(offset_from_ldr(&text, 0), Some(0x7c0));
+        assert_eq!(offset_from_ldr(&text, 4), Some(0x10));
+        assert_eq!(offset_from_ldr(&text, 8), None);
+    }
+
+    #[test]
+    fn resolves_globals_read_through_an_adrp() {
+        let mut text = code(&[NOP; 0x1000]);
+        text[0x2000..0x2008].copy_from_slice(&code(&[ADRP_X8_NEXT_PAGE, LDR_X8_X8_7C0]));
+
+        assert!(is_adrp_ldr(&text, 0x2000));
+        assert!(!is_adrp_ldr(&text, 0x2004));
+        assert_eq!(global_from_adrp(&text, 0x2000), Some(0x37c0));
+    }
+
+    #[test]
+    fn skips_matches_failing_the_check() {
+        static PATTERNS: &[Pattern] = &[Pattern::exact(&[0x1F, 0x20, 0x03, 0xD5, 0x1F, 0x20, 0x03, 0xD5])];
+        let signature = Signature {
+            name: "test",
+            patterns: PATTERNS,
+            adjust: 8,
+            check: is_bl,
+            optional: false,
+        };
+
+        assert_eq!(signature.resolve(&code(&[NOP, NOP, NOP])), None);
+        assert_eq!(signature.resolve(&code(&[NOP, NOP, BL])), Some(8));
+        assert!(signature.is_valid_at(&code(&[NOP, NOP, BL]), 8));
+        assert!(!signature.is_valid_at(&code(&[NOP, NOP, NOP]), 8));
+    }
+}
@@ -6,12 +6,28 @@ use std::{
 use serde::Deserialize;
 use smash_arc::serde::Hash40String;
 
+static KNOWN_KEYS: &[&str] = &[
+    "unshare-blacklist",
+    "unshare_blacklist",
+    "new-files",
+    "new_files",
+    "preprocess-reshare",
+    "preprocess_reshare",
+    "preprocess-reshare-ext",
+    "preprocess_reshare_ext",
+    "new-shared-files",
+    "new_shared_files",
+    "new-dir-files",
+    "new_dir_files",
+];
+
 #[derive(Debug, Default, Deserialize)]
 pub struct ModConfig {
     #[serde(alias = "unshare-blacklist")]
     #[serde(default = "HashSet::new")]
     pub unshare_blacklist: HashSet<Hash40String>,
 
+    /// New file -> directories that should depend on it
     #[serde(alias = "new-files")]
     #[serde(default = "HashMap::new")]
     pub new_files: HashMap<Hash40String, Option<HashSet<Hash40String>>>,
@@ -20,6 +36,7 @@ pub struct ModConfig {
     #[serde(default = "HashMap::new")]
     pub preprocess_reshare: HashMap<Hash40String, Hash40String>,
 
+    /// Dependent file extension -> source file extension, applied to every `preprocess-reshare` directory pair
     #[serde(alias = "preprocess-reshare-ext")]
     #[serde(default = "HashMap::new")]
     pub preprocess_reshare_ext: HashMap<Hash40String, Hash40String>,
@@ -34,6 +51,14 @@ pub struct ModConfig {
 }
 
 impl ModConfig {
+    /// Gets the keys of a raw config.json that do not map to any field of the config, so that they can be reported
+    pub fn unknown_keys(value: &serde_json::Value) -> Vec<&str> {
+        match value.as_object() {
+            Some(object) => object.keys().map(String::as_str).filter(|key| !KNOWN_KEYS.contains(key)).collect(),
+            None => Vec::new(),
+        }
+    }
+
     pub fn merge(&mut self, other: ModConfig) {
         let Self {
             unshare_blacklist,
@@ -49,12 +74,13 @@ impl ModConfig {
         self.preprocess_reshare_ext.extend(preprocess_reshare_ext.into_iter());
 
         for (hash, list) in new_files.into_iter() {
-            if let Some(list) = list {
-                if let Some(Some(current_list)) = self.new_files.get_mut(&hash) {
-                    current_list.extend(list.into_iter());
-                } else {
-                    let _ = self.new_files.insert(hash, Some(list));
-                }
+            match (self.new_files.get_mut(&hash), list) {
+                (Some(Some(current_list)), Some(list)) => current_list.extend(list.into_iter()),
+                (Some(current_list), Some(list)) => *current_list = Some(list),
+                (Some(_), None) => {},
+                (None, list) => {
+                    let _ = self.new_files.insert(hash, list);
+                },
             }
         }
 
@@ -12,6 +12,7 @@ use smash_arc::{
 };
 
 use crate::{
+    fs::pipeline,
     get_smash_hash, hashes,
     resource::{self, CppVector, FilesystemInfo, LoadedData, LoadedFilepath},
     PathExtension,
@@ -158,13 +159,7 @@ impl LoadedArcEx for LoadedArc {
     }
 
     fn get_shared_file(&self, hash: Hash40) -> Result<FilePathIdx, LookupError> {
-        let file_info = self.get_file_info_from_hash(hash)?;
-        let new_hash = self.get_file_paths()[file_info.file_path_index].path.hash40();
-        if new_hash != hash {
-            self.get_shared_file(new_hash)
-        } else {
-            Ok(file_info.file_path_index)
-        }
+        pipeline::shared_file_index(self, hash)
     }
 
     fn make_addition_context() -> AdditionContext {
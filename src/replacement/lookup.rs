@@ -8,8 +8,7 @@ use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
 use smash_arc::{ArcLookup, Hash40, LoadedArc};
 
-use super::LoadedArcEx;
-use crate::hashes;
+use crate::{fs::pipeline, hashes, resource};
 
 // FilePath -> (DirInfo, child_index)
 #[derive(Deserialize, Serialize)]
@@ -91,6 +90,17 @@ static SHARE_LOOKUP: Lazy<RwLock<ShareLookupState>> = Lazy::new(|| {
     RwLock::new(lut)
 });
 
+// Extension -> FilePaths with it, for the extension callbacks. Built from the tables of the game the first time it is needed.
+static EXTENSION_LOOKUP: Lazy<HashMap<Hash40, Vec<Hash40>>> = Lazy::new(|| {
+    let mut lookup: HashMap<Hash40, Vec<Hash40>> = HashMap::new();
+
+    for file_path in resource::arc().get_file_paths().iter() {
+        lookup.entry(file_path.ext.hash40()).or_default().push(file_path.path.hash40());
+    }
+
+    lookup
+});
+
 pub fn initialize_unshare(arc: Option<&LoadedArc>) {
     if arc.is_none() {
         Lazy::force(&UNSHARE_LOOKUP);
@@ -100,15 +110,7 @@ pub fn initialize_unshare(arc: Option<&LoadedArc>) {
     let mut lookup_state = UNSHARE_LOOKUP.write();
     let lookup = match *lookup_state {
         UnshareLookupState::Missing => {
-            let mut lookup = UnshareLookup(HashMap::new());
-
-            let file_paths = arc.get_file_paths();
-
-            for dir_info in arc.get_dir_infos() {
-                for (child_index, file_info) in arc.get_file_infos()[dir_info.file_info_range()].iter().enumerate() {
-                    lookup.insert(file_paths[file_info.file_path_index].path.hash40(), (dir_info.path.hash40(), child_index));
-                }
-            }
+            let lookup = UnshareLookup(pipeline::file_directories(arc));
 
             match bincode::serialize(&lookup) {
                 Ok(data) => {
@@ -139,41 +141,18 @@ pub fn initialize_share(arc: Option<&LoadedArc>) {
     let mut lookup_state = SHARE_LOOKUP.write();
     let lookup = match *lookup_state {
         ShareLookupState::Missing => {
-            let mut path_shared: HashMap<Hash40, Vec<Hash40>> = HashMap::new();
+            let (path_shared, failed) = pipeline::shared_file_groups(arc);
 
-            let mut shared_files = HashSet::new();
-
-            let filepaths = arc.get_file_paths();
-
-            for (current_index, file_path) in filepaths.iter().enumerate() {
-                let hash = file_path.path.hash40();
-
-                let shared_file_index = match arc.get_shared_file(hash) {
-                    Ok(idx) => {
-                        if usize::from(idx) == current_index {
-                            continue;
-                        }
-                        idx
-                    },
-                    Err(_) => {
-                        error!(
-                            "Failed to get shared file for '{}' ({:#x}) while generating share.lut",
-                            hashes::find(hash),
-                            hash.0
-                        );
-                        continue;
-                    },
-                };
-
-                let shared_hash = filepaths[shared_file_index].path.hash40();
-
-                if let Some(shared_file_hashes) = path_shared.get_mut(&shared_hash) {
-                    shared_file_hashes.push(hash);
-                } else {
-                    let _ = path_shared.insert(shared_hash, vec![hash]);
-                }
+            for hash in failed {
+                error!(
+                    "Failed to get shared file for '{}' ({:#x}) while generating share.lut",
+                    hashes::find(hash),
+                    hash.0
+                );
             }
 
+            let mut shared_files = HashSet::new();
+
             for (src, shared) in path_shared.iter() {
                 shared_files.insert(*src);
                 for share in shared {
@@ -218,6 +197,11 @@ pub fn get_dir_entry_for_file<H: Into<Hash40>>(hash: H) -> Option<(Hash40, usize
     }
 }
 
+/// Gets every file of the game with the extension, such as `nutexb`
+pub fn files_with_extension(ext: Hash40) -> &'static [Hash40] {
+    EXTENSION_LOOKUP.get(&ext).map_or(&[], Vec::as_slice)
+}
+
 pub fn is_shared_file<H: Into<Hash40>>(hash: H) -> bool {
     let lut = SHARE_LOOKUP.read();
     match &*lut {
@@ -6,6 +6,33 @@ use super::{AdditionContext, LoadedArcEx};
 use crate::hashes;
 
 pub fn reshare_contained_files(ctx: &mut AdditionContext, dependent: Hash40, source: Hash40) -> HashSet<Hash40> {
+    reshare_contained_files_filtered(ctx, dependent, source, |_| true, |_| true)
+}
+
+/// Reshares the files with the extension `dependent_ext` in the dependent directory to the files with the extension `source_ext`
+/// in the source directory. This is run after [`reshare_contained_files`] so that, when the source directory has several files
+/// with the same shared data, the dependent file is pointed to the one with the expected extension.
+pub fn reshare_contained_files_by_ext(
+    ctx: &mut AdditionContext,
+    dependent: Hash40,
+    source: Hash40,
+    dependent_ext: Hash40,
+    source_ext: Hash40,
+) -> HashSet<Hash40> {
+    reshare_contained_files_filtered(ctx, dependent, source, |ext| ext == dependent_ext, |ext| ext == source_ext)
+}
+
+fn reshare_contained_files_filtered<D, S>(
+    ctx: &mut AdditionContext,
+    dependent: Hash40,
+    source: Hash40,
+    dependent_filter: D,
+    source_filter: S,
+) -> HashSet<Hash40>
+where
+    D: Fn(Hash40) -> bool,
+    S: Fn(Hash40) -> bool,
+{
     let source_range = match ctx.get_dir_info_from_hash(source) {
         Ok(dir_info) => dir_info.file_info_range(),
         Err(_) => {
@@ -32,6 +59,7 @@ pub fn reshare_contained_files(ctx: &mut AdditionContext, dependent: Hash40, sou
 
     let filepath_to_index: HashMap<FilePathIdx, FilePathIdx> = ctx.file_infos[source_range]
         .iter()
+        .filter(|x| source_filter(ctx.filepaths[usize::from(x.file_path_index)].ext.hash40()))
         .filter_map(|x| {
             let hash = ctx.filepaths[usize::from(x.file_path_index)].path.hash40();
             match ctx.get_shared_file(hash) {
@@ -51,6 +79,10 @@ pub fn reshare_contained_files(ctx: &mut AdditionContext, dependent: Hash40, sou
     dependent_range
         .into_iter()
         .filter_map(|dep_idx| {
+            if !dependent_filter(ctx.filepaths[usize::from(ctx.file_infos[dep_idx].file_path_index)].ext.hash40()) {
+                return None;
+            }
+
             let shared_file_idx = ctx.get_shared_info_index(FileInfoIdx(dep_idx as u32));
             if let Some(new_path_idx) = filepath_to_index.get(&ctx.file_infos[usize::from(shared_file_idx)].file_path_index) {
                 let hash = ctx.filepaths[usize::from(ctx.file_infos[dep_idx].file_path_index)].path.hash40();
@@ -4,7 +4,7 @@ use smash_arc::{ArcLookup, Hash40};
 
 use super::FileInfoFlagsExt;
 use crate::{
-    config, hashes, offsets, reg_w, reg_x,
+    api, config, hashes, offsets, reg_w, reg_x,
     resource::{self, InflateFile, LoadInfo, LoadType},
     GLOBAL_FILESYSTEM,
 };
@@ -74,7 +74,7 @@ pub fn handle_file_replace(hash: Hash40) {
     let file_info = match arc.get_file_info_from_hash(hash) {
         Ok(info) => info,
         Err(_) => {
-            error!("Failed to find file info for '{}' ({:#x}) when replacing.", hashes::find(hash), hash.0);
+            error!(hash = hash.0; "Failed to find file info for '{}' ({:#x}) when replacing.", hashes::find(hash), hash.0);
             return;
         },
     };
@@ -86,6 +86,7 @@ pub fn handle_file_replace(hash: Hash40) {
 
     if filesystem_info.get_loaded_filepaths()[filepath_index].is_loaded == 0 {
         warn!(
+            hash = hash.0;
             "When replacing file '{}' ({:#x}), the file is not marked as loaded. FilepathIdx: {:#x}, LoadedDataIdx: {:#x}",
             hashes::find(hash),
             hash.0,
@@ -96,6 +97,7 @@ pub fn handle_file_replace(hash: Hash40) {
 
     if filesystem_info.get_loaded_datas()[file_info_indice_index].data.is_null() {
         warn!(
+            hash = hash.0;
             "When replacing file '{}' ({:#x}), the loaded data buffer is empty. FilepathIdx: {:#x}, LoadedDataIdx: {:#x}",
             hashes::find(hash),
             hash.0,
@@ -105,8 +107,6 @@ pub fn handle_file_replace(hash: Hash40) {
         return;
     }
 
-    let mut fs = crate::GLOBAL_FILESYSTEM.write();
-
     let buffer = unsafe {
         std::slice::from_raw_parts_mut(
             filesystem_info.get_loaded_datas()[file_info_indice_index].data as *mut u8,
@@ -114,6 +114,11 @@ pub fn handle_file_replace(hash: Hash40) {
         )
     };
 
+    // Sent before acquiring the filesystem so that plugins can still use the API from their callback
+    api::event::send_file_replacing(hash, buffer);
+
+    let mut fs = crate::GLOBAL_FILESYSTEM.write();
+
     if let Some(size) = fs.load_into(hash, buffer) {
         if arc.get_file_paths()[filepath_index].ext.hash40() == Hash40::from("nutexb") {
             if size < decompressed_size as usize {
@@ -137,6 +142,9 @@ pub fn handle_file_replace(hash: Hash40) {
             size,
             resource::res_service().buffer_size
         );
+
+        drop(fs);
+        api::event::send_file_replaced(hash, buffer, size);
     } else {
         warn!(
             "Failed to load file '{}' ({:#x}) into buffer with size {:#X}",
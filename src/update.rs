@@ -1,30 +1,76 @@
+#[cfg(feature = "updater")]
 use std::fmt;
 
+#[cfg(feature = "updater")]
 use gh_updater::ReleaseFinderConfig;
+#[cfg(feature = "updater")]
 use semver::Version;
-use zip::ZipArchive;
 
+pub mod install;
+pub mod rollback;
+
+#[cfg(feature = "updater")]
 pub enum VersionDifference {
     ChangeToStable(String),
     ChangeToBeta(String),
     Regular(String),
+    ChangeToPinned(String),
 }
 
+#[cfg(feature = "updater")]
 impl fmt::Display for VersionDifference {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::ChangeToStable(ver) => write!(f, "An uninstalled stable version of ARCropolis ({})", ver),
             Self::ChangeToBeta(ver) => write!(f, "A new beta version of ARCropolis ({})", ver),
             Self::Regular(ver) => write!(f, "A new update for ARCropolis ({})", ver),
+            Self::ChangeToPinned(ver) => write!(f, "The pinned version of ARCropolis ({})", ver),
+        }
+    }
+}
+
+/// The restrictions set in the configuration on which versions the updater can install
+#[cfg(feature = "updater")]
+pub struct UpdatePolicy {
+    pub pinned: Option<Version>,
+    pub ignored: Vec<Version>,
+    pub never_downgrade: bool,
+}
+
+#[cfg(feature = "updater")]
+impl UpdatePolicy {
+    pub fn from_config() -> Self {
+        let parse = |version: &str| match Version::parse(version.trim_start_matches('v')) {
+            Ok(version) => Some(version),
+            Err(e) => {
+                warn!("Ignoring invalid version '{}' in the update configuration. Reason: {:?}", version, e);
+                None
+            },
+        };
+
+        Self {
+            pinned: crate::config::pinned_version().and_then(|version| parse(&version)),
+            ignored: crate::config::ignored_versions().iter().filter_map(|version| parse(version)).collect(),
+            never_downgrade: crate::config::never_downgrade(),
         }
     }
+
+    /// Whether a release can be considered at all, before comparing it to the current version
+    fn allows(&self, target: &Version) -> bool {
+        !self.ignored.contains(target) && self.pinned.as_ref().map_or(true, |pinned| pinned == target)
+    }
 }
 
-fn compare_tags(current: &str, target: &str) -> Result<Option<VersionDifference>, semver::Error> {
+#[cfg(feature = "updater")]
+fn compare_tags(current: &str, target: &str, policy: &UpdatePolicy) -> Result<Option<VersionDifference>, semver::Error> {
     let current = Version::parse(current)?;
     let target = Version::parse(target)?;
 
-    if current.pre.is_empty() && !target.pre.is_empty() {
+    if !policy.allows(&target) || target == current || (policy.never_downgrade && target < current) {
+        Ok(None)
+    } else if policy.pinned.is_some() {
+        Ok(Some(VersionDifference::ChangeToPinned(target.to_string())))
+    } else if current.pre.is_empty() && !target.pre.is_empty() {
         Ok(Some(VersionDifference::ChangeToBeta(target.to_string())))
     } else if !current.pre.is_empty() && target.pre.is_empty() && current < target {
         Ok(Some(VersionDifference::ChangeToStable(target.to_string())))
@@ -35,14 +81,21 @@ fn compare_tags(current: &str, target: &str) -> Result<Option<VersionDifference>
     }
 }
 
+/// Looks for a newer release on GitHub and installs it if the callback agrees to it
+#[cfg(feature = "updater")]
 pub fn check_for_updates<F>(beta_enabled: bool, f: F)
 where
     F: Fn(VersionDifference) -> bool,
 {
+    let policy = UpdatePolicy::from_config();
+
+    // A pinned prerelease can only be found when prereleases are looked for
+    let with_prereleases = beta_enabled || policy.pinned.as_ref().map_or(false, |pinned| !pinned.pre.is_empty());
+
     let release = ReleaseFinderConfig::new("ARCropolis")
         .with_author("Raytwo")
         .with_repository("ARCropolis")
-        .with_prereleases(beta_enabled)
+        .with_prereleases(with_prereleases)
         .find_release();
 
     let (release, prerelease) = match release {
@@ -53,29 +106,40 @@ where
         },
     };
 
-    let prerelease_tag = prerelease
-        .as_ref()
-        .map(|x| Version::parse(x.get_release_tag().trim_start_matches('v')).expect("Failed to parse version strings!"));
-    let release_tag = release
-        .as_ref()
-        .map(|x| Version::parse(x.get_release_tag().trim_start_matches('v')).expect("Failed to parse version strings!"));
+    if release.is_none() && prerelease.is_none() {
+        error!("No github releases were found!");
+        return;
+    }
 
-    let release = match (prerelease_tag, release_tag) {
-        (None, None) => {
-            error!("No github releases were found!");
-            return;
-        },
-        (prerelease_tag, release_tag) => {
-            if prerelease_tag > release_tag {
-                prerelease.unwrap()
-            } else {
-                // even if they are equal it won't matter
-                release.unwrap()
+    // Pick the newest release which is neither ignored nor different from the pinned version
+    let release = vec![prerelease, release]
+        .into_iter()
+        .flatten()
+        .filter_map(|release| match Version::parse(release.get_release_tag().trim_start_matches('v')) {
+            Ok(tag) => policy.allows(&tag).then(|| (tag, release)),
+            Err(e) => {
+                warn!("Skipping the release '{}', its tag is not a version. Reason: {:?}", release.get_release_tag(), e);
+                None
+            },
+        })
+        .max_by(|(a, _), (b, _)| a.cmp(b));
+
+    let release = match release {
+        Some((_, release)) => release,
+        None => {
+            match policy.pinned.as_ref() {
+                Some(pinned) => warn!(
+                    "The pinned version ({}) is not the latest release, it can only be installed from {}.",
+                    pinned,
+                    install::LOCAL_RELEASE_PATH
+                ),
+                None => info!("Every available release is in the ignored versions."),
             }
+            return;
         },
     };
 
-    let version_difference = match compare_tags(env!("CARGO_PKG_VERSION"), release.get_release_tag().trim_start_matches('v')) {
+    let version_difference = match compare_tags(env!("CARGO_PKG_VERSION"), release.get_release_tag().trim_start_matches('v'), &policy) {
         Ok(diff) => diff,
         Err(e) => {
             error!("Failed to parse version strings: {:?}", e);
@@ -84,23 +148,27 @@ where
     };
 
     if let Some(update_kind) = version_difference {
+        let checksum = release
+            .get_asset_by_name("release.zip.sha256")
+            .and_then(|manifest| String::from_utf8(manifest).ok());
+
+        // Without a checksum the archive would be refused once downloaded, so the user is not asked about it
+        if checksum.is_none() && !crate::config::allow_unverified_updates() {
+            warn!(
+                "{} was found, but the release does not provide a checksum for its archive. Allow unverified updates in the configuration to install it.",
+                update_kind
+            );
+            return;
+        }
+
         if !f(update_kind) {
             return;
         }
-        if let Some(release) = release.get_asset_by_name("release.zip") {
-            let mut zip = match ZipArchive::new(std::io::Cursor::new(release)) {
-                Ok(zip) => zip,
-                Err(e) => {
-                    error!("Failed to parse zip data: {:?}", e);
-                    return;
-                },
-            };
-
-            if let Err(e) = zip.extract("sd:/") {
-                panic!("ARCropolis failed to extract update ZIP. Reason: {:?}", e);
+        if let Some(archive) = release.get_asset_by_name("release.zip") {
+            match install::install_update(&archive, checksum.as_deref()) {
+                Ok(()) => unsafe { skyline::nn::oe::RequestToRelaunchApplication() },
+                Err(e) => error!("Failed to install the update. Reason: {}", e),
             }
-
-            unsafe { skyline::nn::oe::RequestToRelaunchApplication() };
         }
     }
 }
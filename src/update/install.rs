@@ -0,0 +1,140 @@
+use std::{io, path::PathBuf};
+
+use skyline::nn;
+use thiserror::Error;
+
+use super::rollback::{self, PreviousBoot, UpdateLayout};
+
+/// Where a release archive can be dropped to be installed on the next boot, without going through GitHub
+pub static UPDATES_PATH: &str = "sd:/ultimate/arcropolis/updates";
+pub static LOCAL_RELEASE_PATH: &str = "sd:/ultimate/arcropolis/updates/release.zip";
+/// The SHA-256 of the local release archive, in the format of `sha256sum`
+pub static LOCAL_CHECKSUM_PATH: &str = "sd:/ultimate/arcropolis/updates/release.zip.sha256";
+
+#[derive(Error, Debug)]
+pub enum UpdateError {
+    #[error("the checksum manifest is missing or empty")]
+    MissingChecksum,
+    #[error("the archive does not match its checksum (expected {expected}, found {found})")]
+    ChecksumMismatch { expected: String, found: String },
+    #[error("IO error: {0}")]
+    IO(#[from] std::io::Error),
+}
+
+/// Hashes data with SHA-256, formatted like `sha256sum` does
+pub fn sha256(data: &[u8]) -> String {
+    let mut hash = [0u8; 0x20];
+    unsafe {
+        nn::crypto::GenerateSha256Hash(hash.as_mut_ptr() as _, 0x20, data.as_ptr() as _, data.len() as u64);
+    }
+    hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Checks an archive against a manifest made by `sha256sum`, which starts with the hash of the file
+pub fn verify_checksum(data: &[u8], manifest: &str) -> Result<(), UpdateError> {
+    let expected = manifest.split_whitespace().next().ok_or(UpdateError::MissingChecksum)?.to_lowercase();
+    let found = sha256(data);
+
+    if expected == found {
+        Ok(())
+    } else {
+        Err(UpdateError::ChecksumMismatch { expected, found })
+    }
+}
+
+/// The installation on the SD card, which release archives are extracted over
+fn sd_layout() -> UpdateLayout {
+    UpdateLayout {
+        root: PathBuf::from("sd:/"),
+        updates: PathBuf::from(UPDATES_PATH),
+    }
+}
+
+/// Installs a release archive over the current installation. Every file it replaces is backed up first, so that the
+/// installation can be restored if the update does not manage to boot. Archives without a checksum manifest are refused,
+/// unless unverified updates were allowed in the configuration.
+pub fn install_update(data: &[u8], checksum: Option<&str>) -> Result<(), UpdateError> {
+    match checksum {
+        Some(checksum) => {
+            verify_checksum(data, checksum)?;
+            info!("The update archive matches its checksum.");
+        },
+        None if crate::config::allow_unverified_updates() => {
+            warn!("The update archive has no checksum, it is installed without being verified as the configuration allows it.");
+        },
+        None => return Err(UpdateError::MissingChecksum),
+    }
+
+    rollback::install_archive(&sd_layout(), data, env!("CARGO_PKG_VERSION"))?;
+    info!("Installed the update, the previous installation will be restored if the next boot fails.");
+
+    Ok(())
+}
+
+/// Installs the release archive dropped in the updates folder, if there is one. Archives which fail to install are
+/// renamed so that they are not tried again on every boot.
+pub fn install_local_update() -> bool {
+    let data = match std::fs::read(LOCAL_RELEASE_PATH) {
+        Ok(data) => data,
+        Err(_) => return false,
+    };
+
+    info!("Found a local update archive at '{}'.", LOCAL_RELEASE_PATH);
+
+    // Local archives did not come from a trusted source, so they have to be verified
+    let result = std::fs::read_to_string(LOCAL_CHECKSUM_PATH)
+        .map_err(|_| UpdateError::MissingChecksum)
+        .and_then(|checksum| install_update(&data, Some(&checksum)));
+
+    match result {
+        Ok(()) => {
+            let _ = std::fs::remove_file(LOCAL_RELEASE_PATH);
+            let _ = std::fs::remove_file(LOCAL_CHECKSUM_PATH);
+            true
+        },
+        Err(e) => {
+            error!("Failed to install the local update archive. Reason: {}", e);
+            let _ = std::fs::rename(LOCAL_RELEASE_PATH, format!("{}.rejected", LOCAL_RELEASE_PATH));
+            crate::dialog_error(format!(
+                "ARCropolis could not install the update archive found in {}.<br>Reason: {}",
+                UPDATES_PATH, e
+            ));
+            false
+        },
+    }
+}
+
+/// Called before anything else on boot. If the previous boot was the first one of an update and it never confirmed
+/// that it booted, the update is considered broken and the previous installation is restored.
+pub fn check_previous_update() -> io::Result<PreviousBoot> {
+    rollback::check_previous_boot(&sd_layout())
+}
+
+/// Logs what `check_previous_update` did once the logger is ready, and returns whether the game has to be relaunched
+pub fn report_previous_update(previous: io::Result<PreviousBoot>) -> bool {
+    match previous {
+        Ok(PreviousBoot::RolledBack(version)) => {
+            error!(
+                "The update installed over version {} did not finish booting, the previous installation was restored.",
+                version
+            );
+            true
+        },
+        Ok(PreviousBoot::FirstBootOfUpdate) => {
+            info!("Booting an update for the first time, it will be rolled back if the boot does not finish.");
+            false
+        },
+        Ok(PreviousBoot::Confirmed) => false,
+        Err(e) => {
+            error!("Failed to check whether the previous update booted. Reason: {:?}", e);
+            false
+        },
+    }
+}
+
+/// Called once `initial_loading` has set up the mod filesystem, which means the update works and its backup is not needed anymore
+pub fn confirm_update() {
+    if rollback::confirm_boot(&sd_layout()) {
+        info!("The update booted successfully.");
+    }
+}
@@ -0,0 +1,176 @@
+use std::{
+    fs::File,
+    io::{self, Cursor},
+    path::PathBuf,
+};
+
+use walkdir::WalkDir;
+use zip::ZipArchive;
+
+/// Where an update is installed to, and where what it replaced is kept until it boots. The plugin works on the SD card,
+/// and the update tester on a local copy of it.
+pub struct UpdateLayout {
+    /// What the paths of the release archive are relative to
+    pub root: PathBuf,
+    /// Where the backup and the markers of the update are kept
+    pub updates: PathBuf,
+}
+
+impl UpdateLayout {
+    /// Every file the update overwrote, at its path relative to the root
+    fn backup_path(&self) -> PathBuf {
+        self.updates.join("backup")
+    }
+
+    /// The files the update added, one per line, which have to be removed when it is rolled back
+    fn created_path(&self) -> PathBuf {
+        self.updates.join("backup.created")
+    }
+
+    /// Written when an update is installed, with the version it replaced, and turned into the booting marker on the next boot
+    fn pending_path(&self) -> PathBuf {
+        self.updates.join("pending")
+    }
+
+    /// Present while the game boots with a freshly installed update, and removed once the boot is confirmed
+    fn booting_path(&self) -> PathBuf {
+        self.updates.join("booting")
+    }
+}
+
+fn to_io_error(e: zip::result::ZipError) -> io::Error {
+    match e {
+        zip::result::ZipError::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e),
+    }
+}
+
+/// Extracts a release archive over the installation. Every file it overwrites is backed up first, and the files it adds
+/// are listed, so that the installation can be put back the way it was if the update does not manage to boot.
+pub fn install_archive(layout: &UpdateLayout, data: &[u8], previous_version: &str) -> io::Result<()> {
+    // Parse the archive before touching anything, so that a broken archive leaves the installation as it was
+    let mut zip = ZipArchive::new(Cursor::new(data)).map_err(to_io_error)?;
+
+    let backup = layout.backup_path();
+    let _ = std::fs::remove_dir_all(&backup);
+    std::fs::create_dir_all(&backup)?;
+
+    let mut created = Vec::new();
+
+    for idx in 0..zip.len() {
+        let file = zip.by_index(idx).map_err(to_io_error)?;
+
+        if file.is_dir() {
+            continue;
+        }
+
+        let local = match file.enclosed_name() {
+            Some(local) => local.to_path_buf(),
+            None => continue,
+        };
+
+        let installed = layout.root.join(&local);
+
+        if installed.is_file() {
+            let backed_up = backup.join(&local);
+
+            if let Some(parent) = backed_up.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+
+            std::fs::copy(&installed, &backed_up)?;
+        } else {
+            created.push(local.to_string_lossy().into_owned());
+        }
+    }
+
+    std::fs::write(layout.created_path(), created.join("\n"))?;
+
+    if let Err(e) = zip.extract(&layout.root) {
+        restore_backup(layout)?;
+        return Err(to_io_error(e));
+    }
+
+    std::fs::write(layout.pending_path(), previous_version)
+}
+
+/// Puts back every file the last update overwrote, and removes the ones it added
+pub fn restore_backup(layout: &UpdateLayout) -> io::Result<()> {
+    let backup = layout.backup_path();
+
+    for entry in WalkDir::new(&backup).into_iter().filter_map(Result::ok) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let local = match entry.path().strip_prefix(&backup) {
+            Ok(local) => local,
+            Err(_) => continue,
+        };
+
+        let installed = layout.root.join(local);
+
+        if let Some(parent) = installed.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        io::copy(&mut File::open(entry.path())?, &mut File::create(installed)?)?;
+    }
+
+    if let Ok(created) = std::fs::read_to_string(layout.created_path()) {
+        for local in created.lines().filter(|local| !local.is_empty()) {
+            let _ = std::fs::remove_file(layout.root.join(local));
+        }
+    }
+
+    Ok(())
+}
+
+fn remove_backup(layout: &UpdateLayout) {
+    let _ = std::fs::remove_dir_all(layout.backup_path());
+    let _ = std::fs::remove_file(layout.created_path());
+}
+
+/// What the check of the previous boot found
+#[derive(Debug, PartialEq)]
+pub enum PreviousBoot {
+    /// Nothing was installed since the previous boot, or its update was confirmed
+    Confirmed,
+    /// An update was installed, and this is its first boot
+    FirstBootOfUpdate,
+    /// The update installed over the contained version never confirmed its boot, and was rolled back
+    RolledBack(String),
+}
+
+/// Checks whether the previous boot was the first one of an update which never confirmed its boot, and rolls it back if so
+pub fn check_previous_boot(layout: &UpdateLayout) -> io::Result<PreviousBoot> {
+    let booting = layout.booting_path();
+
+    if booting.exists() {
+        let version = std::fs::read_to_string(&booting).unwrap_or_default();
+        restore_backup(layout)?;
+        remove_backup(layout);
+        std::fs::remove_file(&booting)?;
+        return Ok(PreviousBoot::RolledBack(version));
+    }
+
+    let pending = layout.pending_path();
+
+    if pending.exists() {
+        std::fs::rename(&pending, &booting)?;
+        return Ok(PreviousBoot::FirstBootOfUpdate);
+    }
+
+    Ok(PreviousBoot::Confirmed)
+}
+
+/// Records that the update booted, which means its backup is not needed anymore. Returns whether there was an update to confirm.
+pub fn confirm_boot(layout: &UpdateLayout) -> bool {
+    if std::fs::remove_file(layout.booting_path()).is_ok() {
+        remove_backup(layout);
+        true
+    } else {
+        false
+    }
+}
+